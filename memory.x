@@ -1,7 +1,8 @@
 # memory.x - 内存布局配置
 MEMORY
 {
-    FLASH    : ORIGIN = 0x08000000, LENGTH = 2048K /* 主闪存，2MB */
+    /* 主闪存 2MB，最后两个 128K 扇区（0x081C0000 起）留给 settings，程序不能放进去 */
+    FLASH    : ORIGIN = 0x08000000, LENGTH = 1792K
     RAM      : ORIGIN = 0x24000000, LENGTH = 512K  /* SRAM1/2/3 (AXI/SRAM) */
     RAM_D3   : ORIGIN = 0x38000000, LENGTH = 128K  /* SRAM4 (D3域) */
 }
@@ -3,10 +3,12 @@
 
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_stm32::gpio::{Input, Level, Output, Pull, Speed};
-use embassy_stm32::Config;
+use embassy_proj1::button::{Button, ButtonEvent, Timing};
+use embassy_proj1::{board, settings};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Level, Output, Pull, Speed};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
-use embassy_time::{Duration, Timer};
+use embassy_time::Duration;
 use {defmt_rtt as _, panic_probe as _};
 
 // 定义LED模式
@@ -28,6 +30,15 @@ impl LedMode {
         }
     }
     
+    fn from_settings(mode: settings::LedMode) -> Self {
+        match mode {
+            settings::LedMode::Slow => LedMode::Slow,
+            settings::LedMode::Medium => LedMode::Medium,
+            settings::LedMode::Fast => LedMode::Fast,
+            settings::LedMode::Off => LedMode::Off,
+        }
+    }
+
     fn duration(&self) -> Duration {
         match self {
             LedMode::Slow => Duration::from_millis(1000),
@@ -48,57 +59,43 @@ async fn main(spawner: Spawner) {
     let p = embassy_stm32::init(Default::default());
     info!("LED Mode Switch Demo Started!");
 
+    // 上电的模式用 console 里 `config set led.mode` 存下的设置
+    let (saved, loaded) = board::settings_store(p.FLASH).load_settings();
+    let start = LedMode::from_settings(saved.led_mode);
+    *MODE.lock().await = start;
+    info!("Settings: {}, mode: {:?}", loaded.as_str(), saved.led_mode);
+
     // 控制PB0引脚的LED（主LED）
     let led = Output::new(p.PB0, Level::High, Speed::Low);
     
-    // 配置PC13为按键输入（上拉模式）
-    let button = Input::new(p.PC13, Pull::Down);
+    // PC13 是板上的用户按键 B1，按下是高电平，用 EXTI13 等边沿
+    let button = Button::new(ExtiInput::new(p.PC13, p.EXTI13, Pull::Down), true, Timing::DEFAULT);
     
-    // 生成按键检测任务和LED控制任务
-    spawner.spawn(button_task(button)).unwrap();
+    // 生成按键任务和LED控制任务
+    spawner.spawn(button_task(button, start)).unwrap();
     spawner.spawn(led_task(led)).unwrap();
 }
 
-// 按键检测任务
+// 按键任务：单击切换到下一个模式，长按关灯，双击回到上电时的模式
 #[embassy_executor::task]
-async fn button_task(button: Input<'static>) {
-    let debounce_time = Duration::from_millis(50);
-    let mut last_state = button.is_high();
-    let mut last_press = embassy_time::Instant::now();
-    let mut long_press_timer = embassy_time::Instant::now();
-    
+async fn button_task(mut button: Button<'static>, start: LedMode) {
     loop {
-        let current_state = button.is_high();
-        
-        // 检测下降沿（按键按下）
-        if last_state && !current_state {
-            long_press_timer = embassy_time::Instant::now();
-        }
-        
-        // 检测上升沿（按键释放）
-        if !last_state && current_state {
-            let now = embassy_time::Instant::now();
-            
-            // 防抖处理
-            if now.duration_since(last_press) > debounce_time {
-                // 短按处理
-                if now.duration_since(long_press_timer) < Duration::from_millis(1000) {
-                    info!("Button short pressed");
-                    
-                    // 切换模式
-                    let mut mode = MODE.lock().await;
-                    *mode = mode.next();
-                    info!("Mode changed to: {:?}", *mode);
-                    
-                    // 发送信号通知LED任务
-                    SIGNAL.signal(());
-                }
-            }
-            last_press = now;
+        let event = button.next().await;
+        info!("Button: {:?}", event);
+
+        let mut mode = MODE.lock().await;
+        let new = match event {
+            ButtonEvent::Click => mode.next(),
+            ButtonEvent::LongPress => LedMode::Off,
+            ButtonEvent::DoubleClick => start,
+            _ => continue,
+        };
+        if new != *mode {
+            *mode = new;
+            info!("Mode changed to: {:?}", new);
+            // 发送信号通知LED任务
+            SIGNAL.signal(());
         }
-        
-        last_state = current_state;
-        Timer::after(Duration::from_millis(10)).await; // 10ms扫描一次
     }
 }
 
@@ -0,0 +1,17 @@
+#![no_std]
+#![no_main]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_proj1::board;
+use embassy_stm32::usart::Config;
+use {defmt_rtt as _, panic_probe as _};
+
+// 上位机发来的 COBS 包原样发回去
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let board = board::init();
+    let usart = board.usart3.into_async(Config::default());
+
+    unwrap!(spawner.spawn(board::packet_echo_task(usart)));
+}
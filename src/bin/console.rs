@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_proj1::board::{GpioPins, SettingsStore};
+use embassy_proj1::usart::framer::Idle;
+use embassy_proj1::usart::line::{self, LineConfig};
+use embassy_proj1::usart::FRAME_SIZE;
+use embassy_proj1::{board, console, gpio, settings};
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+static FRAMER: StaticCell<Idle<FRAME_SIZE>> = StaticCell::new();
+static PINS: StaticCell<GpioPins> = StaticCell::new();
+static STORE: StaticCell<SettingsStore> = StaticCell::new();
+
+// 同一套命令同时开在调试串口、USB 虚拟串口和 TCP 上，各个终端互不影响
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let board = board::init();
+    gpio::install(PINS.init(board.pins));
+
+    let mut store = board::settings_store(board.flash);
+    let (saved, loaded) = store.load_settings();
+    info!("settings: {}", loaded.as_str());
+    let line = LineConfig {
+        baudrate: saved.baudrate,
+        ..LineConfig::DEFAULT
+    };
+    settings::install(saved, STORE.init(store)).await;
+
+    let (usart, line) = board.usart3.into_async_line(line);
+    line::set_current(line);
+    board::spawn_shell(&spawner, usart, FRAMER.init(Idle::new()));
+    console::spawn_usb_console(&spawner, board.usb);
+    console::spawn_tcp_console(&spawner, board.eth);
+}
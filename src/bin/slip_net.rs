@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4};
+use embassy_proj1::board;
+use embassy_proj1::link::slip_net;
+use embassy_proj1::usart::RingRx;
+use embassy_stm32::mode::Async;
+use embassy_stm32::uid;
+use embassy_stm32::usart::{Config, Uart};
+use embassy_time::Duration;
+use embedded_io_async::Write;
+use heapless::Vec;
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+// 通过 SLIP 跑 IP，板子地址 192.168.7.2，TCP 端口 1234 回显。
+// 上位机：
+//   slattach -p slip -s 115200 /dev/ttyACM0 &
+//   ip addr add 192.168.7.1 peer 192.168.7.2 dev sl0 && ip link set sl0 up
+//   nc 192.168.7.2 1234
+
+#[embassy_executor::task]
+async fn slip_task(runner: slip_net::Runner<'static>, uart: Uart<'static, Async>) -> ! {
+    let (tx, rx) = uart.split();
+    runner.run(RingRx::new(rx, board::rx_ring()), tx).await
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, slip_net::Device<'static>>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let board = board::init();
+    let usart = board.usart3.into_async(Config::default());
+
+    static STATE: StaticCell<slip_net::State> = StaticCell::new();
+    let (runner, device) = slip_net::new(STATE.init(slip_net::State::new()));
+    unwrap!(spawner.spawn(slip_task(runner, usart)));
+
+    let config = embassy_net::Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 7, 2), 24),
+        gateway: Some(Ipv4Address::new(192, 168, 7, 1)),
+        dns_servers: Vec::new(),
+    });
+
+    // 没开 RNG，用芯片唯一 ID 当种子，至少每块板子不一样
+    let id = uid::uid();
+    let seed = u64::from_le_bytes(unwrap!(id[..8].try_into()));
+
+    static RESOURCES: StaticCell<StackResources<2>> = StaticCell::new();
+    let (stack, runner) =
+        embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+    unwrap!(spawner.spawn(net_task(runner)));
+
+    tcp_echo(stack).await
+}
+
+async fn tcp_echo(stack: Stack<'static>) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut buf = [0; 512];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(30)));
+
+        info!("Listening on TCP:1234...");
+        if let Err(e) = socket.accept(1234).await {
+            warn!("accept error: {:?}", e);
+            continue;
+        }
+        info!("Connection from {:?}", socket.remote_endpoint());
+
+        loop {
+            let n = match socket.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("read error: {:?}", e);
+                    break;
+                }
+            };
+            if let Err(e) = socket.write_all(&buf[..n]).await {
+                warn!("write error: {:?}", e);
+                break;
+            }
+        }
+        socket.close();
+    }
+}
@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+use alloc::string::String;
+
+use cortex_m_rt::entry;
+use defmt::*;
+use embassy_executor::Executor;
+use embassy_proj1::board::{self, BlockingReader};
+use embassy_proj1::usart::framer::Nul;
+use embassy_stm32::usart::Config;
+use embassy_time::{Duration, Timer};
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+// 导入内存分配器
+use linked_list_allocator::LockedHeap;
+// 定义全局内存分配器
+#[global_allocator]
+static ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+// 定义堆内存区域
+const HEAP_SIZE: usize = 1024 * 8; // 例如，分配 8KB 作为堆
+static mut HEAP_MEM: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+
+#[embassy_executor::task]
+async fn main_task() {
+    let board = board::init();
+    let mut usart = board.usart3.into_blocking(Config::default());
+
+    info!("UART echo server started");
+
+    let mut framer = Nul::<64>::new();
+    // 对端发到一半停下来（没有 `\0`）时，超时也会结束一条消息
+    let mut reader = BlockingReader::new(board::BLOCKING_TIMEOUTS);
+    loop {
+        let (frame, ended) = reader.echo(&mut usart, &mut framer);
+        let echoed_str: String = frame.iter().map(|b| *b as char).collect();
+        info!("Echoed string: {:?} ({:?})", echoed_str.as_str(), ended);
+    }
+}
+
+#[embassy_executor::task]
+async fn periodic_task() {
+    loop {
+        info!("Periodic task running...");
+        Timer::after(Duration::from_micros(20)).await;
+        info!("Periodic task finished.");
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+#[entry]
+fn main() -> ! {
+    info!("Starting UART echo example with periodic messages");
+    let executor = EXECUTOR.init(Executor::new());
+    // 因为操作原始指针和静态可变变量，需要 unsafe
+    unsafe {
+        ALLOCATOR
+            .lock()
+            .init(core::ptr::addr_of_mut!(HEAP_MEM).cast(), HEAP_SIZE);
+    }
+    executor.run(|spawner| {
+        unwrap!(spawner.spawn(main_task()));
+        unwrap!(spawner.spawn(periodic_task()));
+    });
+}
@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_proj1::board;
+use embassy_proj1::usart::framer::Nul;
+use embassy_proj1::pipeline::Policy;
+use embassy_proj1::usart::{ByteRx, FramePipeline, FramePool, FRAME_SIZE};
+use embassy_stm32::usart::Config;
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+// Define a static pipeline for communication between the reader and writer tasks.
+// Each message is a buffer borrowed from FRAMES; it goes back to the pool once the writer drops it.
+static FRAMES: FramePool = FramePool::new();
+// The writer only echoes, so when it falls behind the oldest frames are the least useful.
+static ECHO_PIPELINE: FramePipeline = FramePipeline::new(Policy::DropOldest);
+// 以 \0 结尾，缓冲区满了也会先发出去
+static FRAMER: StaticCell<Nul<FRAME_SIZE>> = StaticCell::new();
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let board = board::init();
+    info!("Hello World!");
+
+    // Split the Uart instance into independent Transmit (Tx) and Receive (Rx) parts.
+    let (tx, rx) = board.usart3.into_async(Config::default()).split();
+
+    unwrap!(spawner.spawn(board::line_reader_task(
+        ByteRx(rx),
+        FRAMER.init(Nul::new()),
+        &FRAMES,
+        &ECHO_PIPELINE,
+    )));
+    unwrap!(spawner.spawn(board::line_writer_task(tx, &ECHO_PIPELINE)));
+}
@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+use cortex_m_rt::entry;
+use defmt::*;
+use embassy_executor::Executor;
+use embassy_proj1::board::{self, BlockingReader};
+use embassy_proj1::usart::framer::Nul;
+use embassy_stm32::usart::Config;
+use embassy_time::{Duration, Timer};
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::task]
+async fn main_task() {
+    let board = board::init();
+    let mut usart = board.usart3.into_blocking(Config::default());
+
+    info!("UART echo server started");
+
+    let mut framer = Nul::<64>::new();
+    // 对端发到一半停下来（没有 `\0`）时，超时也会结束一条消息
+    let mut reader = BlockingReader::new(board::BLOCKING_TIMEOUTS);
+    loop {
+        // 没有数据时每 1 ms 看一次，中间让出 CPU，periodic_task 照样能跑
+        let (frame, ended) = reader.poll_echo(&mut usart, &mut framer, Duration::from_millis(1)).await;
+        info!("Echoed string: {:?} ({:?})", frame, ended);
+    }
+}
+
+#[embassy_executor::task]
+async fn periodic_task() {
+    loop {
+        info!("Periodic task running...");
+        Timer::after(Duration::from_secs(2)).await;
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+#[entry]
+fn main() -> ! {
+    info!("Starting UART echo example with periodic messages");
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        unwrap!(spawner.spawn(main_task()));
+        unwrap!(spawner.spawn(periodic_task()));
+    });
+}
@@ -0,0 +1,837 @@
+//! Nucleo-H743ZI 板级代码：外设初始化、中断绑定和各个 binary 共用的 embassy 任务。
+//!
+//! USART3 接在 ST-LINK 的虚拟串口上，PD9 是 RX，PD8 是 TX。USB OTG FS 接在 CN13 上，
+//! 以太网通过 RMII 接板载的 LAN8742A。
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_stm32::flash::{self, Flash};
+use embassy_stm32::gpio::{Flex, Level, Pin, Speed};
+use embassy_stm32::mode::{Async, Blocking};
+use embassy_stm32::pac::usart::vals::{Abrmod, Over8};
+use embassy_stm32::peripherals::{
+    DMA1_CH1, DMA1_CH2, ETH, FLASH, PA1, PA11, PA12, PA2, PA7, PB13, PC1, PC4, PC5, PD8, PD9, PG11, PG13,
+    USART3, USB_OTG_FS,
+};
+use embassy_stm32::rcc::mux;
+use embassy_stm32::usart::{Config, Uart, UartTx};
+use embassy_stm32::{bind_interrupts, eth, pac, peripherals, usart, usb};
+use embassy_embedded_hal::SetConfig;
+use embassy_time::{with_deadline, Duration, Instant, Timer};
+use embedded_storage::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+use static_cell::StaticCell;
+
+use crate::gpio::{PinDriver, PinId, PinMode, PinPool, Pull};
+use crate::link::reliable::{self, Link, LinkError};
+use crate::link::{slip, PacketError, PacketRx, PacketTx};
+use crate::pipeline::{Policy, Sent};
+use crate::settings::LogStore;
+use crate::shell;
+use crate::shell::cancel::{Cancel, CTRL_C};
+use crate::usart::line::{self, LineConfig, LineRequest};
+use crate::usart::stats::{self, ErrorKind, ErrorMonitor, RecoveryPolicy};
+use crate::usart::timeout::{self, Ended, MessageTimer};
+use crate::usart::{
+    ByteRx, FramePipeline, FramePool, FramedReader, Framer, ReadError, RingRx, Timeouts,
+    FRAME_SIZE,
+};
+
+bind_interrupts!(pub struct Irqs {
+    USART3 => usart::InterruptHandler<peripherals::USART3>;
+    OTG_FS => usb::InterruptHandler<peripherals::USB_OTG_FS>;
+    ETH => eth::InterruptHandler;
+});
+
+/// USART3 以及它用到的引脚和 DMA 通道
+pub struct Usart3 {
+    pub usart: USART3,
+    pub rx: PD9,
+    pub tx: PD8,
+    pub tx_dma: DMA1_CH1,
+    pub rx_dma: DMA1_CH2,
+}
+
+impl Usart3 {
+    /// DMA 收发的异步串口
+    pub fn into_async(self, config: Config) -> Uart<'static, Async> {
+        unwrap!(Uart::new(
+            self.usart,
+            self.rx,
+            self.tx,
+            Irqs,
+            self.tx_dma,
+            self.rx_dma,
+            config,
+        ))
+    }
+
+    /// 按 `line` 打开 DMA 收发的异步串口，返回真正用上的参数。
+    ///
+    /// `line` 通常是 flash 里存着的设置，当前时钟下分不出这个波特率时不能就这么卡在启动阶段，
+    /// 退回 [`LineConfig::DEFAULT`]，至少还能连上 shell 改回来
+    pub fn into_async_line(self, line: LineConfig) -> (Uart<'static, Async>, LineConfig) {
+        let mut uart = self.into_async(LineConfig::DEFAULT.to_config());
+        if line == LineConfig::DEFAULT {
+            return (uart, line);
+        }
+        match uart.set_config(&line.to_config()) {
+            Ok(()) => (uart, line),
+            Err(e) => {
+                warn!("Unsupported line config {}: {:?}, using {}", line, e, LineConfig::DEFAULT);
+                unwrap!(uart.set_config(&LineConfig::DEFAULT.to_config()));
+                (uart, LineConfig::DEFAULT)
+            }
+        }
+    }
+
+    /// 阻塞串口，用不到 DMA 通道
+    pub fn into_blocking(self, config: Config) -> Uart<'static, Blocking> {
+        unwrap!(Uart::new_blocking(self.usart, self.rx, self.tx, config))
+    }
+}
+
+/// USB OTG FS，接在板子下方的 USB 口（CN13）上
+pub struct UsbFs {
+    pub otg: USB_OTG_FS,
+    pub dp: PA12,
+    pub dm: PA11,
+}
+
+/// 以太网 MAC 和接 LAN8742A PHY 的 RMII 引脚
+pub struct EthPins {
+    pub eth: ETH,
+    pub ref_clk: PA1,
+    pub mdio: PA2,
+    pub mdc: PC1,
+    pub crs: PA7,
+    pub rx_d0: PC4,
+    pub rx_d1: PC5,
+    pub tx_d0: PG13,
+    pub tx_d1: PB13,
+    pub tx_en: PG11,
+}
+
+/// shell 可以借用的引脚，见 [`crate::gpio`]
+pub type GpioPins = PinPool<Flex<'static>>;
+
+impl PinDriver for Flex<'static> {
+    fn set_mode(&mut self, mode: PinMode) {
+        match mode {
+            PinMode::Input(pull) => self.set_as_input(match pull {
+                Pull::None => embassy_stm32::gpio::Pull::None,
+                Pull::Up => embassy_stm32::gpio::Pull::Up,
+                Pull::Down => embassy_stm32::gpio::Pull::Down,
+            }),
+            PinMode::Output => self.set_as_output(Speed::Low),
+            PinMode::OpenDrain => self.set_as_input_output(Speed::Low),
+            PinMode::Analog => self.set_as_analog(),
+        }
+    }
+
+    fn set_output(&mut self, high: bool) {
+        self.set_level(Level::from(high));
+    }
+
+    fn output(&self) -> bool {
+        self.is_set_high()
+    }
+
+    fn level(&self) -> bool {
+        self.is_high()
+    }
+}
+
+fn pin_id(pin: &impl Pin) -> PinId {
+    PinId::new(pin.port(), pin.pin())
+}
+
+/// 各个 binary 用到的外设
+pub struct Board {
+    pub usart3: Usart3,
+    pub usb: UsbFs,
+    pub eth: EthPins,
+    /// 上面这些外设没用到的引脚
+    pub pins: GpioPins,
+    /// 片上 flash，最后两个扇区存设置，见 [`settings_store`]
+    pub flash: FLASH,
+}
+
+/// 设置存在 bank 2 的最后两个 128 KB 扇区（0x081C0000 起），`memory.x` 里的 FLASH 不包括它们
+pub const SETTINGS_OFFSET: u32 = 0x1C_0000;
+/// 设置轮着用的扇区数
+pub const SETTINGS_SECTORS: u32 = 2;
+
+/// 存设置用的 flash。擦写时 CPU 会停在那里等，擦一个扇区要一两秒
+pub type SettingsStore = LogStore<EccFlash>;
+
+/// 片上 flash，读到双位 ECC 错误时返回 `Err`，不进 BusFault。
+///
+/// 写一个 256 位 flash 字写到一半掉电，这个字读出来可能是双位 ECC 错误，默认会触发 BusFault。
+/// 读的时候置上 FAULTMASK 并打开 `SCB.CCR.BFHFNMIGN`，读数据时的总线错误就被忽略了，
+/// 读完再看两个 bank 的 `DBECCERR`。置上 FAULTMASK 期间所有中断都被屏蔽，读设置时才用，没关系。
+pub struct EccFlash(Flash<'static, flash::Blocking>);
+
+impl EccFlash {
+    pub fn new(flash: FLASH) -> Self {
+        Self(Flash::new_blocking(flash))
+    }
+}
+
+impl ErrorType for EccFlash {
+    type Error = flash::Error;
+}
+
+impl ReadNorFlash for EccFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), flash::Error> {
+        const BFHFNMIGN: u32 = 1 << 8;
+        // CFSR 里 BusFault 的 PRECISERR 和 BFARVALID，写 1 清掉
+        const BUS_FAULT: u32 = (1 << 9) | (1 << 15);
+        let read = unsafe {
+            let scb = &*cortex_m::peripheral::SCB::PTR;
+            core::arch::asm!("cpsid f");
+            scb.ccr.modify(|r| r | BFHFNMIGN);
+            cortex_m::asm::dsb();
+            cortex_m::asm::isb();
+            let read = self.0.blocking_read(offset, bytes);
+            cortex_m::asm::dsb();
+            scb.ccr.modify(|r| r & !BFHFNMIGN);
+            scb.cfsr.write(BUS_FAULT);
+            cortex_m::asm::isb();
+            core::arch::asm!("cpsie f");
+            read
+        };
+        let mut ecc = false;
+        for n in 0..2 {
+            let bank = pac::FLASH.bank(n);
+            if bank.sr().read().dbeccerr() {
+                // 不清掉的话之后的擦写都会报错
+                bank.ccr().write(|w| w.set_clr_dbeccerr(true));
+                ecc = true;
+            }
+        }
+        match ecc {
+            true => Err(flash::Error::Prog),
+            false => read,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+impl NorFlash for EccFlash {
+    const WRITE_SIZE: usize = flash::WRITE_SIZE;
+    const ERASE_SIZE: usize = flash::MAX_ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), flash::Error> {
+        self.0.blocking_erase(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), flash::Error> {
+        self.0.blocking_write(offset, bytes)
+    }
+}
+
+/// 挂载设置扇区，上次写到一半掉电的话这里会整理一次
+pub fn settings_store(flash: FLASH) -> SettingsStore {
+    let flash = EccFlash::new(flash);
+    let (store, mounted) = unwrap!(LogStore::mount(flash, SETTINGS_OFFSET, SETTINGS_SECTORS));
+    info!("Settings storage: {}", mounted.as_str());
+    store
+}
+
+/// 循环 DMA 接收缓冲区的大小。921600 波特率下 8 KB 能扛住大约 90 ms 的处理延迟
+pub const RX_RING_SIZE: usize = 8 * 1024;
+
+static RX_RING: StaticCell<[u8; RX_RING_SIZE]> = StaticCell::new();
+
+/// 给 [`RingRx`] 用的 DMA 缓冲区，只能取一次
+pub fn rx_ring() -> &'static mut [u8] {
+    RX_RING.init([0; RX_RING_SIZE])
+}
+
+pub fn init() -> Board {
+    let mut config = embassy_stm32::Config::default();
+    // USB 要 48 MHz 时钟，HSI48 默认是开着的
+    config.rcc.mux.usbsel = mux::Usbsel::HSI48;
+    let p = embassy_stm32::init(config);
+
+    let mut pins = GpioPins::new();
+    macro_rules! reserve {
+        ($owner:literal: $($pin:ident)*) => {
+            $(pins.reserve(pin_id(&p.$pin), $owner);)*
+        };
+    }
+    macro_rules! free {
+        ($($pin:ident)*) => {
+            $(pins.add(pin_id(&p.$pin), Flex::new(p.$pin));)*
+        };
+    }
+    reserve!("usart3": PD8 PD9);
+    // PA9 是 VBUS 检测
+    reserve!("usb": PA9 PA11 PA12);
+    reserve!("eth": PA1 PA2 PA7 PB13 PC1 PC4 PC5 PG11 PG13);
+    // 调试器用的 SWD 和 SWO，改了就连不上了
+    reserve!("swd": PA13 PA14 PB3);
+    // 其余的引脚都交给 shell，LD1（PB0）、LD2（PE1）、LD3（PB14）和 B1（PC13）也在里面
+    free!(
+        PA0 PA3 PA4 PA5 PA6 PA8 PA10 PA15
+        PB0 PB1 PB2 PB4 PB5 PB6 PB7 PB8 PB9 PB10 PB11 PB12 PB14 PB15
+        PC0 PC2 PC3 PC6 PC7 PC8 PC9 PC10 PC11 PC12 PC13 PC14 PC15
+        PD0 PD1 PD2 PD3 PD4 PD5 PD6 PD7 PD10 PD11 PD12 PD13 PD14 PD15
+        PE0 PE1 PE2 PE3 PE4 PE5 PE6 PE7 PE8 PE9 PE10 PE11 PE12 PE13 PE14 PE15
+        PF0 PF1 PF2 PF3 PF4 PF5 PF6 PF7 PF8 PF9 PF10 PF11 PF12 PF13 PF14 PF15
+        PG0 PG1 PG2 PG3 PG4 PG5 PG6 PG7 PG8 PG9 PG10 PG12 PG14 PG15
+        PH0 PH1
+    );
+
+    Board {
+        usart3: Usart3 {
+            usart: p.USART3,
+            rx: p.PD9,
+            tx: p.PD8,
+            tx_dma: p.DMA1_CH1,
+            rx_dma: p.DMA1_CH2,
+        },
+        usb: UsbFs {
+            otg: p.USB_OTG_FS,
+            dp: p.PA12,
+            dm: p.PA11,
+        },
+        eth: EthPins {
+            eth: p.ETH,
+            ref_clk: p.PA1,
+            mdio: p.PA2,
+            mdc: p.PC1,
+            crs: p.PA7,
+            rx_d0: p.PC4,
+            rx_d1: p.PC5,
+            tx_d0: p.PG13,
+            tx_d1: p.PB13,
+            tx_en: p.PG11,
+        },
+        pins,
+        flash: p.FLASH,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// 接收错误处理
+// ---------------------------------------------------------------------------
+
+/// 记一次接收错误。短时间内错误太多就调用 `reinit` 按当前参数重新初始化串口
+/// （USART 先关再开会清掉卡住的状态，循环 DMA 也会重新启动），返回 `true` 表示重新初始化过。
+fn rx_error(
+    e: ReadError<usart::Error>,
+    monitor: &mut ErrorMonitor,
+    reinit: impl FnOnce(&Config) -> Result<(), usart::ConfigError>,
+) -> bool {
+    let e = match e {
+        ReadError::Read(e) => e,
+        // 串口的 read 不会返回 0
+        ReadError::Eof => return false,
+    };
+    let kind = ErrorKind::from(e);
+    stats::CONSOLE.error(kind);
+    warn!("UART RX error: {:?} ({} so far)", kind, stats::CONSOLE.get().errors());
+
+    if !monitor.record(Instant::now()) {
+        return false;
+    }
+    warn!("Too many UART errors, re-initialising USART3");
+    match reinit(&line::current().to_config()) {
+        Ok(()) => {
+            stats::CONSOLE.recovered();
+            true
+        }
+        Err(e) => {
+            error!("Failed to re-initialise USART3: {:?}", e);
+            false
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// 阻塞模式
+// ---------------------------------------------------------------------------
+
+/// 轮询/阻塞模式默认的超时：字节间 20 ms，整条消息 1 s。
+/// 终端里手敲的字符之间可能隔得比这久，这种场合用 [`Timeouts::NONE`]
+pub const BLOCKING_TIMEOUTS: Timeouts =
+    Timeouts::new(Duration::from_millis(20), Duration::from_secs(1));
+
+/// 轮询阻塞串口的接收状态：超时计时和错误统计要跨帧保留
+pub struct BlockingReader {
+    timer: MessageTimer,
+    monitor: ErrorMonitor,
+}
+
+impl BlockingReader {
+    pub fn new(timeouts: Timeouts) -> Self {
+        Self {
+            timer: MessageTimer::new(timeouts),
+            monitor: ErrorMonitor::new(RecoveryPolicy::default()),
+        }
+    }
+
+    /// 阻塞地读到一帧并原样回显，返回这一帧和它是怎么结束的。
+    ///
+    /// 对端发到一半停下来时，超时前收到的部分也算一帧（见 [`Framer::timeout`]）。
+    pub fn echo<'f, F: Framer>(
+        &mut self,
+        uart: &mut Uart<'static, Blocking>,
+        framer: &'f mut F,
+    ) -> (&'f [u8], Ended) {
+        let monitor = &mut self.monitor;
+        let ended = timeout::read_frame(framer, &mut self.timer, || {
+            match embedded_hal::serial::Read::read(uart) {
+                Ok(byte) => Some(byte),
+                Err(nb::Error::WouldBlock) => None,
+                Err(nb::Error::Other(e)) => {
+                    rx_error(ReadError::Read(e), monitor, |config| uart.set_config(config));
+                    None
+                }
+            }
+        });
+        let frame = F::frame(framer);
+        unwrap!(uart.blocking_write(frame));
+        (frame, ended)
+    }
+
+    /// 和 [`echo`](Self::echo) 一样，只是用轮询：没有数据时隔 `period` 再来看，中间让出 CPU
+    pub async fn poll_echo<'f, F: Framer>(
+        &mut self,
+        uart: &mut Uart<'static, Blocking>,
+        framer: &'f mut F,
+        period: Duration,
+    ) -> (&'f [u8], Ended) {
+        let monitor = &mut self.monitor;
+        let ended = timeout::poll_frame(framer, &mut self.timer, period, || {
+            match embedded_hal::serial::Read::read(uart) {
+                Ok(byte) => Some(byte),
+                Err(nb::Error::WouldBlock) => None,
+                Err(nb::Error::Other(e)) => {
+                    rx_error(ReadError::Read(e), monitor, |config| uart.set_config(config));
+                    None
+                }
+            }
+        })
+        .await;
+        let frame = F::frame(framer);
+        unwrap!(uart.blocking_write(frame));
+        (frame, ended)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// 逐字节读取 + 回显（interrupt 例子）
+// ---------------------------------------------------------------------------
+
+// Task responsible for reading bytes, buffering, and sending complete messages
+#[embassy_executor::task]
+pub async fn line_reader_task(
+    rx: ByteRx<'static>,
+    framer: &'static mut dyn Framer,
+    pool: &'static FramePool,
+    pipeline: &'static FramePipeline,
+) {
+    info!("Reader task started.");
+    let mut reader = FramedReader::new(rx, framer);
+    let mut monitor = ErrorMonitor::new(RecoveryPolicy::default());
+
+    loop {
+        match reader.next_frame().await {
+            Ok(frame) => {
+                for piece in frame.chunks(FRAME_SIZE) {
+                    let mut buf = pool.alloc().await;
+                    buf.extend_from_slice(piece);
+                    if pipeline.send(buf).await != Sent::Queued {
+                        warn!("Writer too slow, frame dropped ({:?})", pipeline.stats());
+                    }
+                }
+                stats::CONSOLE.frame(frame.len());
+                info!("Reader sent message with length: {}", frame.len());
+            }
+            Err(e) => {
+                if rx_error(e, &mut monitor, |config| reader.rx_mut().0.set_config(config)) {
+                    reader.framer_mut().reset();
+                }
+                // Prevent tight loop on persistent error
+                embassy_time::Timer::after_millis(100).await;
+            }
+        }
+    }
+}
+
+// Task responsible for receiving complete messages from the channel and writing them out
+#[embassy_executor::task]
+pub async fn line_writer_task(
+    mut tx: UartTx<'static, Async>,
+    pipeline: &'static FramePipeline,
+) {
+    info!("Writer task started.");
+    loop {
+        let frame = pipeline.receive().await;
+        if let Err(e) = tx.write(&frame).await {
+            error!("UART Write error: {:?}", e);
+            embassy_time::Timer::after_millis(100).await;
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// DMA 回显
+// ---------------------------------------------------------------------------
+
+#[embassy_executor::task]
+pub async fn echo_task(uart: Uart<'static, Async>, framer: &'static mut dyn Framer) {
+    info!("UART DMA echo server started");
+
+    let (mut tx, rx) = uart.split();
+    let mut reader = FramedReader::new(RingRx::new(rx, rx_ring()), framer);
+    let mut monitor = ErrorMonitor::new(RecoveryPolicy::default());
+
+    loop {
+        let frame = match reader.next_frame().await {
+            Ok(frame) => frame,
+            Err(e) => {
+                if rx_error(e, &mut monitor, |config| reader.rx_mut().set_config(config)) {
+                    reader.framer_mut().reset();
+                }
+                continue;
+            }
+        };
+        stats::CONSOLE.frame(frame.len());
+        info!("Received {} bytes: {:?}", frame.len(), frame);
+
+        if let Err(e) = shell::echo(&mut tx, frame).await {
+            error!("UART write error: {:?}", e);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// COBS 二进制包回环
+// ---------------------------------------------------------------------------
+
+/// 编码后单帧的最大长度
+pub const PACKET_SIZE: usize = 256;
+
+/// 收到一个包就原样发回去，方便上位机测试打包和解包
+#[embassy_executor::task]
+pub async fn packet_echo_task(uart: Uart<'static, Async>) {
+    info!("COBS packet echo started");
+
+    let (tx, rx) = uart.split();
+    let mut rx = PacketRx::<_, PACKET_SIZE>::new(RingRx::new(rx, rx_ring()));
+    let mut tx = PacketTx::<_, PACKET_SIZE>::new(tx);
+
+    loop {
+        match rx.recv().await {
+            Ok(payload) => {
+                info!("Received packet: {} bytes", payload.len());
+                if let Err(e) = tx.send(payload).await {
+                    error!("Failed to send packet: {:?}", e);
+                }
+            }
+            Err(PacketError::Io(usart::Error::Overrun)) => {
+                warn!("UART RX overrun, data lost ({} so far)", rx.rx().overruns())
+            }
+            Err(e) => warn!("Dropped packet: {:?}", e),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// 可靠链路回环
+// ---------------------------------------------------------------------------
+
+/// 可靠链路的发送窗口
+pub const LINK_WINDOW: usize = 4;
+
+/// 收到的包原样发回去，每 100 个包打印一次统计
+#[embassy_executor::task]
+pub async fn reliable_echo_task(uart: Uart<'static, Async>) {
+    info!("Reliable link echo started");
+
+    let (tx, rx) = uart.split();
+    let mut link = Link::<_, _, PACKET_SIZE, LINK_WINDOW>::new(
+        RingRx::new(rx, rx_ring()),
+        tx,
+        reliable::Config::default(),
+    );
+    let mut buf = [0u8; PACKET_SIZE];
+
+    loop {
+        let len = match link.recv().await {
+            Ok(payload) => {
+                buf[..payload.len()].copy_from_slice(payload);
+                payload.len()
+            }
+            Err(e) => {
+                warn!("Link receive error: {:?}", e);
+                continue;
+            }
+        };
+
+        match link.send(&buf[..len]).await {
+            Ok(()) => {}
+            Err(LinkError::Timeout) => warn!("Peer stopped acknowledging, packets dropped"),
+            Err(e) => error!("Link send error: {:?}", e),
+        }
+
+        let stats = link.stats();
+        if stats.rx_packets % 100 == 0 {
+            info!("Link stats: {:?}", stats);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// SLIP 回环
+// ---------------------------------------------------------------------------
+
+/// 收到一个 SLIP 包就原样发回去
+#[embassy_executor::task]
+pub async fn slip_echo_task(uart: Uart<'static, Async>) {
+    info!("SLIP packet echo started");
+
+    let (mut tx, rx) = uart.split();
+    let mut reader = FramedReader::new(
+        RingRx::new(rx, rx_ring()),
+        slip::Decoder::<PACKET_SIZE>::new(),
+    );
+    let mut last_dropped = 0;
+
+    loop {
+        match reader.next_frame().await {
+            Ok(payload) => {
+                info!("Received packet: {} bytes", payload.len());
+                if let Err(e) = slip::write_frame(&mut tx, payload).await {
+                    error!("Failed to send packet: {:?}", e);
+                }
+            }
+            Err(e) => {
+                warn!("UART RX error: {:?}", e);
+                reader.framer_mut().reset();
+            }
+        }
+
+        let decoder = reader.framer();
+        let dropped = decoder.errors() + decoder.oversized();
+        if dropped != last_dropped {
+            warn!("Dropped packets: {} bad escape, {} oversized", decoder.errors(), decoder.oversized());
+            last_dropped = dropped;
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// DMA shell：接收任务 + 处理任务
+// ---------------------------------------------------------------------------
+
+static FRAMES: FramePool = FramePool::new();
+/// 接收任务到处理任务的流水线。默认 `Block`，binary 可以在 `spawn_shell` 之前换成别的策略
+pub static SHELL_PIPELINE: FramePipeline = FramePipeline::new(Policy::Block);
+/// 串口 shell 的 Ctrl-C：接收任务收到后通知处理任务里正在执行的命令
+static SHELL_CANCEL: Cancel = Cancel::new();
+
+/// 命令执行时的 Ctrl-C 直接交给 [`SHELL_CANCEL`]，不进流水线
+fn keep_byte(byte: u8) -> bool {
+    !(byte == CTRL_C && SHELL_CANCEL.request())
+}
+
+#[embassy_executor::task]
+pub async fn receive_task(
+    rx: RingRx<'static>,
+    framer: &'static mut dyn Framer,
+    pool: &'static FramePool,
+    pipeline: &'static FramePipeline,
+) {
+    info!("UART DMA shell receiver started");
+    let mut reader = FramedReader::new(rx, framer);
+    let mut monitor = ErrorMonitor::new(RecoveryPolicy::default());
+
+    // 处理任务忙的时候这里会卡在 send 上，但循环 DMA 仍在后台接收，不会丢字节
+    loop {
+        let received = match select(
+            crate::usart::receive(&mut reader, pool, pipeline, keep_byte),
+            line::wait_request(),
+        )
+        .await
+        {
+            Either::First(received) => received,
+            Either::Second(req) => {
+                switch_line(&mut reader, req, pool, pipeline).await;
+                continue;
+            }
+        };
+
+        match received {
+            Ok((n, 0)) => {
+                stats::CONSOLE.frame(n);
+                info!("Received {} bytes", n)
+            }
+            Ok((n, _)) => {
+                stats::CONSOLE.frame(n);
+                warn!("Processing too slow, dropped frame: {:?}", pipeline.stats())
+            }
+            Err(e) => {
+                if rx_error(e, &mut monitor, |config| reader.rx_mut().set_config(config)) {
+                    reader.framer_mut().reset();
+                }
+            }
+        }
+    }
+}
+
+/// 按请求切换串口参数，[`line::FALLBACK_TIMEOUT`] 之内没收到完整的一帧就退回去
+async fn switch_line<F: Framer>(
+    reader: &mut FramedReader<RingRx<'static>, F>,
+    req: LineRequest,
+    pool: &'static FramePool,
+    pipeline: &FramePipeline,
+) {
+    let old = line::current();
+    let new = match req {
+        LineRequest::Set(new) => match reader.rx_mut().set_config(&new.to_config()) {
+            Ok(()) => new,
+            Err(e) => {
+                error!("Unsupported line config {}: {:?}", new, e);
+                return;
+            }
+        },
+        LineRequest::AutoBaud => {
+            match auto_baud(reader.rx_mut(), &old, line::FALLBACK_TIMEOUT).await {
+                Ok(new) => new,
+                Err(e) => {
+                    warn!("Auto baud detection failed: {:?}", e);
+                    return;
+                }
+            }
+        }
+    };
+    reader.framer_mut().reset();
+    info!("UART switched to {}, waiting for traffic", new);
+
+    let deadline = Instant::now() + line::FALLBACK_TIMEOUT;
+    loop {
+        let received = crate::usart::receive(reader, pool, pipeline, keep_byte);
+        match with_deadline(deadline, received).await {
+            Ok(Ok((n, _))) => {
+                stats::CONSOLE.frame(n);
+                info!("Received {} bytes, keeping {}", n, new);
+                line::set_current(new);
+                return;
+            }
+            // 参数对不上的时候多半是帧错误、噪声，继续等
+            Ok(Err(e)) => debug!("UART read error after switch: {:?}", e),
+            Err(_) => break,
+        }
+    }
+
+    warn!("No traffic at {}, falling back to {}", new, old);
+    unwrap!(reader.rx_mut().set_config(&old.to_config()));
+    reader.framer_mut().reset();
+}
+
+/// 自动检测波特率失败的原因
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum AutoBaudError {
+    /// 超时之前没有收到字符
+    Timeout,
+    /// 硬件测不出来，比如第一个字符的最低位是 0
+    Failed,
+    /// 测出来的波特率配不上
+    Config(usart::ConfigError),
+}
+
+/// 用 USART3 的自动波特率检测（ABR）测出对方的波特率，然后按测得的值重新配置。
+///
+/// 用的是“起始位长度”模式，第一个字符的最低位必须是 1，回车（`\r`）就可以。
+/// 数据位、校验和停止位沿用 `current`。
+pub async fn auto_baud(
+    rx: &mut RingRx<'static>,
+    current: &LineConfig,
+    timeout: Duration,
+) -> Result<LineConfig, AutoBaudError> {
+    let r = pac::USART3;
+    let old_div = usart_div(r);
+
+    // ABREN 只能在 USART 关掉的时候改
+    critical_section::with(|_| {
+        r.cr1().modify(|w| w.set_ue(false));
+        r.cr2().modify(|w| {
+            w.set_abren(true);
+            w.set_abrmod(Abrmod::START);
+        });
+        r.cr1().modify(|w| w.set_ue(true));
+    });
+
+    let deadline = Instant::now() + timeout;
+    let measured = loop {
+        let isr = r.isr().read();
+        if isr.abre() {
+            break Err(AutoBaudError::Failed);
+        }
+        if isr.abrf() {
+            // 硬件已经把测得的分频写进了 BRR，按和原来分频的比例换算出波特率
+            let baudrate =
+                (current.baudrate as u64 * old_div as u64 / usart_div(r).max(1) as u64) as u32;
+            break Ok(line::nearest_standard(baudrate));
+        }
+        if Instant::now() >= deadline {
+            break Err(AutoBaudError::Timeout);
+        }
+        Timer::after_millis(1).await;
+    };
+
+    // 重新配置会写 CR2，顺便把 ABREN 关掉
+    let config = LineConfig {
+        baudrate: measured.unwrap_or(current.baudrate),
+        ..*current
+    };
+    rx.set_config(&config.to_config()).map_err(AutoBaudError::Config)?;
+    measured.map(|_| config)
+}
+
+/// BRR 里的分频值。OVER8 模式下低 4 位的存法不一样
+fn usart_div(r: pac::usart::Usart) -> u32 {
+    let brr = r.brr().read().brr() as u32;
+    if r.cr1().read().over8() == Over8::OVERSAMPLING8 {
+        (brr & !0xF) | ((brr & 0x7) << 1)
+    } else {
+        brr
+    }
+}
+
+#[embassy_executor::task]
+pub async fn processing_task(
+    mut tx: UartTx<'static, Async>,
+    pipeline: &'static FramePipeline,
+) {
+    info!("Processing task started");
+
+    let mut term = shell::session::Terminal::new();
+    if let Err(e) = tx.write(shell::PROMPT.as_bytes()).await {
+        error!("Failed to send prompt: {:?}", e);
+    }
+    loop {
+        match shell::process(&mut tx, pipeline, &mut term, &SHELL_CANCEL).await {
+            Ok(n) => info!("Processed {} bytes", n),
+            Err(e) => error!("Failed to send response: {:?}", e),
+        }
+    }
+}
+
+/// 拆分串口并启动 shell 的接收任务和处理任务，`framer` 决定一条命令在哪里结束
+pub fn spawn_shell(spawner: &Spawner, uart: Uart<'static, Async>, framer: &'static mut dyn Framer) {
+    let (tx, rx) = uart.split();
+    let rx = RingRx::new(rx, rx_ring());
+    unwrap!(spawner.spawn(receive_task(rx, framer, &FRAMES, &SHELL_PIPELINE)));
+    unwrap!(spawner.spawn(processing_task(tx, &SHELL_PIPELINE)));
+}
@@ -0,0 +1,431 @@
+//! 按键：消抖，识别单击、双击、长按和长按连发。
+//!
+//! [`Gesture`] 是纯状态机，只吃带时间戳的原始电平变化（[`Gesture::edge`]）和“到点了”
+//! （[`Gesture::poll`]），不碰硬件也不读时钟，主机上可以用造出来的时间戳测试。
+//! 板子上用 [`Button`]：平时睡在 EXTI 中断上，有边沿或者到了 [`Gesture::deadline`] 才醒。
+//!
+//! 一次按下松开的事件顺序：
+//!
+//! - 单击：`Press`、`Release`，过了双击间隔没有再按才出 `Click`
+//! - 双击：`Press`、`Release`、`Press`、`Release`、`DoubleClick`，不出 `Click`
+//! - 长按：`Press`、`LongPress`、`Repeat`……、`Release`，不出 `Click`
+
+use embassy_time::{Duration, Instant};
+
+/// 按键事件
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ButtonEvent {
+    /// 按下（消抖之后）
+    Press,
+    /// 松开（消抖之后）
+    Release,
+    /// 短按一次，双击间隔里没有再按
+    Click,
+    /// 双击间隔里按了第二次并且松开了
+    DoubleClick,
+    /// 按住超过长按时间
+    LongPress,
+    /// 长按之后一直按着，每隔一段时间出一个
+    Repeat,
+}
+
+/// 时间参数
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct Timing {
+    /// 电平保持这么久不变才算数
+    pub debounce: Duration,
+    /// 松开之后这么久之内再按算双击，`Duration::MIN` 表示不识别双击，松开马上出 `Click`
+    pub double_click: Duration,
+    /// 按住这么久算长按
+    pub long_press: Duration,
+    /// 长按之后每隔多久出一个 `Repeat`，`None` 表示不连发
+    pub repeat: Option<Duration>,
+}
+
+impl Timing {
+    pub const DEFAULT: Self = Self {
+        debounce: Duration::from_millis(20),
+        double_click: Duration::from_millis(300),
+        long_press: Duration::from_millis(1000),
+        repeat: Some(Duration::from_millis(200)),
+    };
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Idle,
+    /// 按着。`second` 表示这是双击的第二下，`next` 是下一次长按或连发的时刻
+    Down {
+        second: bool,
+        long: bool,
+        next: Option<Instant>,
+    },
+    /// 松开了，等到 `until` 看有没有第二下
+    WaitSecond {
+        until: Instant,
+    },
+}
+
+/// 按键手势的状态机
+pub struct Gesture {
+    timing: Timing,
+    /// 最近一次看到的原始电平和时间
+    raw: bool,
+    raw_at: Instant,
+    /// 消抖之后的电平
+    pressed: bool,
+    state: State,
+}
+
+impl Gesture {
+    /// 开始时按键是松开的
+    pub const fn new(timing: Timing) -> Self {
+        Self {
+            timing,
+            raw: false,
+            raw_at: Instant::MIN,
+            pressed: false,
+            state: State::Idle,
+        }
+    }
+
+    pub fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    /// 消抖之后是不是按着
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// 在 `at` 时刻看到原始电平是 `pressed`（抖动也照样报进来），和上次一样就不算变化
+    pub fn edge(&mut self, pressed: bool, at: Instant, emit: &mut impl FnMut(ButtonEvent)) {
+        self.poll(at, emit);
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.raw_at = at;
+        }
+    }
+
+    /// 处理 `now` 之前（含）到点的事
+    pub fn poll(&mut self, now: Instant, emit: &mut impl FnMut(ButtonEvent)) {
+        while let Some(at) = self.deadline() {
+            if at > now {
+                break;
+            }
+            self.fire(at, emit);
+        }
+    }
+
+    /// 下一次要调 [`poll`](Self::poll) 的时刻，没有要等的就是 `None`
+    pub fn deadline(&self) -> Option<Instant> {
+        let settle = (self.raw != self.pressed).then(|| self.raw_at + self.timing.debounce);
+        let gesture = match self.state {
+            State::Idle => None,
+            State::Down { next, .. } => next,
+            State::WaitSecond { until } => Some(until),
+        };
+        match (settle, gesture) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn fire(&mut self, at: Instant, emit: &mut impl FnMut(ButtonEvent)) {
+        if self.raw != self.pressed && at >= self.raw_at + self.timing.debounce {
+            self.pressed = self.raw;
+            // 时间按真正变化的那一刻算，不算消抖等的时间
+            match self.pressed {
+                true => self.press(self.raw_at, emit),
+                false => self.release(self.raw_at, emit),
+            }
+            return;
+        }
+        match self.state {
+            State::Down { second, long, .. } => {
+                let next = self.timing.repeat.map(|every| at + every);
+                if long {
+                    emit(ButtonEvent::Repeat);
+                } else {
+                    emit(ButtonEvent::LongPress);
+                }
+                self.state = State::Down {
+                    second,
+                    long: true,
+                    next,
+                };
+            }
+            // 第二下在间隔里已经按下去了，只是还在消抖，等它消完再说
+            State::WaitSecond { .. } if self.raw && !self.pressed => {
+                self.state = State::WaitSecond {
+                    until: self.raw_at + self.timing.debounce,
+                };
+            }
+            State::WaitSecond { .. } => {
+                emit(ButtonEvent::Click);
+                self.state = State::Idle;
+            }
+            State::Idle => {}
+        }
+    }
+
+    fn press(&mut self, at: Instant, emit: &mut impl FnMut(ButtonEvent)) {
+        emit(ButtonEvent::Press);
+        self.state = State::Down {
+            second: matches!(self.state, State::WaitSecond { .. }),
+            long: false,
+            next: Some(at + self.timing.long_press),
+        };
+    }
+
+    fn release(&mut self, at: Instant, emit: &mut impl FnMut(ButtonEvent)) {
+        emit(ButtonEvent::Release);
+        self.state = match self.state {
+            State::Down { long: true, .. } => State::Idle,
+            State::Down { second: true, .. } => {
+                emit(ButtonEvent::DoubleClick);
+                State::Idle
+            }
+            _ if self.timing.double_click == Duration::MIN => {
+                emit(ButtonEvent::Click);
+                State::Idle
+            }
+            _ => State::WaitSecond {
+                until: at + self.timing.double_click,
+            },
+        };
+    }
+}
+
+#[cfg(target_os = "none")]
+pub use self::exti::Button;
+
+#[cfg(target_os = "none")]
+mod exti {
+    use embassy_futures::select::select;
+    use embassy_stm32::exti::ExtiInput;
+    use embassy_time::{Instant, Timer};
+    use heapless::Deque;
+
+    use super::{ButtonEvent, Gesture, Timing};
+
+    /// 接在 EXTI 上的按键
+    pub struct Button<'d> {
+        input: ExtiInput<'d>,
+        active_high: bool,
+        gesture: Gesture,
+        /// 一次可能出好几个事件（比如 `Release` 和 `DoubleClick`），先放在这里
+        pending: Deque<ButtonEvent, 4>,
+    }
+
+    impl<'d> Button<'d> {
+        /// `active_high`：按下时引脚是高电平
+        pub fn new(input: ExtiInput<'d>, active_high: bool, timing: Timing) -> Self {
+            let mut button = Self {
+                input,
+                active_high,
+                gesture: Gesture::new(timing),
+                pending: Deque::new(),
+            };
+            // 上电时就按着的话，当成刚按下
+            let pressed = button.pressed();
+            button.gesture.edge(pressed, Instant::now(), &mut |_| {});
+            button
+        }
+
+        fn pressed(&self) -> bool {
+            self.input.is_high() == self.active_high
+        }
+
+        /// 等下一个事件
+        pub async fn next(&mut self) -> ButtonEvent {
+            loop {
+                if let Some(event) = self.pending.pop_front() {
+                    return event;
+                }
+                let deadline = self.gesture.deadline().unwrap_or(Instant::MAX);
+                select(self.input.wait_for_any_edge(), Timer::at(deadline)).await;
+                // 不管是边沿还是到点都重新读一次电平，等边沿之前错过的变化也能补上
+                let pressed = self.pressed();
+                let now = Instant::now();
+                let pending = &mut self.pending;
+                let mut emit = |event| {
+                    if pending.push_back(event).is_err() {
+                        defmt::warn!("Button event dropped: {:?}", event);
+                    }
+                };
+                self.gesture.edge(pressed, now, &mut emit);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::ButtonEvent::*;
+    use super::*;
+
+    fn ms(n: u64) -> Instant {
+        Instant::from_millis(n)
+    }
+
+    /// 按时间顺序喂进 `edges`（毫秒, 电平），中间按 [`Gesture::deadline`] 调 `poll`，
+    /// 跑到 `end` 毫秒为止。返回每个事件和出来时的毫秒数
+    fn run(timing: Timing, edges: &[(u64, bool)], end: u64) -> Vec<(u64, ButtonEvent)> {
+        let mut gesture = Gesture::new(timing);
+        let mut events = Vec::new();
+        let mut edges = edges.iter().peekable();
+        loop {
+            let edge = edges.peek().map(|&&(at, _)| ms(at));
+            let now = match (edge, gesture.deadline()) {
+                (Some(a), Some(b)) => a.min(b),
+                (Some(at), None) | (None, Some(at)) => at,
+                (None, None) => break,
+            };
+            if now > ms(end) {
+                break;
+            }
+            let mut emit = |event| events.push((now.as_millis(), event));
+            match edges.next_if(|&&(at, _)| ms(at) == now) {
+                Some(&(_, pressed)) => gesture.edge(pressed, now, &mut emit),
+                None => gesture.poll(now, &mut emit),
+            }
+        }
+        events
+    }
+
+    fn kinds(events: &[(u64, ButtonEvent)]) -> Vec<ButtonEvent> {
+        events.iter().map(|&(_, event)| event).collect()
+    }
+
+    #[test]
+    fn click_with_bounce() {
+        let edges = [
+            (100, true),
+            (102, false),
+            (104, true),
+            (200, false),
+            (203, true),
+            (205, false),
+        ];
+        let events = run(Timing::DEFAULT, &edges, 2000);
+        // 抖动不出事件，Click 要等双击间隔过去
+        assert_eq!(events, [(124, Press), (225, Release), (505, Click)]);
+    }
+
+    #[test]
+    fn double_click() {
+        let events = run(
+            Timing::DEFAULT,
+            &[(100, true), (200, false), (350, true), (450, false)],
+            2000,
+        );
+        assert_eq!(
+            kinds(&events),
+            [Press, Release, Press, Release, DoubleClick]
+        );
+
+        // 第二下太晚，算两次单击
+        let events = run(
+            Timing::DEFAULT,
+            &[(100, true), (200, false), (600, true), (700, false)],
+            2000,
+        );
+        assert_eq!(
+            kinds(&events),
+            [Press, Release, Click, Press, Release, Click]
+        );
+
+        // 第二下按在间隔快结束的时候，间隔到了还在消抖
+        let events = run(
+            Timing::DEFAULT,
+            &[(100, true), (200, false), (495, true), (600, false)],
+            2000,
+        );
+        assert_eq!(
+            kinds(&events),
+            [Press, Release, Press, Release, DoubleClick]
+        );
+
+        // 第二下按成了长按，不算双击
+        let events = run(
+            Timing::DEFAULT,
+            &[(100, true), (200, false), (300, true), (1400, false)],
+            3000,
+        );
+        assert_eq!(kinds(&events), [Press, Release, Press, LongPress, Release]);
+    }
+
+    #[test]
+    fn long_press_repeats() {
+        let events = run(Timing::DEFAULT, &[(100, true), (1650, false)], 3000);
+        assert_eq!(
+            events,
+            [
+                (120, Press),
+                (1100, LongPress),
+                (1300, Repeat),
+                (1500, Repeat),
+                (1670, Release)
+            ]
+        );
+    }
+
+    #[test]
+    fn no_repeat_no_double_click() {
+        let timing = Timing {
+            repeat: None,
+            double_click: Duration::MIN,
+            ..Timing::DEFAULT
+        };
+        let events = run(
+            timing,
+            &[(100, true), (1650, false), (2000, true), (2050, false)],
+            3000,
+        );
+        assert_eq!(
+            events,
+            [
+                (120, Press),
+                (1100, LongPress),
+                (1670, Release),
+                (2020, Press),
+                (2070, Release),
+                (2070, Click)
+            ]
+        );
+    }
+
+    #[test]
+    fn glitches_are_ignored() {
+        let events = run(
+            Timing::DEFAULT,
+            &[(100, true), (105, false), (300, true), (310, false)],
+            3000,
+        );
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn late_poll_catches_up() {
+        let mut gesture = Gesture::new(Timing::DEFAULT);
+        let mut events = Vec::new();
+        gesture.edge(true, ms(0), &mut |event| events.push(event));
+        gesture.edge(false, ms(1250), &mut |event| events.push(event));
+        assert_eq!(events, [Press, LongPress, Repeat]);
+        gesture.poll(ms(5000), &mut |event| events.push(event));
+        assert_eq!(events, [Press, LongPress, Repeat, Release]);
+        assert!(!gesture.is_pressed());
+        assert_eq!(gesture.deadline(), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! 把 shell 开到 USB 虚拟串口和 TCP 上。
+//!
+//! 调试串口那一路还是 [`board::spawn_shell`](crate::board::spawn_shell)（切换波特率要靠它的接收任务），
+//! 这里的每个连接各跑一个 [`Session`]，行缓冲和历史互不影响，命令表共用。
+//!
+//! - USB：板子下方的 USB 口（CN13）枚举成 CDC ACM 虚拟串口，拔掉再插上会重新开始；
+//! - TCP：DHCP 拿到地址后在 [`TCP_PORT`] 上监听，最多同时 [`TCP_SESSIONS`] 个连接，
+//!   用 `nc <ip> 2323` 连接（telnet 会发协商字节，不推荐）。不合法的 UTF-8 显示成 `\xNN`。
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{Stack, StackResources};
+use embassy_stm32::eth::generic_smi::GenericSMI;
+use embassy_stm32::eth::{Ethernet, PacketQueue};
+use embassy_stm32::peripherals::{ETH, USB_OTG_FS};
+use embassy_stm32::{uid, usb};
+use embassy_time::Duration;
+use embassy_usb::class::cdc_acm::{self, CdcAcmClass};
+use embassy_usb::driver::EndpointError;
+use embassy_usb::UsbDevice;
+use static_cell::StaticCell;
+
+use crate::board::{EthPins, Irqs, UsbFs};
+use crate::shell::session::{Session, SessionError};
+use crate::usart::utf8::Invalid;
+
+// ---------------------------------------------------------------------------
+// USB 虚拟串口
+// ---------------------------------------------------------------------------
+
+type UsbDriver = usb::Driver<'static, USB_OTG_FS>;
+
+/// 全速 USB 批量端点的包长
+const USB_PACKET_SIZE: u16 = 64;
+
+/// USB 端点错误，包一层好实现 `embedded_io_async` 的错误类型
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct UsbError(pub EndpointError);
+
+impl embedded_io_async::Error for UsbError {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        match self.0 {
+            EndpointError::BufferOverflow => embedded_io_async::ErrorKind::OutOfMemory,
+            EndpointError::Disabled => embedded_io_async::ErrorKind::NotConnected,
+        }
+    }
+}
+
+/// CDC ACM 的接收端，按包读进来再按字节交出去
+pub struct UsbSerialRx {
+    rx: cdc_acm::Receiver<'static, UsbDriver>,
+    buf: [u8; USB_PACKET_SIZE as usize],
+    pos: usize,
+    len: usize,
+}
+
+impl UsbSerialRx {
+    pub fn new(rx: cdc_acm::Receiver<'static, UsbDriver>) -> Self {
+        Self {
+            rx,
+            buf: [0; USB_PACKET_SIZE as usize],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    /// 等主机打开串口（DTR 置位）
+    pub async fn wait_connection(&mut self) {
+        self.rx.wait_connection().await
+    }
+}
+
+impl embedded_io_async::ErrorType for UsbSerialRx {
+    type Error = UsbError;
+}
+
+impl embedded_io_async::Read for UsbSerialRx {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, UsbError> {
+        if self.pos == self.len {
+            self.len = self.rx.read_packet(&mut self.buf).await.map_err(UsbError)?;
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.len - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// CDC ACM 的发送端。每包最多发 `包长 - 1` 字节，这样永远不会发满包，
+/// 主机不用等零长度包就知道一次传输结束了
+pub struct UsbSerialTx {
+    tx: cdc_acm::Sender<'static, UsbDriver>,
+}
+
+impl UsbSerialTx {
+    pub fn new(tx: cdc_acm::Sender<'static, UsbDriver>) -> Self {
+        Self { tx }
+    }
+}
+
+impl embedded_io_async::ErrorType for UsbSerialTx {
+    type Error = UsbError;
+}
+
+impl embedded_io_async::Write for UsbSerialTx {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, UsbError> {
+        let n = buf.len().min(USB_PACKET_SIZE as usize - 1);
+        self.tx.write_packet(&buf[..n]).await.map_err(UsbError)?;
+        Ok(n)
+    }
+}
+
+#[embassy_executor::task]
+async fn usb_task(mut device: UsbDevice<'static, UsbDriver>) -> ! {
+    device.run().await
+}
+
+#[embassy_executor::task]
+async fn usb_console_task(class: CdcAcmClass<'static, UsbDriver>) -> ! {
+    let (tx, rx) = class.split();
+    let mut session = Session::new(UsbSerialRx::new(rx), UsbSerialTx::new(tx));
+
+    loop {
+        session.rx_mut().wait_connection().await;
+        info!("USB console connected");
+        match session.run().await {
+            Ok(()) => {}
+            Err(SessionError::Read(e)) | Err(SessionError::Write(e)) => {
+                info!("USB console disconnected: {:?}", e)
+            }
+        }
+    }
+}
+
+/// 把 USB 枚举成虚拟串口并在上面开一个 shell
+pub fn spawn_usb_console(spawner: &Spawner, usb: UsbFs) {
+    static EP_OUT_BUFFER: StaticCell<[u8; 256]> = StaticCell::new();
+    static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+    static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+    static MSOS_DESCRIPTOR: StaticCell<[u8; 0]> = StaticCell::new();
+    static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+    static STATE: StaticCell<cdc_acm::State> = StaticCell::new();
+
+    let mut config = usb::Config::default();
+    // 板子由 ST-LINK 供电，USB 口拔插时板子不掉电，要检测 VBUS（PA9）
+    config.vbus_detection = true;
+    let driver = usb::Driver::new_fs(
+        usb.otg,
+        Irqs,
+        usb.dp,
+        usb.dm,
+        EP_OUT_BUFFER.init([0; 256]),
+        config,
+    );
+
+    let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("embassy_proj1");
+    config.product = Some("Shell console");
+    config.max_packet_size_0 = 64;
+
+    let mut builder = embassy_usb::Builder::new(
+        driver,
+        config,
+        CONFIG_DESCRIPTOR.init([0; 256]),
+        BOS_DESCRIPTOR.init([0; 256]),
+        MSOS_DESCRIPTOR.init([0; 0]),
+        CONTROL_BUF.init([0; 64]),
+    );
+    let class = CdcAcmClass::new(
+        &mut builder,
+        STATE.init(cdc_acm::State::new()),
+        USB_PACKET_SIZE,
+    );
+
+    unwrap!(spawner.spawn(usb_task(builder.build())));
+    unwrap!(spawner.spawn(usb_console_task(class)));
+}
+
+// ---------------------------------------------------------------------------
+// TCP
+// ---------------------------------------------------------------------------
+
+/// TCP shell 的端口
+pub const TCP_PORT: u16 = 2323;
+/// 最多同时几个 TCP 连接
+pub const TCP_SESSIONS: usize = 2;
+
+type EthDevice = Ethernet<'static, ETH, GenericSMI>;
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, EthDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn announce_task(stack: Stack<'static>) {
+    stack.wait_config_up().await;
+    if let Some(config) = stack.config_v4() {
+        info!("TCP console on {}:{}", config.address.address(), TCP_PORT);
+    }
+}
+
+#[embassy_executor::task(pool_size = TCP_SESSIONS)]
+async fn tcp_console_task(stack: Stack<'static>) -> ! {
+    let mut rx_buffer = [0; 512];
+    let mut tx_buffer = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        // 连着不动的终端半小时后断开，免得占着连接数
+        socket.set_timeout(Some(Duration::from_secs(30 * 60)));
+
+        if let Err(e) = socket.accept(TCP_PORT).await {
+            warn!("accept error: {:?}", e);
+            continue;
+        }
+        info!("TCP console connected from {:?}", socket.remote_endpoint());
+
+        let (rx, tx) = socket.split();
+        let mut session = Session::new(rx, tx);
+        // nc 原样转发本地终端的编码，Windows 上常常是 GBK，显示成 `\xNN` 比一串 � 好查
+        session.terminal_mut().editor.set_invalid(Invalid::Hex);
+        match session.run().await {
+            Ok(()) => info!("TCP console closed"),
+            Err(SessionError::Read(e)) | Err(SessionError::Write(e)) => {
+                info!("TCP console error: {:?}", e)
+            }
+        }
+        socket.close();
+    }
+}
+
+/// 启动以太网，DHCP 拿到地址后在 [`TCP_PORT`] 上开 shell
+pub fn spawn_tcp_console(spawner: &Spawner, pins: EthPins) {
+    static PACKETS: StaticCell<PacketQueue<4, 4>> = StaticCell::new();
+    static RESOURCES: StaticCell<StackResources<{ TCP_SESSIONS + 1 }>> = StaticCell::new();
+
+    // 本地管理的 MAC 地址，后几个字节取芯片唯一 ID，同一网段里的几块板子一般不会冲突
+    let id = uid::uid();
+    let mac = [0x02, 0x00, id[0], id[1], id[2], id[3]];
+    let device = Ethernet::new(
+        PACKETS.init(PacketQueue::new()),
+        pins.eth,
+        Irqs,
+        pins.ref_clk,
+        pins.mdio,
+        pins.mdc,
+        pins.crs,
+        pins.rx_d0,
+        pins.rx_d1,
+        pins.tx_d0,
+        pins.tx_d1,
+        pins.tx_en,
+        GenericSMI::new(0),
+        mac,
+    );
+
+    // 没开 RNG，用芯片唯一 ID 当种子，至少每块板子不一样
+    let seed = u64::from_le_bytes(unwrap!(id[..8].try_into()));
+    let config = embassy_net::Config::dhcpv4(Default::default());
+    let (stack, runner) =
+        embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+    unwrap!(spawner.spawn(net_task(runner)));
+    unwrap!(spawner.spawn(announce_task(stack)));
+
+    for _ in 0..TCP_SESSIONS {
+        unwrap!(spawner.spawn(tcp_console_task(stack)));
+    }
+}
@@ -0,0 +1,383 @@
+//! 运行时借给 shell 的 GPIO 引脚。
+//!
+//! 板级代码把还没分给外设的引脚放进 [`PinPool`]，分给外设的引脚用 [`PinPool::reserve`]
+//! 记下是谁在用（比如 PD8/PD9 是 `usart3`），然后用 [`install`] 交出去。shell 的 `gpio`
+//! 命令只能配置池子里的引脚，碰到被占用的引脚会报告被谁占着。
+//!
+//! 引脚具体怎么配置交给 [`PinDriver`]：MCU 上是 `embassy_stm32::gpio::Flex`，
+//! 主机上测试时可以换成假的。刚放进池子的引脚不动硬件，交还（[`Bank::release`]）时回到模拟模式，
+//! 和复位后的状态一样。
+
+use core::cell::RefCell;
+use core::fmt::{self, Write as _};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+/// 最多几组端口（A 到 K）
+pub const PORTS: usize = 11;
+/// 每组端口几个引脚
+pub const PINS_PER_PORT: usize = 16;
+
+/// 引脚编号，比如 `PB0`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct PinId {
+    /// 0 是 A 组
+    port: u8,
+    pin: u8,
+}
+
+impl PinId {
+    pub const fn new(port: u8, pin: u8) -> Self {
+        Self { port, pin }
+    }
+
+    /// 解析 `PB0`、`pc13` 这样的写法
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.as_bytes();
+        if s.len() < 3 || !s[0].eq_ignore_ascii_case(&b'p') {
+            return None;
+        }
+        let port = s[1].to_ascii_uppercase().checked_sub(b'A')?;
+        // `str::parse` 还认 `+1`，这里只要数字
+        if !s[2..].iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let pin: u8 = core::str::from_utf8(&s[2..]).ok()?.parse().ok()?;
+        // `PB01` 这种写法不认
+        if s[2] == b'0' && s.len() > 3 {
+            return None;
+        }
+        (usize::from(port) < PORTS && usize::from(pin) < PINS_PER_PORT)
+            .then_some(Self { port, pin })
+    }
+
+    /// 按 PA0、PA1 ... PK15 的顺序列出所有可能的编号
+    pub fn all() -> impl Iterator<Item = PinId> {
+        (0..PORTS as u8)
+            .flat_map(|port| (0..PINS_PER_PORT as u8).map(move |pin| PinId::new(port, pin)))
+    }
+
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    /// 端口字母
+    pub fn port_name(&self) -> char {
+        char::from(b'A' + self.port)
+    }
+
+    fn index(&self) -> usize {
+        usize::from(self.port) * PINS_PER_PORT + usize::from(self.pin)
+    }
+}
+
+impl fmt::Display for PinId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 用 `pad` 输出，这样 `{:<6}` 之类的对齐才有用
+        let mut s = heapless::String::<4>::new();
+        let _ = write!(s, "P{}{}", self.port_name(), self.pin);
+        f.pad(&s)
+    }
+}
+
+/// 输入时的上下拉
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// 引脚的工作方式
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum PinMode {
+    Input(Pull),
+    /// 推挽输出
+    Output,
+    /// 开漏输出，同时可以读回线上的电平
+    OpenDrain,
+    /// 模拟模式，也是复位后和交还之后的状态
+    Analog,
+}
+
+impl PinMode {
+    /// 所有取值，按 [`as_str`](Self::as_str) 的写法
+    pub const ALL: [PinMode; 6] = [
+        PinMode::Input(Pull::None),
+        PinMode::Input(Pull::Up),
+        PinMode::Input(Pull::Down),
+        PinMode::Output,
+        PinMode::OpenDrain,
+        PinMode::Analog,
+    ];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.as_str() == s)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PinMode::Input(Pull::None) => "in",
+            PinMode::Input(Pull::Up) => "in-up",
+            PinMode::Input(Pull::Down) => "in-down",
+            PinMode::Output => "out",
+            PinMode::OpenDrain => "od",
+            PinMode::Analog => "analog",
+        }
+    }
+
+    /// 可以设置输出电平
+    pub fn is_output(&self) -> bool {
+        matches!(self, PinMode::Output | PinMode::OpenDrain)
+    }
+}
+
+/// 一个引脚现在归谁
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum PinState {
+    /// 在池子里，没人用
+    Free,
+    /// shell 配置过了
+    Claimed(PinMode),
+    /// 被这个驱动占着
+    Owned(&'static str),
+}
+
+/// GPIO 操作失败的原因
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum GpioError {
+    /// 板级代码没有调用 [`install`]
+    NotInstalled,
+    /// 这个封装上没有这个引脚
+    NoSuchPin,
+    /// 被别的驱动占着
+    InUse(&'static str),
+    /// 还没用 `gpio mode` 配置过
+    NotClaimed,
+    /// 不是输出模式
+    NotOutput,
+}
+
+impl GpioError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GpioError::NotInstalled => "no GPIO pins on this board",
+            GpioError::NoSuchPin => "no such pin",
+            GpioError::InUse(_) => "pin in use",
+            GpioError::NotClaimed => "pin not configured, use gpio mode first",
+            GpioError::NotOutput => "pin is not an output",
+        }
+    }
+}
+
+/// 一个引脚的硬件操作
+pub trait PinDriver {
+    fn set_mode(&mut self, mode: PinMode);
+    /// 设置输出电平，`true` 是高电平
+    fn set_output(&mut self, high: bool);
+    /// 输出寄存器里的电平
+    fn output(&self) -> bool;
+    /// 线上的电平
+    fn level(&self) -> bool;
+}
+
+/// 一个引脚的位置上放着什么
+enum Slot<P> {
+    /// 这个封装上没有，或者板级代码没交出来
+    Absent,
+    Owned(&'static str),
+    Free(P),
+    Claimed(P, PinMode),
+}
+
+/// 所有引脚和它们归谁，见模块说明
+pub struct PinPool<P> {
+    slots: [Slot<P>; PORTS * PINS_PER_PORT],
+}
+
+impl<P: PinDriver> PinPool<P> {
+    pub fn new() -> Self {
+        Self {
+            slots: core::array::from_fn(|_| Slot::Absent),
+        }
+    }
+
+    /// 把空闲的引脚放进池子
+    pub fn add(&mut self, id: PinId, pin: P) {
+        self.slots[id.index()] = Slot::Free(pin);
+    }
+
+    /// 记下 `id` 被 `owner` 占着
+    pub fn reserve(&mut self, id: PinId, owner: &'static str) {
+        self.slots[id.index()] = Slot::Owned(owner);
+    }
+
+    /// 要配置好了的引脚
+    fn claimed(&mut self, id: PinId) -> Result<(&mut P, PinMode), GpioError> {
+        match &mut self.slots[id.index()] {
+            Slot::Absent => Err(GpioError::NoSuchPin),
+            Slot::Owned(owner) => Err(GpioError::InUse(owner)),
+            Slot::Free(_) => Err(GpioError::NotClaimed),
+            Slot::Claimed(pin, mode) => Ok((pin, *mode)),
+        }
+    }
+
+    /// 要配置成输出的引脚
+    fn output(&mut self, id: PinId) -> Result<&mut P, GpioError> {
+        match self.claimed(id)? {
+            (pin, mode) if mode.is_output() => Ok(pin),
+            _ => Err(GpioError::NotOutput),
+        }
+    }
+}
+
+impl<P: PinDriver> Default for PinPool<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// shell 用来操作引脚池的接口，不用知道引脚的具体类型
+pub trait Bank {
+    /// 引脚归谁，封装上没有的引脚是 `None`
+    fn state(&self, id: PinId) -> Option<PinState>;
+    /// 配置引脚，空闲的引脚同时归 shell 所有
+    fn configure(&mut self, id: PinId, mode: PinMode) -> Result<(), GpioError>;
+    /// 回到模拟模式并放回池子
+    fn release(&mut self, id: PinId) -> Result<(), GpioError>;
+    fn write(&mut self, id: PinId, high: bool) -> Result<(), GpioError>;
+    /// 翻转输出，返回新的电平
+    fn toggle(&mut self, id: PinId) -> Result<bool, GpioError>;
+    fn read(&mut self, id: PinId) -> Result<bool, GpioError>;
+}
+
+impl<P: PinDriver> Bank for PinPool<P> {
+    fn state(&self, id: PinId) -> Option<PinState> {
+        match &self.slots[id.index()] {
+            Slot::Absent => None,
+            Slot::Owned(owner) => Some(PinState::Owned(owner)),
+            Slot::Free(_) => Some(PinState::Free),
+            Slot::Claimed(_, mode) => Some(PinState::Claimed(*mode)),
+        }
+    }
+
+    fn configure(&mut self, id: PinId, mode: PinMode) -> Result<(), GpioError> {
+        let slot = &mut self.slots[id.index()];
+        match core::mem::replace(slot, Slot::Absent) {
+            Slot::Absent => Err(GpioError::NoSuchPin),
+            Slot::Owned(owner) => {
+                *slot = Slot::Owned(owner);
+                Err(GpioError::InUse(owner))
+            }
+            Slot::Free(mut pin) | Slot::Claimed(mut pin, _) => {
+                pin.set_mode(mode);
+                *slot = Slot::Claimed(pin, mode);
+                Ok(())
+            }
+        }
+    }
+
+    fn release(&mut self, id: PinId) -> Result<(), GpioError> {
+        let slot = &mut self.slots[id.index()];
+        match core::mem::replace(slot, Slot::Absent) {
+            Slot::Claimed(mut pin, _) => {
+                pin.set_mode(PinMode::Analog);
+                *slot = Slot::Free(pin);
+                Ok(())
+            }
+            other => {
+                *slot = other;
+                self.claimed(id).map(|_| ())
+            }
+        }
+    }
+
+    fn write(&mut self, id: PinId, high: bool) -> Result<(), GpioError> {
+        self.output(id)?.set_output(high);
+        Ok(())
+    }
+
+    fn toggle(&mut self, id: PinId) -> Result<bool, GpioError> {
+        let pin = self.output(id)?;
+        let high = !pin.output();
+        pin.set_output(high);
+        Ok(high)
+    }
+
+    fn read(&mut self, id: PinId) -> Result<bool, GpioError> {
+        Ok(self.claimed(id)?.0.level())
+    }
+}
+
+type Installed = Option<&'static mut (dyn Bank + Send)>;
+
+static BANK: Mutex<CriticalSectionRawMutex, RefCell<Installed>> = Mutex::new(RefCell::new(None));
+
+/// 把引脚池交给 shell，之后只能通过 [`with`] 访问
+pub fn install(bank: &'static mut (dyn Bank + Send)) {
+    BANK.lock(|b| *b.borrow_mut() = Some(bank));
+}
+
+/// 在临界区里操作引脚池，`f` 要尽量短
+pub fn with<R>(f: impl FnOnce(&mut dyn Bank) -> Result<R, GpioError>) -> Result<R, GpioError> {
+    BANK.lock(|b| match b.borrow_mut().as_deref_mut() {
+        Some(bank) => f(bank),
+        None => Err(GpioError::NotInstalled),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+    use crate::testing::FakePin;
+
+    #[test]
+    fn parse_pin() {
+        assert_eq!(PinId::parse("PB0"), Some(PinId::new(1, 0)));
+        assert_eq!(PinId::parse("pc13"), Some(PinId::new(2, 13)));
+        assert_eq!(PinId::parse("PK15"), Some(PinId::new(10, 15)));
+        assert_eq!(PinId::new(2, 13).to_string(), "PC13");
+        for bad in [
+            "PA+1", "PA-1", "PA 1", "PA1x", "PB01", "PB16", "PL0", "PZ1", "P1", "XA1", "PA",
+        ] {
+            assert_eq!(PinId::parse(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn pool() {
+        let mut pool = PinPool::<FakePin>::new();
+        let (pb0, pd8) = (PinId::new(1, 0), PinId::new(3, 8));
+        pool.add(pb0, FakePin::default());
+        pool.reserve(pd8, "usart3");
+
+        assert_eq!(pool.state(pb0), Some(PinState::Free));
+        assert_eq!(pool.state(PinId::new(1, 1)), None);
+        assert_eq!(pool.read(pb0), Err(GpioError::NotClaimed));
+        assert_eq!(
+            pool.configure(pd8, PinMode::Output),
+            Err(GpioError::InUse("usart3"))
+        );
+        assert_eq!(pool.state(pd8), Some(PinState::Owned("usart3")));
+
+        pool.configure(pb0, PinMode::Input(Pull::Up)).unwrap();
+        assert_eq!(pool.read(pb0), Ok(true));
+        assert_eq!(pool.write(pb0, false), Err(GpioError::NotOutput));
+
+        pool.configure(pb0, PinMode::Output).unwrap();
+        assert_eq!(pool.toggle(pb0), Ok(true));
+        assert_eq!(pool.read(pb0), Ok(true));
+        pool.release(pb0).unwrap();
+        assert_eq!(pool.state(pb0), Some(PinState::Free));
+        assert_eq!(pool.release(pb0), Err(GpioError::NotClaimed));
+    }
+}
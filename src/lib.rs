@@ -0,0 +1,31 @@
+//! 各个串口实验 binary 共用的代码。
+//!
+//! - `usart`：收包、缓冲相关的逻辑，对 `embedded_io_async::Read` 泛型
+//! - `shell`：收到一帧之后怎么回应，对 `embedded_io_async::Write` 泛型
+//! - `link`：串口之上的二进制分帧协议（COBS 等）
+//! - `pool`：在任务之间传递的帧缓冲池
+//! - `gpio`：运行时借给 shell 的引脚池，记录哪些引脚被别的外设占着
+//! - `button`：按键消抖和单击、双击、长按识别
+//! - `pipeline`：生产者和消费者之间的队列，队列满时按配置的策略阻塞或丢弃
+//! - `settings`：掉电不丢的设置，存在片上 flash 最后两个扇区里，轮着写
+//! - `board`：Nucleo-H743ZI 上的引脚、DMA 通道、中断绑定以及 embassy 任务
+//! - `console`：把 shell 开到 USB 虚拟串口和 TCP 上
+//!
+//! `board` 和 `console` 只在 MCU 上编译，其余模块在主机上也能编译和测试。
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(target_os = "none")]
+pub mod board;
+#[cfg(target_os = "none")]
+pub mod console;
+pub mod button;
+pub mod gpio;
+pub mod link;
+pub mod pipeline;
+pub mod pool;
+pub mod settings;
+pub mod shell;
+pub mod usart;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,123 @@
+//! COBS（Consistent Overhead Byte Stuffing）编解码。
+//!
+//! 编码后的数据里不含 `0`，于是可以用 `0` 作为帧分隔符，负载里有没有 `0` 都不受影响。
+//! 每 254 个字节最多多出 1 个字节的开销。
+
+/// 编解码错误
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Error {
+    /// 输出缓冲区放不下
+    BufferTooSmall,
+    /// 输入不是合法的 COBS 数据（含有 0、长度码越界或者为空）
+    Malformed,
+}
+
+/// 编码 `len` 字节最多需要的空间（不含帧分隔符）
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// 把 `src` 编码到 `dst`，返回写入的字节数。不会写帧分隔符。
+pub fn encode(src: &[u8], dst: &mut [u8]) -> Result<usize, Error> {
+    if dst.len() < max_encoded_len(src.len()) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let mut code_idx = 0;
+    let mut out = 1;
+    let mut code = 1u8;
+
+    for &byte in src {
+        if byte != 0 {
+            dst[out] = byte;
+            out += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            dst[code_idx] = code;
+            code_idx = out;
+            out += 1;
+            code = 1;
+        }
+    }
+    dst[code_idx] = code;
+    Ok(out)
+}
+
+/// 把 `src` 解码到 `dst`，返回解码后的字节数
+pub fn decode(src: &[u8], dst: &mut [u8]) -> Result<usize, Error> {
+    let mut out = 0;
+    decode_with(src, |chunk, zero| {
+        let end = out + chunk.len() + zero as usize;
+        if end > dst.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        dst[out..out + chunk.len()].copy_from_slice(chunk);
+        if zero {
+            dst[end - 1] = 0;
+        }
+        out = end;
+        Ok(())
+    })?;
+    Ok(out)
+}
+
+/// 原地解码，返回解码后的长度，结果在 `buf[..len]` 里。
+///
+/// 解码后的数据不会比编码前长，所以不需要额外的缓冲区。
+pub fn decode_in_place(buf: &mut [u8]) -> Result<usize, Error> {
+    check(buf)?;
+
+    let mut read = 0;
+    let mut write = 0;
+    while read < buf.len() {
+        let code = buf[read] as usize;
+        read += 1;
+        buf.copy_within(read..read + code - 1, write);
+        read += code - 1;
+        write += code - 1;
+        if code != 0xFF && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+    Ok(write)
+}
+
+/// 逐段遍历编码数据，`f` 收到每一段数据以及后面是否要补一个 `0`
+fn decode_with(
+    src: &[u8],
+    mut f: impl FnMut(&[u8], bool) -> Result<(), Error>,
+) -> Result<(), Error> {
+    check(src)?;
+
+    let mut read = 0;
+    while read < src.len() {
+        let code = src[read] as usize;
+        read += 1;
+        let chunk = &src[read..read + code - 1];
+        read += code - 1;
+        f(chunk, code != 0xFF && read < src.len())?;
+    }
+    Ok(())
+}
+
+/// 检查长度码是否都落在数据范围内、数据里有没有 0
+fn check(src: &[u8]) -> Result<(), Error> {
+    if src.is_empty() {
+        return Err(Error::Malformed);
+    }
+
+    let mut read = 0;
+    while read < src.len() {
+        let code = src[read] as usize;
+        if code == 0 || read + code > src.len() {
+            return Err(Error::Malformed);
+        }
+        if src[read + 1..read + code].contains(&0) {
+            return Err(Error::Malformed);
+        }
+        read += code;
+    }
+    Ok(())
+}
@@ -0,0 +1,14 @@
+//! 串口之上的链路层：把任意二进制数据打包成帧在串口上传输。
+//!
+//! 原来的回显任务只能传文本，还把 `0` 当作结束符，二进制数据里一旦有 `0` 就会被截断。
+//! 这里的代码同样只依赖 `embedded_io_async` 的 `Read` / `Write`。
+
+pub mod cobs;
+pub mod crc;
+mod packet;
+pub mod reliable;
+pub mod slip;
+#[cfg(all(target_os = "none", feature = "slip-net"))]
+pub mod slip_net;
+
+pub use packet::{PacketError, PacketRx, PacketTx};
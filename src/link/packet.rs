@@ -0,0 +1,125 @@
+//! COBS 分帧的收发端：每个包编码后以 `0` 结尾。
+
+use embedded_io_async::{Read, Write};
+
+use super::cobs;
+
+/// 收发包的错误
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum PacketError<E> {
+    /// 底层串口出错
+    Io(E),
+    /// 包太大：接收时缓冲区满了还没遇到分隔符（这一帧剩下的部分会被丢掉），
+    /// 或者发送时编码结果放不下
+    Oversized,
+    /// 收到的帧不是合法的 COBS 数据
+    Malformed,
+}
+
+/// 收包端，`N` 是编码后单帧的最大长度
+pub struct PacketRx<R, const N: usize> {
+    rx: R,
+    buf: [u8; N],
+    len: usize,
+    /// 已经找过分隔符的字节数，避免重复扫描
+    scanned: usize,
+    /// 上一个包占用的字节数，下次收包前挪走
+    consumed: usize,
+    /// 正在丢弃一个超长帧，直到下一个分隔符
+    discarding: bool,
+}
+
+impl<R: Read, const N: usize> PacketRx<R, N> {
+    pub fn new(rx: R) -> Self {
+        Self {
+            rx,
+            buf: [0; N],
+            len: 0,
+            scanned: 0,
+            consumed: 0,
+            discarding: false,
+        }
+    }
+
+    pub fn rx(&self) -> &R {
+        &self.rx
+    }
+
+    /// 收下一个包，返回解码后的负载。
+    ///
+    /// 负载是在接收缓冲区里原地解码的，不会再拷贝一次。空帧（连续的分隔符）会被跳过。
+    pub async fn recv(&mut self) -> Result<&[u8], PacketError<R::Error>> {
+        loop {
+            if self.consumed > 0 {
+                self.buf.copy_within(self.consumed..self.len, 0);
+                self.len -= self.consumed;
+                self.consumed = 0;
+                self.scanned = 0;
+            }
+
+            if let Some(pos) = self.buf[self.scanned..self.len].iter().position(|&b| b == 0) {
+                let end = self.scanned + pos;
+                self.consumed = end + 1;
+
+                if core::mem::take(&mut self.discarding) || end == 0 {
+                    continue;
+                }
+                let n = cobs::decode_in_place(&mut self.buf[..end])
+                    .map_err(|_| PacketError::Malformed)?;
+                return Ok(&self.buf[..n]);
+            }
+            self.scanned = self.len;
+
+            if self.len == N {
+                // 缓冲区满了还没遇到分隔符，丢掉这一帧
+                self.len = 0;
+                self.scanned = 0;
+                if !core::mem::replace(&mut self.discarding, true) {
+                    return Err(PacketError::Oversized);
+                }
+            }
+
+            let n = self
+                .rx
+                .read(&mut self.buf[self.len..])
+                .await
+                .map_err(PacketError::Io)?;
+            self.len += n;
+        }
+    }
+}
+
+/// 发包端，`N` 是编码后单帧（含分隔符）的最大长度
+pub struct PacketTx<W, const N: usize> {
+    tx: W,
+    buf: [u8; N],
+}
+
+impl<W: Write, const N: usize> PacketTx<W, N> {
+    /// 能发送的最大负载长度
+    pub const MAX_PAYLOAD: usize = max_payload(N);
+
+    pub fn new(tx: W) -> Self {
+        Self { tx, buf: [0; N] }
+    }
+
+    pub fn tx_mut(&mut self) -> &mut W {
+        &mut self.tx
+    }
+
+    /// 编码并发送一个包
+    pub async fn send(&mut self, payload: &[u8]) -> Result<(), PacketError<W::Error>> {
+        let n = cobs::encode(payload, &mut self.buf[..N - 1]).map_err(|_| PacketError::Oversized)?;
+        self.buf[n] = 0;
+        self.tx.write_all(&self.buf[..=n]).await.map_err(PacketError::Io)
+    }
+}
+
+/// 编码后（含分隔符）不超过 `frame` 字节的最大负载长度
+const fn max_payload(frame: usize) -> usize {
+    let mut len = frame.saturating_sub(2);
+    while len > 0 && cobs::max_encoded_len(len) + 1 > frame {
+        len -= 1;
+    }
+    len
+}
@@ -0,0 +1,574 @@
+//! 可靠传输：在 COBS 包上加序号和 CRC，靠 ACK / NACK 和超时重传保证送达。
+//!
+//! 每个包的格式（COBS 编码之前）：
+//!
+//! ```text
+//! | 类型 (1) | 序号 (1) | 负载 (0..) | CRC (2 或 4，小端) |
+//! ```
+//!
+//! - `DATA` 带负载，序号从 0 开始逐个加 1，回绕；
+//! - `ACK n` 表示 `n` 之前的包都收到了，下一个想要 `n`；
+//! - `NACK n` 同样确认 `n` 之前的包，并要求从 `n` 开始重发；
+//! - `SYNC` 和 `DATA` 一样带负载，发送端放弃过一批包之后的第一个包用它，
+//!   接收端收到时直接跳到这个序号，不再等被放弃的那些。
+//!
+//! 发送端最多有 `WINDOW` 个包在路上没被确认（回退 N 帧）。
+//! 接收端只收序号正好等于期望值的包，乱序的丢掉并回一次 NACK，重复的再回一次 ACK。
+//! CRC 错的包当作没收到，同样回 NACK。
+//!
+//! 两端都只有一个 [`Link`]，收包、回 ACK、超时重传都在 [`Link::recv`] / [`Link::send`] /
+//! [`Link::flush`] 里顺带完成，所以要有任务一直在调用它们，链路才会往前走。
+
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{Read, Write};
+
+use super::crc::{crc16, crc32};
+use super::{PacketError, PacketRx, PacketTx};
+
+const DATA: u8 = 0;
+const ACK: u8 = 1;
+const NACK: u8 = 2;
+const SYNC: u8 = 3;
+
+/// 类型 + 序号
+const HEADER: usize = 2;
+
+/// 校验方式
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Crc {
+    /// CRC-16/CCITT-FALSE，短包够用
+    Crc16,
+    /// CRC-32/ISO-HDLC，包比较长或者线路比较差时用
+    Crc32,
+}
+
+impl Crc {
+    /// 校验值的字节数
+    pub const fn size(self) -> usize {
+        match self {
+            Crc::Crc16 => 2,
+            Crc::Crc32 => 4,
+        }
+    }
+
+    /// 在 `buf[..len]` 后面追加校验值，返回总长度
+    fn append(self, buf: &mut [u8], len: usize) -> usize {
+        match self {
+            Crc::Crc16 => {
+                let crc = crc16(&buf[..len]);
+                buf[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+            }
+            Crc::Crc32 => {
+                let crc = crc32(&buf[..len]);
+                buf[len..len + 4].copy_from_slice(&crc.to_le_bytes());
+            }
+        }
+        len + self.size()
+    }
+
+    /// 校验通过就返回去掉校验值的部分
+    fn check(self, frame: &[u8]) -> Option<&[u8]> {
+        let (body, crc) = frame.split_at_checked(frame.len().checked_sub(self.size())?)?;
+        let ok = match self {
+            Crc::Crc16 => crc16(body).to_le_bytes()[..] == *crc,
+            Crc::Crc32 => crc32(body).to_le_bytes()[..] == *crc,
+        };
+        ok.then_some(body)
+    }
+}
+
+/// 链路参数，两端要一致
+#[derive(Clone, Copy, Debug, defmt::Format)]
+pub struct Config {
+    pub crc: Crc,
+    /// 最早发出的包这么久还没被确认，就把窗口里的包全部重发
+    pub ack_timeout: Duration,
+    /// 连续重发这么多次都没有进展，就认为对端不在了
+    pub max_retries: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            crc: Crc::Crc16,
+            ack_timeout: Duration::from_millis(200),
+            max_retries: 5,
+        }
+    }
+}
+
+/// 收发统计
+#[derive(Clone, Copy, Default, Debug, defmt::Format)]
+pub struct Stats {
+    /// 第一次发出的数据包
+    pub tx_packets: u32,
+    pub tx_bytes: u32,
+    /// 重发的数据包
+    pub retransmits: u32,
+    /// 等 ACK 超时的次数
+    pub timeouts: u32,
+    pub nacks_received: u32,
+    /// 按顺序交给上层的数据包
+    pub rx_packets: u32,
+    pub rx_bytes: u32,
+    /// CRC 错或者解不开的帧
+    pub crc_errors: u32,
+    pub duplicates: u32,
+    pub out_of_order: u32,
+    /// 上层还没取走上一个包，只好丢掉（对端会重发）
+    pub dropped: u32,
+    pub acks_sent: u32,
+    pub nacks_sent: u32,
+}
+
+/// 链路错误
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum LinkError<E> {
+    /// 底层串口出错
+    Io(E),
+    /// 负载超过 [`Link::MAX_PAYLOAD`]
+    Oversized,
+    /// 重发了 `max_retries` 次还是没有确认。没确认的包都被丢掉了，对端可能收到了也可能没有，
+    /// 下一个包带上 `SYNC` 让对端跳过它们
+    Timeout,
+}
+
+/// 可靠链路。
+///
+/// `N` 是编码后单帧的最大长度（同 [`PacketRx`] / [`PacketTx`]），
+/// `WINDOW` 是最多允许多少个包没被确认，必须小于 128。
+/// 窗口里的每个包都要留一份以便重发，所以占用大约 `(WINDOW + 2) * N` 字节。
+pub struct Link<R, W, const N: usize, const WINDOW: usize> {
+    rx: PacketRx<R, N>,
+    tx: PacketTx<W, N>,
+    config: Config,
+
+    /// 已发出还没确认的包（已经带上头和 CRC），从 `head` 开始的 `in_flight` 个
+    window: [[u8; N]; WINDOW],
+    lens: [usize; WINDOW],
+    head: usize,
+    in_flight: usize,
+    /// `window[head]` 的序号
+    base: u8,
+    /// 到这个时间还没有进展就重发
+    deadline: Instant,
+    retries: u8,
+    /// 放弃过没确认的包，下一个新包要用 `SYNC` 发
+    resync: bool,
+
+    /// 下一个期望收到的序号
+    expected: u8,
+    /// 当前这个缺口已经发过 NACK 了，收到期望的包之前不再重复发
+    nacked: bool,
+    /// 收到了还没交给上层的包
+    inbox: [u8; N],
+    inbox_len: Option<usize>,
+    /// 组 ACK / NACK 用
+    scratch: [u8; 8],
+
+    stats: Stats,
+}
+
+impl<E, R, W, const N: usize, const WINDOW: usize> Link<R, W, N, WINDOW>
+where
+    R: Read<Error = E>,
+    W: Write<Error = E>,
+{
+    /// 能发送的最大负载长度（按 CRC-32 算，两种校验都能用）
+    pub const MAX_PAYLOAD: usize = PacketTx::<W, N>::MAX_PAYLOAD - HEADER - Crc::Crc32.size();
+
+    pub fn new(rx: R, tx: W, config: Config) -> Self {
+        assert!(WINDOW > 0 && WINDOW < 128);
+        assert!(Self::MAX_PAYLOAD > 0);
+
+        Self {
+            rx: PacketRx::new(rx),
+            tx: PacketTx::new(tx),
+            config,
+            window: [[0; N]; WINDOW],
+            lens: [0; WINDOW],
+            head: 0,
+            in_flight: 0,
+            base: 0,
+            deadline: Instant::MAX,
+            retries: 0,
+            resync: false,
+            expected: 0,
+            nacked: false,
+            inbox: [0; N],
+            inbox_len: None,
+            scratch: [0; 8],
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// 还没被确认的包数
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// 发送一个包。
+    ///
+    /// 包放进窗口并发出去就返回，不等确认；窗口满了会先等确认腾出位置。
+    /// 要确认对端都收到了，调用 [`flush`](Self::flush)。
+    pub async fn send(&mut self, payload: &[u8]) -> Result<(), LinkError<E>> {
+        if payload.len() > Self::MAX_PAYLOAD {
+            return Err(LinkError::Oversized);
+        }
+        while self.in_flight == WINDOW {
+            self.poll().await?;
+        }
+
+        let idx = (self.head + self.in_flight) % WINDOW;
+        let slot = &mut self.window[idx];
+        slot[0] = if core::mem::take(&mut self.resync) {
+            SYNC
+        } else {
+            DATA
+        };
+        slot[1] = self.base.wrapping_add(self.in_flight as u8);
+        slot[HEADER..HEADER + payload.len()].copy_from_slice(payload);
+        let len = self.config.crc.append(slot, HEADER + payload.len());
+        self.lens[idx] = len;
+
+        if self.in_flight == 0 {
+            self.deadline = Instant::now() + self.config.ack_timeout;
+        }
+        self.in_flight += 1;
+        self.stats.tx_packets = self.stats.tx_packets.wrapping_add(1);
+        self.stats.tx_bytes = self.stats.tx_bytes.wrapping_add(payload.len() as u32);
+
+        self.tx.send(&self.window[idx][..len]).await.map_err(tx_error)
+    }
+
+    /// 等到发出的包全部被确认。期间收到的数据包会留给下一次 [`recv`](Self::recv)
+    pub async fn flush(&mut self) -> Result<(), LinkError<E>> {
+        while self.in_flight > 0 {
+            self.poll().await?;
+        }
+        Ok(())
+    }
+
+    /// 按顺序收下一个数据包
+    pub async fn recv(&mut self) -> Result<&[u8], LinkError<E>> {
+        loop {
+            if let Some(len) = self.inbox_len.take() {
+                return Ok(&self.inbox[..len]);
+            }
+            self.poll().await?;
+        }
+    }
+
+    /// 处理一个收到的帧，或者处理一次超时
+    async fn poll(&mut self) -> Result<(), LinkError<E>> {
+        let received = if self.in_flight > 0 {
+            match select(self.rx.recv(), Timer::at(self.deadline)).await {
+                Either::First(received) => received,
+                Either::Second(()) => return self.on_timeout().await,
+            }
+        } else {
+            self.rx.recv().await
+        };
+
+        let frame = match received {
+            Ok(frame) => frame,
+            Err(PacketError::Io(e)) => return Err(LinkError::Io(e)),
+            Err(PacketError::Oversized | PacketError::Malformed) => return self.on_corrupt().await,
+        };
+        let Some((&kind, &seq, payload)) = self
+            .config
+            .crc
+            .check(frame)
+            .and_then(|body| Some((body.first()?, body.get(1)?, &body[HEADER..])))
+        else {
+            return self.on_corrupt().await;
+        };
+
+        match kind {
+            DATA | SYNC => {
+                let mut ahead = seq.wrapping_sub(self.expected);
+                if kind == SYNC && ahead < 128 {
+                    // 对端放弃了 `expected` 到 `seq` 之间的包
+                    self.expected = seq;
+                    ahead = 0;
+                }
+                if ahead == 0 {
+                    if self.inbox_len.is_some() {
+                        self.stats.dropped = self.stats.dropped.wrapping_add(1);
+                        return Ok(());
+                    }
+                    self.inbox[..payload.len()].copy_from_slice(payload);
+                    self.inbox_len = Some(payload.len());
+                    self.stats.rx_packets = self.stats.rx_packets.wrapping_add(1);
+                    self.stats.rx_bytes = self.stats.rx_bytes.wrapping_add(payload.len() as u32);
+                    self.expected = self.expected.wrapping_add(1);
+                    self.nacked = false;
+                    self.send_control(ACK).await
+                } else if ahead >= 128 {
+                    // 对端没收到我们的 ACK，又重发了一遍
+                    self.stats.duplicates = self.stats.duplicates.wrapping_add(1);
+                    self.send_control(ACK).await
+                } else {
+                    self.stats.out_of_order = self.stats.out_of_order.wrapping_add(1);
+                    self.nack_once().await
+                }
+            }
+            ACK => {
+                self.acknowledge(seq);
+                Ok(())
+            }
+            NACK => {
+                self.stats.nacks_received = self.stats.nacks_received.wrapping_add(1);
+                self.acknowledge(seq);
+                self.retransmit().await
+            }
+            _ => self.on_corrupt().await,
+        }
+    }
+
+    /// 确认 `next` 之前的包
+    fn acknowledge(&mut self, next: u8) {
+        let n = next.wrapping_sub(self.base) as usize;
+        if n == 0 || n > self.in_flight {
+            return;
+        }
+        self.base = next;
+        self.head = (self.head + n) % WINDOW;
+        self.in_flight -= n;
+        self.retries = 0;
+        self.deadline = if self.in_flight > 0 {
+            Instant::now() + self.config.ack_timeout
+        } else {
+            Instant::MAX
+        };
+    }
+
+    async fn on_timeout(&mut self) -> Result<(), LinkError<E>> {
+        self.stats.timeouts = self.stats.timeouts.wrapping_add(1);
+        self.retries += 1;
+        if self.retries > self.config.max_retries {
+            // 放弃窗口里的包，序号跳过它们。对端可能已经收到了（只是 ACK 丢了），
+            // 序号重用的话新包会被当成重复的丢掉
+            self.base = self.base.wrapping_add(self.in_flight as u8);
+            self.head = (self.head + self.in_flight) % WINDOW;
+            self.in_flight = 0;
+            self.resync = true;
+            self.retries = 0;
+            self.deadline = Instant::MAX;
+            return Err(LinkError::Timeout);
+        }
+        self.retransmit().await
+    }
+
+    /// 把窗口里的包全部重发一遍
+    async fn retransmit(&mut self) -> Result<(), LinkError<E>> {
+        for i in 0..self.in_flight {
+            let idx = (self.head + i) % WINDOW;
+            self.tx.send(&self.window[idx][..self.lens[idx]]).await.map_err(tx_error)?;
+            self.stats.retransmits = self.stats.retransmits.wrapping_add(1);
+        }
+        if self.in_flight > 0 {
+            self.deadline = Instant::now() + self.config.ack_timeout;
+        }
+        Ok(())
+    }
+
+    async fn on_corrupt(&mut self) -> Result<(), LinkError<E>> {
+        self.stats.crc_errors = self.stats.crc_errors.wrapping_add(1);
+        self.nack_once().await
+    }
+
+    async fn nack_once(&mut self) -> Result<(), LinkError<E>> {
+        if core::mem::replace(&mut self.nacked, true) {
+            return Ok(());
+        }
+        self.send_control(NACK).await
+    }
+
+    /// 发 ACK / NACK，带上期望的序号
+    async fn send_control(&mut self, kind: u8) -> Result<(), LinkError<E>> {
+        self.scratch[0] = kind;
+        self.scratch[1] = self.expected;
+        let len = self.config.crc.append(&mut self.scratch, HEADER);
+        if kind == ACK {
+            self.stats.acks_sent = self.stats.acks_sent.wrapping_add(1);
+        } else {
+            self.stats.nacks_sent = self.stats.nacks_sent.wrapping_add(1);
+        }
+        self.tx.send(&self.scratch[..len]).await.map_err(tx_error)
+    }
+}
+
+fn tx_error<E>(e: PacketError<E>) -> LinkError<E> {
+    match e {
+        PacketError::Io(e) => LinkError::Io(e),
+        PacketError::Oversized | PacketError::Malformed => LinkError::Oversized,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::Cell;
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::pipe::Pipe;
+    use embassy_time::with_timeout;
+
+    use super::*;
+
+    type Wire = Pipe<NoopRawMutex, 8192>;
+
+    /// 按整帧丢包、改错字节、和下一帧换顺序的信道，`loss` 是每种情况的百分比
+    struct Lossy<'a> {
+        wire: &'a Wire,
+        rng: u32,
+        loss: &'a Cell<u32>,
+        held: Option<Vec<u8>>,
+    }
+
+    impl<'a> Lossy<'a> {
+        fn new(wire: &'a Wire, seed: u32, loss: &'a Cell<u32>) -> Self {
+            Self {
+                wire,
+                rng: seed,
+                loss,
+                held: None,
+            }
+        }
+    }
+
+    impl embedded_io_async::ErrorType for Lossy<'_> {
+        type Error = Infallible;
+    }
+
+    impl Write for Lossy<'_> {
+        // `PacketTx` 一次写一整帧
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            self.rng = self.rng.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let roll = (self.rng >> 16) % 100;
+            let loss = self.loss.get();
+            let mut frame = buf.to_vec();
+            if roll < loss {
+                return Ok(buf.len());
+            } else if roll < 2 * loss {
+                let i = self.rng as usize % frame.len();
+                frame[i] ^= 0x55;
+            } else if roll < 3 * loss && self.held.is_none() {
+                self.held = Some(frame);
+                return Ok(buf.len());
+            }
+            self.wire.write_all(&frame).await;
+            if let Some(held) = self.held.take() {
+                self.wire.write_all(&held).await;
+            }
+            Ok(buf.len())
+        }
+    }
+
+    fn config(crc: Crc, max_retries: u8) -> Config {
+        Config {
+            crc,
+            ack_timeout: Duration::from_millis(10),
+            max_retries,
+        }
+    }
+
+    /// 一直收到安静了 200 ms 为止
+    async fn drain<R: Read<Error = Infallible>, W: Write<Error = Infallible>>(
+        link: &mut Link<R, W, 128, 4>,
+    ) -> Vec<Vec<u8>> {
+        let mut got = Vec::new();
+        while let Ok(packet) = with_timeout(Duration::from_millis(200), link.recv()).await {
+            got.push(packet.unwrap().to_vec());
+        }
+        got
+    }
+
+    fn transfer(crc: Crc, loss: u32) {
+        const COUNT: usize = 200;
+        let (ab, ba) = (Wire::new(), Wire::new());
+        let loss = Cell::new(loss);
+        let mut a = Link::<_, _, 128, 4>::new(&ba, Lossy::new(&ab, 1, &loss), config(crc, 50));
+        let mut b = Link::<_, _, 128, 4>::new(&ab, Lossy::new(&ba, 7, &loss), config(crc, 50));
+        let (_, got) = block_on(join(
+            async {
+                for i in 0..COUNT {
+                    a.send(&[i as u8; 37][..i % 37 + 1]).await.unwrap();
+                }
+                a.flush().await.unwrap();
+            },
+            drain(&mut b),
+        ));
+
+        assert_eq!(got.len(), COUNT);
+        for (i, packet) in got.iter().enumerate() {
+            assert_eq!(packet[..], [i as u8; 37][..i % 37 + 1]);
+        }
+        if loss.get() > 0 {
+            assert!(a.stats().retransmits > 0);
+            assert!(b.stats().crc_errors + b.stats().out_of_order > 0);
+        }
+    }
+
+    #[test]
+    fn clean() {
+        transfer(Crc::Crc16, 0);
+    }
+
+    #[test]
+    fn lossy_reordering() {
+        transfer(Crc::Crc16, 5);
+        transfer(Crc::Crc32, 10);
+    }
+
+    /// 一个方向完全断掉，发送端放弃之后再恢复，后面的包照样能按顺序送到
+    fn give_up(data_lost: bool) -> Vec<Vec<u8>> {
+        let (ab, ba) = (Wire::new(), Wire::new());
+        let (ab_loss, ba_loss) = (Cell::new(0), Cell::new(0));
+        let cut = if data_lost { &ab_loss } else { &ba_loss };
+        let cfg = config(Crc::Crc16, 2);
+        let mut a = Link::<_, _, 128, 4>::new(&ba, Lossy::new(&ab, 1, &ab_loss), cfg);
+        let mut b = Link::<_, _, 128, 4>::new(&ab, Lossy::new(&ba, 7, &ba_loss), cfg);
+        let (_, got) = block_on(join(
+            async {
+                a.send(b"one").await.unwrap();
+                a.flush().await.unwrap();
+                cut.set(100);
+                a.send(b"two").await.unwrap();
+                a.send(b"three").await.unwrap();
+                assert_eq!(a.flush().await, Err(LinkError::Timeout));
+                assert_eq!(a.in_flight(), 0);
+                cut.set(0);
+                a.send(b"four").await.unwrap();
+                a.send(b"five").await.unwrap();
+                a.flush().await.unwrap();
+            },
+            drain(&mut b),
+        ));
+        got
+    }
+
+    #[test]
+    fn give_up_after_acks_lost() {
+        // 对端其实都收到了，新包不能因为序号重用被当成重复的
+        let got = give_up(false);
+        assert_eq!(got, [&b"one"[..], b"two", b"three", b"four", b"five"]);
+    }
+
+    #[test]
+    fn give_up_after_data_lost() {
+        let got = give_up(true);
+        assert_eq!(got, [&b"one"[..], b"four", b"five"]);
+    }
+}
@@ -0,0 +1,294 @@
+//! SLIP（RFC 1055）编解码。
+//!
+//! 帧以 `END`（0xC0）结尾，数据里的 `END` / `ESC` 用两个字节转义。
+//! 解码器实现了 [`Framer`]，可以直接替换各个 USART 读取循环里的 [`Nul`](crate::usart::framer::Nul)。
+
+use embedded_io_async::Write;
+
+use crate::usart::Framer;
+
+pub const END: u8 = 0xC0;
+pub const ESC: u8 = 0xDB;
+pub const ESC_END: u8 = 0xDC;
+pub const ESC_ESC: u8 = 0xDD;
+
+/// 编码错误
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Error {
+    /// 输出缓冲区放不下
+    BufferTooSmall,
+}
+
+/// 编码 `len` 字节最多需要的空间，包括首尾两个 `END`
+pub const fn max_encoded_len(len: usize) -> usize {
+    2 * len + 2
+}
+
+/// 把 `src` 编码到 `dst`，返回写入的字节数。
+///
+/// 和 RFC 1055 的示例一样，帧前面也放一个 `END`，把线路上的噪声单独隔成一个空帧。
+pub fn encode(src: &[u8], dst: &mut [u8]) -> Result<usize, Error> {
+    let mut out = 0;
+    let mut put = |byte: u8| {
+        let slot = dst.get_mut(out).ok_or(Error::BufferTooSmall)?;
+        *slot = byte;
+        out += 1;
+        Ok(())
+    };
+
+    put(END)?;
+    for &byte in src {
+        match byte {
+            END => {
+                put(ESC)?;
+                put(ESC_END)?;
+            }
+            ESC => {
+                put(ESC)?;
+                put(ESC_ESC)?;
+            }
+            _ => put(byte)?,
+        }
+    }
+    put(END)?;
+    Ok(out)
+}
+
+/// 边转义边发送一帧，不需要额外的缓冲区
+pub async fn write_frame<W: Write>(tx: &mut W, payload: &[u8]) -> Result<(), W::Error> {
+    tx.write_all(&[END]).await?;
+    for chunk in payload.split_inclusive(|&b| b == END || b == ESC) {
+        match chunk.split_last() {
+            Some((&END, rest)) => {
+                tx.write_all(rest).await?;
+                tx.write_all(&[ESC, ESC_END]).await?;
+            }
+            Some((&ESC, rest)) => {
+                tx.write_all(rest).await?;
+                tx.write_all(&[ESC, ESC_ESC]).await?;
+            }
+            _ => tx.write_all(chunk).await?,
+        }
+    }
+    tx.write_all(&[END]).await
+}
+
+/// SLIP 解码器，`N` 是解码后单帧的最大长度。
+///
+/// 坏帧（`ESC` 后面跟了不认识的字节，或者超过 `N`）整帧丢掉，不会交出去，
+/// 分别计入 [`errors`](Self::errors) 和 [`oversized`](Self::oversized)。空帧会被忽略。
+pub struct Decoder<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    /// 上一帧已经交出去了，下次 push 之前要先清空
+    done: bool,
+    /// 上一个字节是 `ESC`
+    escaped: bool,
+    /// 当前帧已经坏了，丢弃到下一个 `END`
+    broken: bool,
+    overflow: bool,
+    errors: u32,
+    oversized: u32,
+}
+
+impl<const N: usize> Decoder<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            done: false,
+            escaped: false,
+            broken: false,
+            overflow: false,
+            errors: 0,
+            oversized: 0,
+        }
+    }
+
+    /// 因为转义错误丢掉的帧数
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    /// 因为太长丢掉的帧数
+    pub fn oversized(&self) -> u32 {
+        self.oversized
+    }
+
+    fn store(&mut self, byte: u8) {
+        if self.len < N {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        } else {
+            self.broken = true;
+            self.overflow = true;
+        }
+    }
+
+    fn end(&mut self) -> bool {
+        let bad = self.broken || self.escaped;
+        if self.overflow {
+            self.oversized = self.oversized.wrapping_add(1);
+        } else if bad {
+            self.errors = self.errors.wrapping_add(1);
+        }
+        self.broken = false;
+        self.overflow = false;
+        self.escaped = false;
+
+        if bad {
+            self.len = 0;
+            return false;
+        }
+        self.done = self.len > 0;
+        self.done
+    }
+}
+
+impl<const N: usize> Default for Decoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Framer for Decoder<N> {
+    fn push(&mut self, byte: u8) -> bool {
+        if core::mem::take(&mut self.done) {
+            self.len = 0;
+        }
+
+        if byte == END {
+            return self.end();
+        }
+        if self.broken {
+            return false;
+        }
+
+        if core::mem::take(&mut self.escaped) {
+            match byte {
+                ESC_END => self.store(END),
+                ESC_ESC => self.store(ESC),
+                _ => self.broken = true,
+            }
+        } else if byte == ESC {
+            self.escaped = true;
+        } else {
+            self.store(byte);
+        }
+        false
+    }
+
+    fn frame(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.done = false;
+        self.escaped = false;
+        self.broken = false;
+        self.overflow = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::testing::{MockRx, MockTx};
+    use crate::usart::FramedReader;
+
+    /// 10.0.0.2 ping 10.0.0.1 的 IPv4 包，IP 标识和 ICMP 数据里特意带上了 `END` / `ESC`
+    const PING: [u8; 36] = [
+        0x45, 0x00, 0x00, 0x24, 0xc0, 0xdb, 0x40, 0x00, 0x40, 0x01, 0x65, 0xfb, 0x0a, 0x00, 0x00,
+        0x02, 0x0a, 0x00, 0x00, 0x01, 0x08, 0x00, 0x46, 0x0d, 0x12, 0x34, 0x00, 0x01, 0xc0, 0xdb,
+        0xdc, 0xdd, 0x00, 0x01, 0x02, 0x03,
+    ];
+
+    /// Linux 的 slip 驱动发 `PING` 时线路上的字节：前后各一个 `END`
+    const PING_WIRE: [u8; 42] = [
+        0xc0, 0x45, 0x00, 0x00, 0x24, 0xdb, 0xdc, 0xdb, 0xdd, 0x40, 0x00, 0x40, 0x01, 0x65, 0xfb,
+        0x0a, 0x00, 0x00, 0x02, 0x0a, 0x00, 0x00, 0x01, 0x08, 0x00, 0x46, 0x0d, 0x12, 0x34, 0x00,
+        0x01, 0xdb, 0xdc, 0xdb, 0xdd, 0xdc, 0xdd, 0x00, 0x01, 0x02, 0x03, 0xc0,
+    ];
+
+    fn decode_all<const N: usize>(decoder: &mut Decoder<N>, stream: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        for &byte in stream {
+            if decoder.push(byte) {
+                frames.push(decoder.frame().to_vec());
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn ping_on_the_wire() {
+        let mut buf = [0; max_encoded_len(PING.len())];
+        let n = encode(&PING, &mut buf).unwrap();
+        assert_eq!(buf[..n], PING_WIRE);
+
+        let mut tx = MockTx::default();
+        block_on(write_frame(&mut tx, &PING)).unwrap();
+        assert_eq!(tx.0, PING_WIRE);
+
+        // 背靠背两个包，中间的 `END` 前后相连，拆在各种大小的 read 里
+        let stream = [&PING_WIRE[..], &PING_WIRE[..]].concat();
+        for size in 1..=stream.len() {
+            let mut reader =
+                FramedReader::new(MockRx::chunked(&stream, size), Decoder::<64>::new());
+            for _ in 0..2 {
+                assert_eq!(block_on(reader.next_frame()).unwrap(), PING);
+            }
+            assert_eq!(reader.framer().errors(), 0);
+        }
+    }
+
+    #[test]
+    fn noisy_stream() {
+        // 上电时的噪声（带一个坏转义）、一个超长帧、只在结尾有 `END` 的帧、正常帧
+        let mut stream = vec![0x01, ESC, 0x05, END];
+        stream.extend([0x07; 80]);
+        stream.push(END);
+        stream.extend([b'h', ESC, ESC_END, b'i', END]);
+        stream.extend_from_slice(&PING_WIRE);
+
+        let mut decoder = Decoder::<64>::new();
+        let frames = decode_all(&mut decoder, &stream);
+        assert_eq!(frames, [&b"h\xc0i"[..], &PING[..]]);
+        assert_eq!(decoder.errors(), 1);
+        assert_eq!(decoder.oversized(), 1);
+
+        // 帧末尾孤零零的 `ESC` 也算坏帧
+        assert!(decode_all(&mut decoder, &[b'a', ESC, END]).is_empty());
+        assert_eq!(decoder.errors(), 2);
+    }
+
+    #[test]
+    fn roundtrip() {
+        for len in 0..200 {
+            let data: Vec<u8> = (0..len)
+                .map(|i| [END, ESC, ESC_END, ESC_ESC, i as u8][i % 5])
+                .collect();
+            let mut buf = vec![0; max_encoded_len(len)];
+            let n = encode(&data, &mut buf).unwrap();
+
+            let mut tx = MockTx::default();
+            block_on(write_frame(&mut tx, &data)).unwrap();
+            assert_eq!(tx.0, buf[..n]);
+
+            let frames = decode_all(&mut Decoder::<200>::new(), &buf[..n]);
+            match len {
+                0 => assert!(frames.is_empty()),
+                _ => assert_eq!(frames, [data]),
+            }
+        }
+        assert_eq!(encode(&[END], &mut [0; 3]), Err(Error::BufferTooSmall));
+    }
+}
@@ -0,0 +1,72 @@
+//! 用 SLIP 把串口接进 `embassy-net`，串口线两端就能直接跑 IP。
+//!
+//! SLIP 没有链路层地址，所以设备是 `HardwareAddress::Ip`，协议栈要开 `medium-ip`。
+//! 上位机那边用 `slattach -p slip -s 115200 /dev/ttyACM0` 之类的命令建立 `sl0` 接口。
+
+use embassy_futures::join::join;
+use embassy_net_driver_channel as ch;
+use embassy_net_driver_channel::driver::{HardwareAddress, LinkState};
+use embedded_io_async::{Read, Write};
+
+use super::slip;
+use crate::usart::{FramedReader, Framer};
+
+/// RFC 1055 建议的 SLIP 包长度
+pub const MTU: usize = 1006;
+
+/// 收发各留几个包的缓冲
+const N_RX: usize = 2;
+const N_TX: usize = 2;
+
+pub type State = ch::State<MTU, N_RX, N_TX>;
+/// 交给 `embassy_net::new` 的设备
+pub type Device<'d> = ch::Device<'d, MTU>;
+
+/// 在串口和协议栈之间搬运数据包
+pub struct Runner<'d> {
+    ch: ch::Runner<'d, MTU>,
+}
+
+/// 创建 SLIP 网络设备。`state` 一般放在 `StaticCell` 里。
+pub fn new(state: &mut State) -> (Runner<'_>, Device<'_>) {
+    let (ch, device) = ch::new(state, HardwareAddress::Ip);
+    (Runner { ch }, device)
+}
+
+impl Runner<'_> {
+    /// 一直运行，不会返回。
+    ///
+    /// 串口读错误（比如溢出）只丢掉当前收到一半的包，交给 TCP 去重传。
+    pub async fn run<R: Read, W: Write>(self, rx: R, mut tx: W) -> ! {
+        let (state, mut rx_ch, mut tx_ch) = self.ch.split();
+        // 串口没有载波检测，一直认为链路是通的
+        state.set_link_state(LinkState::Up);
+
+        let mut reader = FramedReader::new(rx, slip::Decoder::<MTU>::new());
+
+        let rx_fut = async {
+            loop {
+                match reader.next_frame().await {
+                    Ok(packet) => {
+                        let buf = rx_ch.rx_buf().await;
+                        buf[..packet.len()].copy_from_slice(packet);
+                        rx_ch.rx_done(packet.len());
+                    }
+                    Err(_) => reader.framer_mut().reset(),
+                }
+            }
+        };
+
+        let tx_fut = async {
+            loop {
+                let packet = tx_ch.tx_buf().await;
+                // 写失败也只能丢掉这个包
+                let _ = slip::write_frame(&mut tx, packet).await;
+                tx_ch.tx_done();
+            }
+        };
+
+        join(rx_fut, tx_fut).await;
+        unreachable!()
+    }
+}
@@ -0,0 +1,150 @@
+//! 生产者和消费者之间带背压策略的队列。
+//!
+//! 原来接收任务直接 `send(...).await`，处理任务慢的时候接收任务就卡在那里，
+//! 这段时间串口来的数据悄悄丢掉了。现在每条流水线可以选择队列满了怎么办（[`Policy`]），
+//! 并且统计丢了多少、队列最多排到多长，方便判断该加大队列还是该让处理变快。
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::{Channel, TrySendError};
+
+/// 队列满了的时候怎么办
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Policy {
+    /// 等消费者腾出位置（原来的行为）。生产者自己要有缓冲，比如循环 DMA
+    Block,
+    /// 丢掉新来的
+    DropNewest,
+    /// 丢掉排得最久的，给新来的腾位置
+    DropOldest,
+    /// 丢掉新来的，并记下来让消费者告诉对端“忙”（见 [`Pipeline::take_busy`]）
+    SignalBusy,
+}
+
+/// [`Pipeline::send`] 的结果
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Sent {
+    Queued,
+    /// 新来的被丢掉了
+    DroppedNewest,
+    /// 排在最前面的被丢掉了，新来的进了队列
+    DroppedOldest,
+}
+
+/// 流水线统计
+#[derive(Clone, Copy, Default, Debug, defmt::Format)]
+pub struct PipelineStats {
+    /// 进了队列的
+    pub queued: u32,
+    /// 因为队列满丢掉的（新的旧的都算）
+    pub dropped: u32,
+    /// 队列最长的时候排了几个
+    pub high_water: usize,
+}
+
+struct Inner {
+    policy: Policy,
+    stats: PipelineStats,
+    /// 还没告诉对端的“忙”次数
+    busy: u32,
+}
+
+/// 深度为 `N` 的流水线，一般放在 `static` 里，生产者和消费者共享同一个引用
+pub struct Pipeline<T, const N: usize> {
+    channel: Channel<CriticalSectionRawMutex, T, N>,
+    inner: Mutex<CriticalSectionRawMutex, RefCell<Inner>>,
+}
+
+impl<T, const N: usize> Pipeline<T, N> {
+    pub const fn new(policy: Policy) -> Self {
+        Self {
+            channel: Channel::new(),
+            inner: Mutex::new(RefCell::new(Inner {
+                policy,
+                stats: PipelineStats {
+                    queued: 0,
+                    dropped: 0,
+                    high_water: 0,
+                },
+                busy: 0,
+            })),
+        }
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.inner.lock(|i| i.borrow().policy)
+    }
+
+    pub fn set_policy(&self, policy: Policy) {
+        self.inner.lock(|i| i.borrow_mut().policy = policy);
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        self.inner.lock(|i| i.borrow().stats)
+    }
+
+    /// 按当前策略把 `item` 放进队列。只有 [`Policy::Block`] 会等待
+    pub async fn send(&self, item: T) -> Sent {
+        let sent = match self.policy() {
+            Policy::Block => {
+                self.channel.send(item).await;
+                Sent::Queued
+            }
+            Policy::DropNewest | Policy::SignalBusy => match self.channel.try_send(item) {
+                Ok(()) => Sent::Queued,
+                Err(_) => Sent::DroppedNewest,
+            },
+            Policy::DropOldest => {
+                let mut item = item;
+                let mut sent = Sent::Queued;
+                // 消费者可能在两次尝试之间取走了一个，所以要循环
+                while let Err(TrySendError::Full(rejected)) = self.channel.try_send(item) {
+                    item = rejected;
+                    if self.channel.try_receive().is_ok() {
+                        sent = Sent::DroppedOldest;
+                    }
+                }
+                sent
+            }
+        };
+
+        let len = self.channel.len();
+        self.inner.lock(|i| {
+            let mut i = i.borrow_mut();
+            let busy = i.policy == Policy::SignalBusy;
+            let stats = &mut i.stats;
+            if sent != Sent::DroppedNewest {
+                stats.queued = stats.queued.wrapping_add(1);
+                stats.high_water = stats.high_water.max(len);
+            }
+            if sent != Sent::Queued {
+                stats.dropped = stats.dropped.wrapping_add(1);
+                if busy {
+                    i.busy = i.busy.wrapping_add(1);
+                }
+            }
+        });
+        sent
+    }
+
+    /// 取出排在最前面的
+    pub async fn receive(&self) -> T {
+        self.channel.receive().await
+    }
+
+    /// 取走并清零 [`Policy::SignalBusy`] 下丢掉的次数，消费者据此告诉对端刚才太忙了
+    pub fn take_busy(&self) -> u32 {
+        self.inner.lock(|i| core::mem::take(&mut i.borrow_mut().busy))
+    }
+
+    /// 队列里现在有几个
+    pub fn len(&self) -> usize {
+        self.channel.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channel.is_empty()
+    }
+}
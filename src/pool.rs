@@ -0,0 +1,161 @@
+//! 固定容量的帧缓冲池。
+//!
+//! 原来接收任务把数据拷进一个共享的 `Mutex<[u8; 64]>`，再通过通道只发一个长度过去，
+//! 处理任务来不及取的时候，下一帧就会把还没处理的数据覆盖掉。现在每一帧都是从池里借出的
+//! 一块独占缓冲区 [`Frame`]，整块通过通道交给处理任务，处理完丢掉（drop）就自动还回池里，
+//! 任务之间不再需要锁。
+//!
+//! 池里没有空闲缓冲区时 [`FramePool::alloc`] 会等待，`try_alloc` 则直接返回 `None`。
+
+use core::cell::{RefCell, UnsafeCell};
+use core::future::poll_fn;
+use core::ops::{Deref, DerefMut};
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::WakerRegistration;
+
+/// `N` 块、每块 `SIZE` 字节的缓冲池，`N` 最多 32。一般放在 `static` 里。
+pub struct FramePool<const SIZE: usize, const N: usize> {
+    bufs: [UnsafeCell<[u8; SIZE]>; N],
+    state: Mutex<CriticalSectionRawMutex, RefCell<State>>,
+}
+
+struct State {
+    /// 第 i 位为 1 表示第 i 块空闲
+    free: u32,
+    /// 等空闲缓冲区的任务
+    waker: WakerRegistration,
+}
+
+// 每块缓冲区同一时间只属于一个 `Frame`，由 `free` 位图保证
+unsafe impl<const SIZE: usize, const N: usize> Sync for FramePool<SIZE, N> {}
+
+impl<const SIZE: usize, const N: usize> FramePool<SIZE, N> {
+    pub const fn new() -> Self {
+        assert!(N > 0 && N <= 32);
+        Self {
+            bufs: [const { UnsafeCell::new([0; SIZE]) }; N],
+            state: Mutex::new(RefCell::new(State {
+                free: u32::MAX >> (32 - N),
+                waker: WakerRegistration::new(),
+            })),
+        }
+    }
+
+    /// 借一块空的缓冲区，没有就返回 `None`
+    pub fn try_alloc(&self) -> Option<Frame<'_, SIZE, N>> {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            if s.free == 0 {
+                return None;
+            }
+            let index = s.free.trailing_zeros() as usize;
+            s.free &= !(1 << index);
+            Some(Frame {
+                pool: self,
+                index,
+                len: 0,
+            })
+        })
+    }
+
+    /// 借一块空的缓冲区，没有就等别的任务还回来
+    pub async fn alloc(&self) -> Frame<'_, SIZE, N> {
+        poll_fn(|cx| match self.try_alloc() {
+            Some(frame) => Poll::Ready(frame),
+            None => {
+                self.state.lock(|s| s.borrow_mut().waker.register(cx.waker()));
+                // 注册之前可能刚好有缓冲区还回来
+                match self.try_alloc() {
+                    Some(frame) => Poll::Ready(frame),
+                    None => Poll::Pending,
+                }
+            }
+        })
+        .await
+    }
+
+    /// 现在有几块空闲
+    pub fn available(&self) -> usize {
+        self.state.lock(|s| s.borrow().free.count_ones() as usize)
+    }
+
+    fn release(&self, index: usize) {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.free |= 1 << index;
+            s.waker.wake();
+        });
+    }
+}
+
+impl<const SIZE: usize, const N: usize> Default for FramePool<SIZE, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 从池里借出的一块缓冲区，解引用得到已写入的 `len` 个字节。drop 时自动还回池里。
+pub struct Frame<'a, const SIZE: usize, const N: usize> {
+    pool: &'a FramePool<SIZE, N>,
+    index: usize,
+    len: usize,
+}
+
+impl<const SIZE: usize, const N: usize> Frame<'_, SIZE, N> {
+    pub const CAPACITY: usize = SIZE;
+
+    /// 追加数据，放不下的部分丢掉，返回实际写入的字节数
+    pub fn extend_from_slice(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(SIZE - self.len);
+        let start = self.len;
+        self.buffer_mut()[start..start + n].copy_from_slice(&data[..n]);
+        self.len += n;
+        n
+    }
+
+    /// 整块缓冲区，配合 [`set_len`](Self::set_len) 直接往里面写，比如作为 `read` 的目标
+    pub fn buffer_mut(&mut self) -> &mut [u8; SIZE] {
+        // 这一块现在只属于 self
+        unsafe { &mut *self.pool.bufs[self.index].get() }
+    }
+
+    /// 设置有效数据的长度，超过容量时截到容量
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.min(SIZE);
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<const SIZE: usize, const N: usize> Deref for Frame<'_, SIZE, N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let buf = unsafe { &*self.pool.bufs[self.index].get() };
+        &buf[..self.len]
+    }
+}
+
+impl<const SIZE: usize, const N: usize> DerefMut for Frame<'_, SIZE, N> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let len = self.len;
+        &mut self.buffer_mut()[..len]
+    }
+}
+
+impl<const SIZE: usize, const N: usize> Drop for Frame<'_, SIZE, N> {
+    fn drop(&mut self) {
+        self.pool.release(self.index);
+    }
+}
+
+impl<const SIZE: usize, const N: usize> defmt::Format for Frame<'_, SIZE, N> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=[u8]}", &self[..])
+    }
+}
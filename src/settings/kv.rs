@@ -0,0 +1,708 @@
+//! 日志式的键值存储，几个擦除扇区轮着用。
+//!
+//! STM32H7 一个扇区 128 KB，擦一次要一两秒、寿命只有一万次左右，每次保存都擦扇区太慢也太费。
+//! 这里只往当前扇区（活动扇区）末尾追加记录，改一个值就是再追加一条，读的时候以最后一条为准。
+//! 扇区写满了才整理：把每个键最新的值抄到下一个扇区，下一个扇区就成了活动扇区。
+//! 扇区按顺序轮着用，每个扇区擦的次数差不多。
+//!
+//! 扇区开头是 `magic: u32 | seq: u32`，后面单独一个写入单位放 `DONE` 标记，再往后是记录：
+//!
+//! ```text
+//! 键长: u8 | 类型: u8 | 值长: u16 | crc32: u32 | 键 | 值
+//! ```
+//!
+//! crc 算的是前四个字节加上键和值，整条记录按 flash 的写入单位补 `0xFF`，多字节都是小端。
+//!
+//! 掉电：
+//!
+//! - 追加到一半掉电，这条记录 CRC 不对。上电时发现日志末尾有写坏的东西，就整理一次，
+//!   新扇区里只有完整的记录，之前的值都还在。
+//! - 整理到一半掉电，新扇区还没写 `DONE`，上电时不算数，继续用旧扇区。
+//! - `DONE` 写完以后新旧两个扇区都有效，用 `seq` 大的那个。旧扇区不马上擦，下次轮到它时才擦。
+//!
+//! STM32H7 一次写一个 256 位的 flash 字，ECC 也按 flash 字算。写到一半掉电，这个字读出来
+//! 可能是双位 ECC 错误。扫描时读出错和 CRC 不对一样处理：扇区头或者 `DONE` 读不出来，
+//! 这个扇区就不算数；记录读不出来，日志就到这里为止，之后整理时不会再读它。
+//! 片上 flash 读到 ECC 错误默认会进 BusFault，板子上要用把它变成 `Err` 的驱动，
+//! 见 `board::EccFlash`。
+//!
+//! 读 flash 时按字节读，`READ_SIZE` 要是 1（片上 flash 是）。
+
+use embedded_storage::nor_flash::NorFlash;
+
+use super::store::{decode, encode, RECORD_SIZE};
+use super::{Loaded, Settings, Storage, StorageError};
+use crate::link::crc::crc32;
+
+/// 扇区开头的标记
+const MAGIC: u32 = u32::from_le_bytes(*b"KVLG");
+/// 扇区整理完成的标记
+const DONE: u32 = u32::from_le_bytes(*b"DONE");
+const SET: u8 = 1;
+const REMOVE: u8 = 2;
+/// 键长、类型、值长和 crc
+const HEADER_SIZE: usize = 8;
+/// 键最长多少字节
+pub const KEY_SIZE: usize = 32;
+/// 值最长多少字节
+pub const VALUE_SIZE: usize = 256;
+/// 最长的一条记录按 64 字节取整，写入单位不能超过 64 字节
+const BUF_SIZE: usize = 320;
+
+/// 挂载的结果
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Mounted {
+    /// 一个有效的扇区都没有，格式化成了空的
+    Formatted,
+    /// 正常
+    Clean,
+    /// 日志末尾有掉电写坏的记录，已经整理到下一个扇区
+    Recovered,
+}
+
+impl Mounted {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mounted::Formatted => "formatted",
+            Mounted::Clean => "clean",
+            Mounted::Recovered => "recovered from interrupted write",
+        }
+    }
+}
+
+/// 键值存储的错误
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum KvError {
+    /// 擦写 flash 出错
+    Flash,
+    /// 整理之后活动扇区还是放不下
+    Full,
+    KeyTooLong,
+    ValueTooLong,
+    /// 读值的缓冲区太小
+    BufferTooSmall,
+}
+
+impl KvError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KvError::Flash => "flash error",
+            KvError::Full => "storage full",
+            KvError::KeyTooLong => "key too long",
+            KvError::ValueTooLong => "value too long",
+            KvError::BufferTooSmall => "buffer too small",
+        }
+    }
+}
+
+/// 读到的一条记录，内容在读的时候给的缓冲区里
+#[derive(Clone, Copy)]
+struct Record {
+    kind: u8,
+    key_len: usize,
+    value_len: usize,
+    /// 补齐之后的长度
+    len: usize,
+}
+
+impl Record {
+    fn key<'a>(&self, buf: &'a [u8]) -> &'a [u8] {
+        &buf[HEADER_SIZE..HEADER_SIZE + self.key_len]
+    }
+
+    fn value<'a>(&self, buf: &'a [u8]) -> &'a [u8] {
+        let start = HEADER_SIZE + self.key_len;
+        &buf[start..start + self.value_len]
+    }
+}
+
+enum Scan {
+    Record(Record),
+    /// 后面没写过
+    End,
+    /// 记录不完整、CRC 不对或者读不出来
+    Torn,
+}
+
+/// 在 `flash` 的 `offset` 处（要对齐擦除扇区）连续的 `sectors` 个扇区上存键值
+pub struct LogStore<F> {
+    flash: F,
+    offset: u32,
+    sectors: u32,
+    /// 活动扇区
+    active: u32,
+    seq: u32,
+    /// 活动扇区里下一条记录写在哪（相对扇区开头）
+    pos: u32,
+}
+
+impl<F: NorFlash> LogStore<F> {
+    /// 找到活动扇区和日志末尾，末尾写坏了就整理一次，一个有效扇区都没有就格式化
+    pub fn mount(flash: F, offset: u32, sectors: u32) -> Result<(Self, Mounted), KvError> {
+        assert!(sectors >= 2, "need at least two sectors");
+        let mut store = Self {
+            flash,
+            offset,
+            sectors,
+            active: 0,
+            seq: 0,
+            pos: 0,
+        };
+
+        let mut newest = None;
+        for sector in 0..sectors {
+            match (store.sector_seq(sector)?, newest) {
+                (Some(seq), Some((_, best))) if seq <= best => {}
+                (Some(seq), _) => newest = Some((sector, seq)),
+                (None, _) => {}
+            }
+        }
+        let Some((active, seq)) = newest else {
+            store.start(0, 1)?;
+            store.commit(0)?;
+            store.seq = 1;
+            store.pos = Self::data_start();
+            return Ok((store, Mounted::Formatted));
+        };
+        store.active = active;
+        store.seq = seq;
+
+        let mut buf = [0; BUF_SIZE];
+        let mut pos = Self::data_start();
+        while let Scan::Record(r) = store.record(active, pos, &mut buf)? {
+            pos += r.len as u32;
+        }
+        store.pos = pos;
+        if store.erased_from(active, pos)? {
+            Ok((store, Mounted::Clean))
+        } else {
+            store.collect()?;
+            Ok((store, Mounted::Recovered))
+        }
+    }
+
+    pub fn flash(&self) -> &F {
+        &self.flash
+    }
+
+    /// 把 `key` 的值读到 `value` 里，返回值的长度，没有这个键就是 `None`
+    pub fn get(&mut self, key: &str, value: &mut [u8]) -> Result<Option<usize>, KvError> {
+        let mut buf = [0; BUF_SIZE];
+        let Some(r) = self.find(key.as_bytes(), &mut buf)? else {
+            return Ok(None);
+        };
+        let v = r.value(&buf);
+        value
+            .get_mut(..v.len())
+            .ok_or(KvError::BufferTooSmall)?
+            .copy_from_slice(v);
+        Ok(Some(v.len()))
+    }
+
+    /// 设置 `key` 的值，和原来一样就不写
+    pub fn set(&mut self, key: &str, value: &[u8]) -> Result<(), KvError> {
+        check(key, value)?;
+        let mut buf = [0; BUF_SIZE];
+        match self.find(key.as_bytes(), &mut buf)? {
+            Some(r) if r.value(&buf) == value => Ok(()),
+            _ => self.append(SET, key.as_bytes(), value),
+        }
+    }
+
+    /// 删掉 `key`，没有这个键也算成功
+    pub fn remove(&mut self, key: &str) -> Result<(), KvError> {
+        check(key, &[])?;
+        let mut buf = [0; BUF_SIZE];
+        match self.find(key.as_bytes(), &mut buf)? {
+            Some(_) => self.append(REMOVE, key.as_bytes(), &[]),
+            None => Ok(()),
+        }
+    }
+
+    /// 活动扇区里还能写多少字节
+    pub fn free(&self) -> u32 {
+        Self::sector_size() - self.pos
+    }
+
+    fn sector_size() -> u32 {
+        F::ERASE_SIZE as u32
+    }
+
+    fn align(len: usize) -> usize {
+        len.next_multiple_of(F::WRITE_SIZE)
+    }
+
+    /// `DONE` 标记在扇区里的位置
+    fn done_offset() -> u32 {
+        Self::align(8) as u32
+    }
+
+    /// 第一条记录在扇区里的位置
+    fn data_start() -> u32 {
+        Self::done_offset() + Self::align(4) as u32
+    }
+
+    fn base(&self, sector: u32) -> u32 {
+        self.offset + sector * Self::sector_size()
+    }
+
+    fn read(&mut self, sector: u32, pos: u32, buf: &mut [u8]) -> Result<(), KvError> {
+        let addr = self.base(sector) + pos;
+        self.flash.read(addr, buf).map_err(|_| KvError::Flash)
+    }
+
+    fn write(&mut self, sector: u32, pos: u32, buf: &[u8]) -> Result<(), KvError> {
+        let addr = self.base(sector) + pos;
+        self.flash.write(addr, buf).map_err(|_| KvError::Flash)
+    }
+
+    /// 整理完成的扇区的序号，没整理完、读不出来或者不是我们的扇区就是 `None`
+    fn sector_seq(&mut self, sector: u32) -> Result<Option<u32>, KvError> {
+        let mut header = [0; 8];
+        let mut done = [0; 4];
+        if self.read(sector, 0, &mut header).is_err()
+            || self.read(sector, Self::done_offset(), &mut done).is_err()
+        {
+            return Ok(None);
+        }
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let seq = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        Ok((magic == MAGIC && u32::from_le_bytes(done) == DONE).then_some(seq))
+    }
+
+    /// 从 `pos` 开始到扇区末尾是不是都没写过，读不出来也算写过
+    fn erased_from(&mut self, sector: u32, mut pos: u32) -> Result<bool, KvError> {
+        let mut buf = [0; 64];
+        while pos < Self::sector_size() {
+            let n = buf.len().min((Self::sector_size() - pos) as usize);
+            let erased = self.read(sector, pos, &mut buf[..n]).is_ok()
+                && buf[..n].iter().all(|&b| b == 0xFF);
+            if !erased {
+                return Ok(false);
+            }
+            pos += n as u32;
+        }
+        Ok(true)
+    }
+
+    /// 读 `pos` 处的一条记录，整条（包括补齐的部分）读到 `buf`
+    fn record(&mut self, sector: u32, pos: u32, buf: &mut [u8; BUF_SIZE]) -> Result<Scan, KvError> {
+        let room = (Self::sector_size() - pos) as usize;
+        if room < HEADER_SIZE {
+            return Ok(Scan::End);
+        }
+        if self.read(sector, pos, &mut buf[..HEADER_SIZE]).is_err() {
+            return Ok(Scan::Torn);
+        }
+        if buf[..HEADER_SIZE].iter().all(|&b| b == 0xFF) {
+            return Ok(Scan::End);
+        }
+
+        let key_len = usize::from(buf[0]);
+        let kind = buf[1];
+        let value_len = usize::from(u16::from_le_bytes([buf[2], buf[3]]));
+        let crc = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let end = HEADER_SIZE + key_len + value_len;
+        let len = Self::align(end);
+        if key_len > KEY_SIZE || value_len > VALUE_SIZE || !matches!(kind, SET | REMOVE) {
+            return Ok(Scan::Torn);
+        }
+        if len > room {
+            return Ok(Scan::Torn);
+        }
+        let body = self.read(sector, pos + HEADER_SIZE as u32, &mut buf[HEADER_SIZE..len]);
+        if body.is_err() || checksum(buf, end) != crc {
+            return Ok(Scan::Torn);
+        }
+        Ok(Scan::Record(Record {
+            kind,
+            key_len,
+            value_len,
+            len,
+        }))
+    }
+
+    /// 在活动扇区里找 `key` 最新的值，读到 `buf` 里。删掉了也是 `None`
+    fn find(&mut self, key: &[u8], buf: &mut [u8; BUF_SIZE]) -> Result<Option<Record>, KvError> {
+        let mut scratch = [0; BUF_SIZE];
+        let mut latest = None;
+        let mut pos = Self::data_start();
+        while pos < self.pos {
+            let Scan::Record(r) = self.record(self.active, pos, &mut scratch)? else {
+                break;
+            };
+            if r.key(&scratch) == key {
+                latest = (r.kind == SET).then_some(pos);
+            }
+            pos += r.len as u32;
+        }
+        match latest {
+            Some(pos) => match self.record(self.active, pos, buf)? {
+                Scan::Record(r) => Ok(Some(r)),
+                _ => Err(KvError::Flash),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// 活动扇区里 `from` 之后还有没有 `key` 的记录
+    fn superseded(&mut self, key: &[u8], mut from: u32) -> Result<bool, KvError> {
+        let mut buf = [0; BUF_SIZE];
+        while from < self.pos {
+            let Scan::Record(r) = self.record(self.active, from, &mut buf)? else {
+                break;
+            };
+            if r.key(&buf) == key {
+                return Ok(true);
+            }
+            from += r.len as u32;
+        }
+        Ok(false)
+    }
+
+    fn append(&mut self, kind: u8, key: &[u8], value: &[u8]) -> Result<(), KvError> {
+        let mut buf = [0xFF; BUF_SIZE];
+        let end = HEADER_SIZE + key.len() + value.len();
+        buf[0] = key.len() as u8;
+        buf[1] = kind;
+        buf[2..4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        buf[HEADER_SIZE..HEADER_SIZE + key.len()].copy_from_slice(key);
+        buf[HEADER_SIZE + key.len()..end].copy_from_slice(value);
+        let crc = checksum(&buf, end);
+        buf[4..8].copy_from_slice(&crc.to_le_bytes());
+
+        let len = Self::align(end);
+        if len as u32 > self.free() {
+            self.collect()?;
+            if len as u32 > self.free() {
+                return Err(KvError::Full);
+            }
+        }
+        if let Err(e) = self.write(self.active, self.pos, &buf[..len]) {
+            // 不知道写进去了多少，当成写满了，下次写之前先整理
+            self.pos = Self::sector_size();
+            return Err(e);
+        }
+        self.pos += len as u32;
+        Ok(())
+    }
+
+    /// 擦掉 `sector` 并写上扇区头，先不写 `DONE`
+    fn start(&mut self, sector: u32, seq: u32) -> Result<(), KvError> {
+        let base = self.base(sector);
+        self.flash
+            .erase(base, base + Self::sector_size())
+            .map_err(|_| KvError::Flash)?;
+        let mut header = [0xFF; 64];
+        header[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&seq.to_le_bytes());
+        self.write(sector, 0, &header[..Self::align(8)])
+    }
+
+    /// `sector` 写上 `DONE`，从这以后上电就认它了
+    fn commit(&mut self, sector: u32) -> Result<(), KvError> {
+        let mut done = [0xFF; 64];
+        done[..4].copy_from_slice(&DONE.to_le_bytes());
+        self.write(sector, Self::done_offset(), &done[..Self::align(4)])
+    }
+
+    /// 把活动扇区里每个键最新的值抄到下一个扇区，抄完才换过去。
+    /// 中途出错时旧扇区还是好的，下次整理会重新擦新扇区
+    fn collect(&mut self) -> Result<(), KvError> {
+        let to = (self.active + 1) % self.sectors;
+        let seq = self.seq.wrapping_add(1);
+        self.start(to, seq)?;
+
+        let mut buf = [0; BUF_SIZE];
+        let mut copied = Self::data_start();
+        let mut pos = Self::data_start();
+        while pos < self.pos {
+            let Scan::Record(r) = self.record(self.active, pos, &mut buf)? else {
+                break;
+            };
+            pos += r.len as u32;
+            if r.kind == SET && !self.superseded(r.key(&buf), pos)? {
+                self.write(to, copied, &buf[..r.len])?;
+                copied += r.len as u32;
+            }
+        }
+        self.commit(to)?;
+
+        self.active = to;
+        self.seq = seq;
+        self.pos = copied;
+        Ok(())
+    }
+}
+
+/// 记录前四个字节加上键和值的 crc，`end` 是键和值的末尾
+fn checksum(buf: &[u8; BUF_SIZE], end: usize) -> u32 {
+    let mut sum = [0; BUF_SIZE];
+    sum[..4].copy_from_slice(&buf[..4]);
+    sum[4..end - 4].copy_from_slice(&buf[HEADER_SIZE..end]);
+    crc32(&sum[..end - 4])
+}
+
+/// 检查键和值的长度
+fn check(key: &str, value: &[u8]) -> Result<(), KvError> {
+    if key.is_empty() || key.len() > KEY_SIZE {
+        return Err(KvError::KeyTooLong);
+    }
+    if value.len() > VALUE_SIZE {
+        return Err(KvError::ValueTooLong);
+    }
+    Ok(())
+}
+
+/// 设置整个存成一个键，格式和 [`FlashStore`](super::FlashStore) 的记录一样
+const SETTINGS_KEY: &str = "settings";
+
+impl<F: NorFlash> LogStore<F> {
+    /// 读出保存的设置，读不出来就用默认值
+    pub fn load_settings(&mut self) -> (Settings, Loaded) {
+        let mut buf = [0xFF; RECORD_SIZE];
+        match self.get(SETTINGS_KEY, &mut buf) {
+            Ok(Some(_)) => match decode(&buf) {
+                Ok(settings) => (settings, Loaded::Saved),
+                Err(loaded) => (Settings::default(), loaded),
+            },
+            Ok(None) => (Settings::default(), Loaded::Empty),
+            Err(_) => (Settings::default(), Loaded::Corrupt),
+        }
+    }
+}
+
+impl From<KvError> for StorageError {
+    fn from(e: KvError) -> Self {
+        match e {
+            KvError::Full | KvError::ValueTooLong => StorageError::TooLarge,
+            _ => StorageError::Flash,
+        }
+    }
+}
+
+impl<F: NorFlash> Storage for LogStore<F> {
+    fn save(&mut self, settings: &Settings) -> Result<(), StorageError> {
+        let mut buf = [0xFF; RECORD_SIZE];
+        let len = encode(settings, &mut buf)?;
+        Ok(self.set(SETTINGS_KEY, &buf[..len])?)
+    }
+
+    fn erase(&mut self) -> Result<(), StorageError> {
+        Ok(self.remove(SETTINGS_KEY)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::collections::HashMap;
+    use std::format;
+    use std::vec::Vec;
+
+    use embedded_storage::nor_flash::ReadNorFlash;
+
+    use super::*;
+    use crate::settings::{Key, MemFlash};
+
+    /// 写入单位和片上 flash 一样是 32 字节，三个 1 KB 的扇区
+    type Flash = MemFlash<3072, 32, 1024>;
+
+    fn get(store: &mut LogStore<Flash>, key: &str) -> Option<Vec<u8>> {
+        let mut buf = [0; VALUE_SIZE];
+        let len = store.get(key, &mut buf).unwrap()?;
+        Some(buf[..len].to_vec())
+    }
+
+    /// 复制一份 flash 的内容，像重新上电一样挂载
+    fn reboot(flash: &Flash) -> (LogStore<Flash>, Mounted) {
+        let mut copy = Flash::new();
+        copy.mem = flash.mem;
+        copy.torn = flash.torn;
+        LogStore::mount(copy, 0, 3).unwrap()
+    }
+
+    #[test]
+    fn set_get_remove() {
+        let (mut store, mounted) = LogStore::mount(Flash::new(), 0, 3).unwrap();
+        assert_eq!(mounted, Mounted::Formatted);
+        assert_eq!(get(&mut store, "a"), None);
+
+        store.set("a", b"1").unwrap();
+        store.set("b", b"hello").unwrap();
+        store.set("a", b"2").unwrap();
+        assert_eq!(get(&mut store, "a").unwrap(), b"2");
+        // 值没变不写
+        let free = store.free();
+        store.set("a", b"2").unwrap();
+        assert_eq!(store.free(), free);
+        store.remove("b").unwrap();
+        assert_eq!(get(&mut store, "b"), None);
+
+        assert_eq!(store.set("", b"x"), Err(KvError::KeyTooLong));
+        assert_eq!(
+            store.set("a", &[0; VALUE_SIZE + 1]),
+            Err(KvError::ValueTooLong)
+        );
+        assert_eq!(store.get("a", &mut []), Err(KvError::BufferTooSmall));
+
+        let (mut again, mounted) = reboot(store.flash());
+        assert_eq!(mounted, Mounted::Clean);
+        assert_eq!(get(&mut again, "a").unwrap(), b"2");
+        assert_eq!(get(&mut again, "b"), None);
+    }
+
+    #[test]
+    fn sectors_rotate() {
+        let (mut store, _) = LogStore::mount(Flash::new(), 0, 3).unwrap();
+        for i in 0..300u32 {
+            store.set("a", &i.to_le_bytes()).unwrap();
+            store.set("c", &[i as u8; 40]).unwrap();
+        }
+        assert_eq!(get(&mut store, "a").unwrap(), 299u32.to_le_bytes());
+        // 每次整理擦一个扇区，不是每次写都擦
+        let erases = store.flash().erases;
+        assert!(erases > 20 && erases < 300, "{}", erases);
+
+        let (mut again, _) = reboot(store.flash());
+        assert_eq!(get(&mut again, "c").unwrap(), [43; 40]);
+    }
+
+    #[test]
+    fn full() {
+        let (mut store, _) = LogStore::mount(Flash::new(), 0, 2).unwrap();
+        let mut n = 0;
+        let err = loop {
+            match store.set(&format!("k{}", n), &[1; 200]) {
+                Ok(()) => n += 1,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(err, KvError::Full);
+        assert_eq!(n, 4);
+        for i in 0..n {
+            assert_eq!(get(&mut store, &format!("k{}", i)).unwrap(), [1; 200]);
+        }
+    }
+
+    #[test]
+    fn torn_word_reads_as_ecc_error() {
+        let (mut store, _) = LogStore::mount(Flash::new(), 0, 3).unwrap();
+        store.set("a", b"1").unwrap();
+        let mut flash = Flash::new();
+        flash.mem = store.flash().mem;
+        // 第二条记录只写进去 5 个字节，读这个 flash 字会出错
+        let pos = LogStore::<Flash>::data_start() + 32;
+        flash.power_fail = Some(5);
+        flash.write(pos, &[0; 32]).unwrap_err();
+        assert_eq!(flash.torn, Some(pos as usize));
+        assert!(flash.read(pos, &mut [0; 8]).is_err());
+
+        let (mut again, mounted) = reboot(&flash);
+        assert_eq!(mounted, Mounted::Recovered);
+        assert_eq!(get(&mut again, "a").unwrap(), b"1");
+        // 整理到了下一个扇区，写坏的那个扇区下次轮到时才擦
+        assert_eq!(again.flash().torn, Some(pos as usize));
+        again.set("a", b"2").unwrap();
+        assert_eq!(reboot(again.flash()).1, Mounted::Clean);
+    }
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Set(&'static str, Vec<u8>),
+        Remove(&'static str),
+    }
+
+    /// 会整理好几次的一串操作
+    fn script() -> Vec<Op> {
+        let mut ops = Vec::new();
+        for i in 0..40u8 {
+            let key = ["x", "y", "z"][usize::from(i) % 3];
+            ops.push(Op::Set(key, std::vec![i; 20 + usize::from(i) * 7 % 60]));
+            if i % 7 == 6 {
+                ops.push(Op::Remove("y"));
+            }
+        }
+        ops
+    }
+
+    fn run(store: &mut LogStore<Flash>, op: &Op) -> Result<(), KvError> {
+        match op {
+            Op::Set(key, value) => store.set(key, value),
+            Op::Remove(key) => store.remove(key),
+        }
+    }
+
+    #[test]
+    fn power_fail_everywhere() {
+        // 先数一下整串操作一共要擦写多少字节
+        let mut flash = Flash::new();
+        flash.power_fail = Some(usize::MAX);
+        let (mut store, _) = LogStore::mount(flash, 0, 3).unwrap();
+        for op in script() {
+            run(&mut store, &op).unwrap();
+        }
+        let total = usize::MAX - store.flash().power_fail.unwrap();
+
+        let mut recovered = 0;
+        let mut torn = 0;
+        for n in 0..total {
+            let mut flash = Flash::new();
+            flash.power_fail = Some(n);
+            // 格式化的时候就掉电了，下次上电重新格式化
+            let Ok((mut store, _)) = LogStore::mount(flash, 0, 3) else {
+                continue;
+            };
+            let mut model = HashMap::new();
+            let mut pending = None;
+            for op in script() {
+                if run(&mut store, &op).is_err() {
+                    pending = Some(op);
+                    break;
+                }
+                match op {
+                    Op::Set(key, value) => model.insert(key, Some(value)),
+                    Op::Remove(key) => model.insert(key, None),
+                };
+            }
+            torn += usize::from(store.flash().torn.is_some());
+
+            let (mut again, mounted) = reboot(store.flash());
+            recovered += usize::from(mounted == Mounted::Recovered);
+            // 每个键要么是掉电前的值，要么是掉电时正在写的值
+            for key in ["x", "y", "z"] {
+                let got = get(&mut again, key);
+                let old = model.get(key).cloned().flatten();
+                let new = match &pending {
+                    Some(Op::Set(k, value)) if *k == key => Some(value.clone()),
+                    Some(Op::Remove(k)) if *k == key => None,
+                    _ => old.clone(),
+                };
+                assert!(got == old || got == new, "n={} {}: {:?}", n, key, got);
+            }
+            // 恢复之后还能接着用
+            again.set("x", b"after").unwrap();
+            let (mut after, mounted) = reboot(again.flash());
+            assert_eq!(mounted, Mounted::Clean, "n={}", n);
+            assert_eq!(get(&mut after, "x").unwrap(), b"after");
+        }
+        assert!(recovered > 100, "{}", recovered);
+        assert!(torn > 100, "{}", torn);
+    }
+
+    #[test]
+    fn settings() {
+        let (mut store, _) = LogStore::mount(Flash::new(), 0, 3).unwrap();
+        assert_eq!(store.load_settings(), (Settings::default(), Loaded::Empty));
+
+        let mut settings = Settings::default();
+        settings.set(Key::HelloText, "yo").unwrap();
+        store.save(&settings).unwrap();
+        let (mut again, _) = reboot(store.flash());
+        assert_eq!(again.load_settings(), (settings, Loaded::Saved));
+
+        again.erase().unwrap();
+        assert_eq!(again.load_settings().1, Loaded::Empty);
+    }
+}
@@ -0,0 +1,166 @@
+//! 放在内存里的假 flash，主机上测试设置的读写用。
+//!
+//! 和片上 flash 一样只能按写入单位对齐写、按扇区擦，写之前那块必须是擦过的（全 `0xFF`），
+//! 否则返回错误。STM32H7 的 flash 带 ECC，同一个 flash 字也只能写一次。
+//!
+//! 设了 [`MemFlash::power_fail`] 就会在写到那么多字节的时候“掉电”：这次写只写进去前面一部分，
+//! 擦除只擦了一部分，之后的擦写都返回 [`MemFlashError::PowerLoss`]，用来测试掉电恢复。
+//! 写到一半的那个 flash 字记在 [`MemFlash::torn`] 里，读到它返回 [`MemFlashError::Ecc`]，
+//! 和片上 flash 上双位 ECC 错误一样，擦掉之后才能再读。
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// `SIZE` 字节的假 flash，写入单位 `WRITE` 字节，擦除扇区 `ERASE` 字节
+pub struct MemFlash<const SIZE: usize, const WRITE: usize = 4, const ERASE: usize = 1024> {
+    pub mem: [u8; SIZE],
+    /// 一共擦过几个扇区
+    pub erases: u32,
+    /// 还能写多少字节就掉电，擦除一个扇区按扇区大小算
+    pub power_fail: Option<usize>,
+    /// 掉电时正在写的 flash 字的地址
+    pub torn: Option<usize>,
+}
+
+impl<const SIZE: usize, const WRITE: usize, const ERASE: usize> MemFlash<SIZE, WRITE, ERASE> {
+    /// 全部是擦过的状态
+    pub const fn new() -> Self {
+        Self {
+            mem: [0xFF; SIZE],
+            erases: 0,
+            power_fail: None,
+            torn: None,
+        }
+    }
+}
+
+impl<const SIZE: usize, const WRITE: usize, const ERASE: usize> Default
+    for MemFlash<SIZE, WRITE, ERASE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 假 flash 的错误
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum MemFlashError {
+    NotAligned,
+    OutOfBounds,
+    /// 写到了没擦过的地方
+    NotErased,
+    /// 模拟的掉电，见 [`MemFlash::power_fail`]
+    PowerLoss,
+    /// 读到了写到一半的 flash 字
+    Ecc,
+}
+
+impl NorFlashError for MemFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            MemFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            MemFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            MemFlashError::NotErased | MemFlashError::PowerLoss | MemFlashError::Ecc => {
+                NorFlashErrorKind::Other
+            }
+        }
+    }
+}
+
+impl<const SIZE: usize, const WRITE: usize, const ERASE: usize> MemFlash<SIZE, WRITE, ERASE> {
+    fn range(&self, from: u32, len: usize, align: usize) -> Result<(usize, usize), MemFlashError> {
+        let from = from as usize;
+        if !from.is_multiple_of(align) || !len.is_multiple_of(align) {
+            return Err(MemFlashError::NotAligned);
+        }
+        match from.checked_add(len) {
+            Some(to) if to <= SIZE => Ok((from, to)),
+            _ => Err(MemFlashError::OutOfBounds),
+        }
+    }
+
+    /// 这次要动 `len` 个字节，返回掉电之前来得及动的字节数
+    fn budget(&mut self, len: usize) -> usize {
+        match &mut self.power_fail {
+            Some(left) => {
+                let done = len.min(*left);
+                *left -= done;
+                done
+            }
+            None => len,
+        }
+    }
+}
+
+impl<const SIZE: usize, const WRITE: usize, const ERASE: usize> ErrorType
+    for MemFlash<SIZE, WRITE, ERASE>
+{
+    type Error = MemFlashError;
+}
+
+impl<const SIZE: usize, const WRITE: usize, const ERASE: usize> ReadNorFlash
+    for MemFlash<SIZE, WRITE, ERASE>
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), MemFlashError> {
+        let (from, to) = self.range(offset, bytes.len(), 1)?;
+        if self
+            .torn
+            .is_some_and(|word| word < to && from < word + WRITE)
+        {
+            return Err(MemFlashError::Ecc);
+        }
+        bytes.copy_from_slice(&self.mem[from..to]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize, const WRITE: usize, const ERASE: usize> NorFlash
+    for MemFlash<SIZE, WRITE, ERASE>
+{
+    const WRITE_SIZE: usize = WRITE;
+    const ERASE_SIZE: usize = ERASE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), MemFlashError> {
+        let len = (to as usize)
+            .checked_sub(from as usize)
+            .ok_or(MemFlashError::OutOfBounds)?;
+        let (from, to) = self.range(from, len, ERASE)?;
+        let done = self.budget(len);
+        self.mem[from..from + done].fill(0xFF);
+        if self
+            .torn
+            .is_some_and(|word| from <= word && word < from + done)
+        {
+            self.torn = None;
+        }
+        self.erases += done.div_ceil(ERASE) as u32;
+        if from + done == to {
+            Ok(())
+        } else {
+            Err(MemFlashError::PowerLoss)
+        }
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), MemFlashError> {
+        let (from, to) = self.range(offset, bytes.len(), WRITE)?;
+        if self.mem[from..to].iter().any(|&b| b != 0xFF) {
+            return Err(MemFlashError::NotErased);
+        }
+        let done = self.budget(bytes.len());
+        self.mem[from..from + done].copy_from_slice(&bytes[..done]);
+        if from + done == to {
+            return Ok(());
+        }
+        if !done.is_multiple_of(WRITE) {
+            self.torn = Some(from + done / WRITE * WRITE);
+        }
+        Err(MemFlashError::PowerLoss)
+    }
+}
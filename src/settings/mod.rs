@@ -0,0 +1,290 @@
+//! 掉电不丢的设置。
+//!
+//! 每一项设置是 [`Settings`] 里的一个字段，在 [`Key`] 里有一个名字（比如 `uart.baud`），
+//! shell 的 `config` 命令按名字读写。改过的值先只在内存里，`config save` 才通过 [`Storage`]
+//! 写到 flash；上电时 [`FlashStore::load`] 或者 [`LogStore::load_settings`] 读回来，
+//! 读不出来或者 CRC 不对就用默认值。
+//!
+//! 存储格式见 [`store`]，格式改了怎么升级旧记录见 [`schema`]。板子上设置整条存进 [`LogStore`]（见 [`kv`]），几个扇区轮着写，
+//! 不用每次保存都擦扇区。主机上测试时可以用 [`MemFlash`] 代替片上 flash。
+
+pub mod kv;
+pub mod mem;
+pub mod schema;
+pub mod store;
+
+use core::cell::RefCell;
+use core::fmt;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::mutex::Mutex as AsyncMutex;
+use heapless::String;
+
+pub use self::kv::{KvError, LogStore, Mounted};
+pub use self::mem::MemFlash;
+pub use self::store::{FlashStore, Loaded, StorageError};
+use crate::usart::line::{LineConfig, STANDARD_BAUDRATES};
+
+/// 字符串设置最长多少字节
+pub const TEXT_SIZE: usize = 32;
+
+/// LED 闪烁的快慢
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum LedMode {
+    Slow,
+    Medium,
+    Fast,
+    Off,
+}
+
+impl LedMode {
+    pub const ALL: [LedMode; 4] = [LedMode::Slow, LedMode::Medium, LedMode::Fast, LedMode::Off];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.as_str() == s)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedMode::Slow => "slow",
+            LedMode::Medium => "medium",
+            LedMode::Fast => "fast",
+            LedMode::Off => "off",
+        }
+    }
+}
+
+/// 所有设置
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Settings {
+    /// 调试串口上电时的波特率，只能是 [`STANDARD_BAUDRATES`] 里的
+    pub baudrate: u32,
+    /// `blink` 上电时的 LED 模式
+    pub led_mode: LedMode,
+    /// `hello` 的回答
+    pub greeting: String<TEXT_SIZE>,
+    /// `hello all` 的回答
+    pub greeting_all: String<TEXT_SIZE>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            baudrate: LineConfig::DEFAULT.baudrate,
+            led_mode: LedMode::Slow,
+            greeting: text("你好！"),
+            greeting_all: text("您们好！"),
+        }
+    }
+}
+
+fn text(s: &str) -> String<TEXT_SIZE> {
+    let mut t = String::new();
+    let _ = t.push_str(s);
+    t
+}
+
+/// 设置项的名字
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Key {
+    UartBaud,
+    LedMode,
+    HelloText,
+    HelloAll,
+}
+
+impl Key {
+    /// `config list` 按这个顺序列出
+    pub const ALL: [Key; 4] = [Key::UartBaud, Key::LedMode, Key::HelloText, Key::HelloAll];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Key::UartBaud => "uart.baud",
+            Key::LedMode => "led.mode",
+            Key::HelloText => "hello.text",
+            Key::HelloAll => "hello.all",
+        }
+    }
+
+    /// 存到 flash 里的编号，已经用过的不能改也不能换给别的设置
+    pub fn id(self) -> u8 {
+        match self {
+            Key::UartBaud => 1,
+            Key::LedMode => 2,
+            Key::HelloText => 3,
+            Key::HelloAll => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.id() == id)
+    }
+
+    /// 可以取的值，只有几种取值的设置才有，用来补全
+    pub fn choices(self) -> &'static [&'static str] {
+        match self {
+            Key::LedMode => &["slow", "medium", "fast", "off"],
+            _ => &[],
+        }
+    }
+}
+
+/// 一项设置的值
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Value<'a> {
+    Uint(u32),
+    Str(&'a str),
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Uint(n) => write!(f, "{}", n),
+            Value::Str(s) => f.write_str(s),
+        }
+    }
+}
+
+/// 设置的值不对
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum SetError {
+    Invalid,
+    TooLong,
+}
+
+impl SetError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SetError::Invalid => "invalid value",
+            SetError::TooLong => "value too long",
+        }
+    }
+}
+
+impl Settings {
+    pub fn get(&self, key: Key) -> Value<'_> {
+        match key {
+            Key::UartBaud => Value::Uint(self.baudrate),
+            Key::LedMode => Value::Str(self.led_mode.as_str()),
+            Key::HelloText => Value::Str(&self.greeting),
+            Key::HelloAll => Value::Str(&self.greeting_all),
+        }
+    }
+
+    /// 按文本设置一项，检查不通过时不改
+    pub fn set(&mut self, key: Key, value: &str) -> Result<(), SetError> {
+        match key {
+            Key::UartBaud => {
+                // 存下来的值上电时直接拿来开串口，只收标准波特率
+                let baudrate = value.parse().map_err(|_| SetError::Invalid)?;
+                if !STANDARD_BAUDRATES.contains(&baudrate) {
+                    return Err(SetError::Invalid);
+                }
+                self.baudrate = baudrate;
+            }
+            Key::LedMode => self.led_mode = LedMode::parse(value).ok_or(SetError::Invalid)?,
+            Key::HelloText => {
+                self.greeting = String::try_from(value).map_err(|_| SetError::TooLong)?
+            }
+            Key::HelloAll => {
+                self.greeting_all = String::try_from(value).map_err(|_| SetError::TooLong)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 把设置写到 flash 的接口，不用知道 flash 的具体类型
+pub trait Storage {
+    fn save(&mut self, settings: &Settings) -> Result<(), StorageError>;
+    /// 擦掉存着的设置，下次上电用默认值
+    fn erase(&mut self) -> Result<(), StorageError>;
+}
+
+static CURRENT: Mutex<CriticalSectionRawMutex, RefCell<Option<Settings>>> =
+    Mutex::new(RefCell::new(None));
+
+type Installed = Option<&'static mut (dyn Storage + Send)>;
+
+// 擦写 flash 要很久，不能放在临界区里
+static STORAGE: AsyncMutex<CriticalSectionRawMutex, Installed> = AsyncMutex::new(None);
+
+/// 上电时读出来的设置和保存它的地方交给 shell
+pub async fn install(settings: Settings, storage: &'static mut (dyn Storage + Send)) {
+    CURRENT.lock(|c| *c.borrow_mut() = Some(settings));
+    *STORAGE.lock().await = Some(storage);
+}
+
+/// 读写当前的设置，没有 [`install`] 过就是默认值
+pub fn with<R>(f: impl FnOnce(&mut Settings) -> R) -> R {
+    CURRENT.lock(|c| f(c.borrow_mut().get_or_insert_with(Settings::default)))
+}
+
+/// 把当前的设置写到 flash
+pub async fn save() -> Result<(), StorageError> {
+    let settings = with(|s| s.clone());
+    match STORAGE.lock().await.as_deref_mut() {
+        Some(storage) => storage.save(&settings),
+        None => Err(StorageError::NotInstalled),
+    }
+}
+
+/// 恢复默认值并擦掉 flash 里存着的设置
+pub async fn reset() -> Result<(), StorageError> {
+    with(|s| *s = Settings::default());
+    match STORAGE.lock().await.as_deref_mut() {
+        Some(storage) => storage.erase(),
+        None => Err(StorageError::NotInstalled),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn set_checks_values() {
+        let mut settings = Settings::default();
+        settings.set(Key::UartBaud, "9600").unwrap();
+        settings.set(Key::LedMode, "fast").unwrap();
+        settings.set(Key::HelloText, "hi there").unwrap();
+        assert_eq!(settings.get(Key::UartBaud), Value::Uint(9600));
+        assert_eq!(settings.get(Key::LedMode).to_string(), "fast");
+        assert_eq!(settings.get(Key::HelloText), Value::Str("hi there"));
+
+        // 不是标准波特率的存下来上电时可能开不了串口
+        for bad in ["abc", "0", "12345", "-9600", "4294967296"] {
+            assert_eq!(
+                settings.set(Key::UartBaud, bad),
+                Err(SetError::Invalid),
+                "{}",
+                bad
+            );
+        }
+        assert_eq!(settings.set(Key::LedMode, "blinky"), Err(SetError::Invalid));
+        assert_eq!(
+            settings.set(Key::HelloAll, &"x".repeat(TEXT_SIZE + 1)),
+            Err(SetError::TooLong)
+        );
+        assert_eq!(settings.baudrate, 9600);
+        assert_eq!(settings.greeting_all, Settings::default().greeting_all);
+    }
+
+    #[test]
+    fn keys() {
+        for key in Key::ALL {
+            assert_eq!(Key::parse(key.name()), Some(key));
+            assert_eq!(Key::from_id(key.id()), Some(key));
+        }
+        assert_eq!(Key::parse("uart"), None);
+    }
+}
@@ -0,0 +1,297 @@
+//! 设置记录的版本和迁移。
+//!
+//! 记录头里存着写它的固件用的格式版本（见 [`store`](super::store)）。上电读到旧版本的记录时，
+//! 按顺序跑 [`MIGRATIONS`] 里的迁移函数，一步一步升级到 [`VERSION`] 再解析；
+//! 比 [`VERSION`] 新的记录（固件降级了）不认，用默认值。
+//!
+//! 改了条目的格式（比如换了值的编码、拆分或合并设置项）就在 [`MIGRATIONS`] 末尾加一个函数，
+//! 版本号自动加一。只是加减设置项不用迁移，不认识的编号本来就会跳过。
+//!
+//! 版本历史：
+//!
+//! - 0：所有值都按文本存。
+//! - 1：数字按 `u32` 小端存。
+
+use heapless::Vec;
+
+use super::store::RECORD_SIZE;
+
+/// 把上一个版本的条目原地改成下一个版本
+pub type Migration = fn(&mut Entries) -> Result<(), MigrateError>;
+
+/// 第 n 个函数把版本 n 升级到版本 n + 1，只能往后加，不能改已有的
+pub const MIGRATIONS: &[Migration] = &[numbers_as_u32];
+
+/// 现在写的记录的版本
+pub const VERSION: u16 = MIGRATIONS.len() as u16;
+
+/// 迁移失败的原因
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum MigrateError {
+    /// 记录是更新的固件写的
+    TooNew,
+    /// 条目格式不对
+    Malformed,
+    /// 迁移之后放不下
+    TooLarge,
+}
+
+/// 把版本 `version` 的条目升级到 [`VERSION`]
+pub fn migrate(entries: &mut Entries, version: u16) -> Result<(), MigrateError> {
+    let from = usize::from(version);
+    let steps = MIGRATIONS.get(from..).ok_or(MigrateError::TooNew)?;
+    steps.iter().try_for_each(|step| step(entries))
+}
+
+/// 一条记录里的条目：`编号: u8 | 长度: u8 | 值`，迁移时在这上面增删改
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Entries {
+    buf: Vec<u8, RECORD_SIZE>,
+}
+
+impl Entries {
+    pub const fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    /// 检查格式，不对就是 `Malformed`
+    pub fn parse(bytes: &[u8]) -> Result<Self, MigrateError> {
+        let buf = Vec::from_slice(bytes).map_err(|_| MigrateError::TooLarge)?;
+        let entries = Self { buf };
+        let mut rest = bytes;
+        while let [_, len, tail @ ..] = rest {
+            rest = tail
+                .get(usize::from(*len)..)
+                .ok_or(MigrateError::Malformed)?;
+        }
+        match rest {
+            [] => Ok(entries),
+            _ => Err(MigrateError::Malformed),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// 按顺序列出 `(编号, 值)`
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        let mut rest = &self.buf[..];
+        core::iter::from_fn(move || {
+            let [id, len, tail @ ..] = rest else {
+                return None;
+            };
+            let (value, tail) = tail.split_at(usize::from(*len));
+            rest = tail;
+            Some((*id, value))
+        })
+    }
+
+    pub fn get(&self, id: u8) -> Option<&[u8]> {
+        self.iter().find(|&(i, _)| i == id).map(|(_, v)| v)
+    }
+
+    /// 改一个条目的值，位置不变；没有这个编号就加在最后
+    pub fn set(&mut self, id: u8, value: &[u8]) -> Result<(), MigrateError> {
+        let mut out = Self::new();
+        let mut added = false;
+        for (i, old) in self.iter() {
+            if i != id {
+                out.push(i, old)?;
+            } else if !added {
+                out.push(id, value)?;
+                added = true;
+            }
+        }
+        if !added {
+            out.push(id, value)?;
+        }
+        *self = out;
+        Ok(())
+    }
+
+    fn push(&mut self, id: u8, value: &[u8]) -> Result<(), MigrateError> {
+        let len = u8::try_from(value.len()).map_err(|_| MigrateError::TooLarge)?;
+        self.buf
+            .extend_from_slice(&[id, len])
+            .and_then(|()| self.buf.extend_from_slice(value))
+            .map_err(|_| MigrateError::TooLarge)
+    }
+
+    pub fn remove(&mut self, id: u8) {
+        let mut start = 0;
+        let mut found = None;
+        for (i, value) in self.iter() {
+            let end = start + 2 + value.len();
+            if i == id {
+                found = Some((start, end));
+                break;
+            }
+            start = end;
+        }
+        if let Some((start, end)) = found {
+            self.buf.copy_within(end.., start);
+            self.buf.truncate(self.buf.len() - (end - start));
+        }
+    }
+}
+
+impl Default for Entries {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 0 → 1：数字设置从文本改成 `u32` 小端。这一版只有 `uart.baud`（编号 1）是数字
+fn numbers_as_u32(entries: &mut Entries) -> Result<(), MigrateError> {
+    const UART_BAUD: u8 = 1;
+    let number = entries
+        .get(UART_BAUD)
+        .and_then(|text| core::str::from_utf8(text).ok())
+        .and_then(|text| text.parse::<u32>().ok());
+    match number {
+        Some(n) => entries.set(UART_BAUD, &n.to_le_bytes()),
+        // 本来就解析不了的值迁移过去也没用，去掉之后读的时候用默认值
+        None => {
+            entries.remove(UART_BAUD);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::link::crc::crc32;
+    use crate::settings::{FlashStore, Key, Loaded, MemFlash, Settings, Storage};
+
+    type Flash = MemFlash<1024, 4, 1024>;
+
+    fn layout(list: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (id, value) in list {
+            out.extend([*id, value.len() as u8]);
+            out.extend_from_slice(value);
+        }
+        out
+    }
+
+    /// 每个版本的固件存下的同一份设置，下标就是版本号。改了格式要在这里加一个
+    fn layouts() -> Vec<Vec<u8>> {
+        std::vec![
+            layout(&[(1, b"9600"), (2, b"fast"), (3, b"hi"), (9, b"future")]),
+            layout(&[
+                (1, &9600u32.to_le_bytes()),
+                (2, b"fast"),
+                (3, b"hi"),
+                (9, b"future")
+            ]),
+        ]
+    }
+
+    /// 上面那份设置读出来应该是什么样
+    fn expected() -> Settings {
+        let mut settings = Settings::default();
+        settings.set(Key::UartBaud, "9600").unwrap();
+        settings.set(Key::LedMode, "fast").unwrap();
+        settings.set(Key::HelloText, "hi").unwrap();
+        settings
+    }
+
+    /// 按 `version` 版本的格式拼一条完整的记录
+    fn record(version: u16, entries: &[u8]) -> Vec<u8> {
+        let mut out = Vec::from(*b"SETS");
+        out.extend((entries.len() as u16).to_le_bytes());
+        out.extend(version.to_le_bytes());
+        out.extend_from_slice(entries);
+        out.extend(crc32(&out).to_le_bytes());
+        out
+    }
+
+    fn load(record: &[u8]) -> (Settings, Loaded) {
+        let mut flash = Flash::new();
+        flash.mem[..record.len()].copy_from_slice(record);
+        FlashStore::new(flash, 0).load()
+    }
+
+    #[test]
+    fn every_version_pair() {
+        let layouts = layouts();
+        assert_eq!(layouts.len(), usize::from(VERSION) + 1);
+        for from in 0..layouts.len() {
+            for to in from..layouts.len() {
+                let mut entries = Entries::parse(&layouts[from]).unwrap();
+                for step in &MIGRATIONS[from..to] {
+                    step(&mut entries).unwrap();
+                }
+                assert_eq!(entries.as_bytes(), &layouts[to][..], "{} -> {}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn every_old_layout_loads() {
+        for (version, layout) in layouts().iter().enumerate() {
+            let saved = record(version as u16, layout);
+            assert_eq!(
+                load(&saved),
+                (expected(), Loaded::Saved),
+                "version {}",
+                version
+            );
+        }
+
+        // 保存的总是最新版本
+        let mut store = FlashStore::new(Flash::new(), 0);
+        store.save(&expected()).unwrap();
+        let mem = &store.flash().mem;
+        assert_eq!(u16::from_le_bytes([mem[6], mem[7]]), VERSION);
+    }
+
+    #[test]
+    fn unparsable_number_is_dropped() {
+        let mut entries = Entries::parse(&layout(&[(1, b"fast"), (3, b"hi")])).unwrap();
+        migrate(&mut entries, 0).unwrap();
+        assert_eq!(entries.as_bytes(), &layout(&[(3, b"hi")])[..]);
+
+        let (settings, loaded) = load(&record(0, &layout(&[(1, b"x"), (3, b"hi")])));
+        assert_eq!(loaded, Loaded::Saved);
+        assert_eq!(settings.baudrate, Settings::default().baudrate);
+        assert_eq!(settings.greeting, "hi");
+    }
+
+    #[test]
+    fn too_new_or_malformed() {
+        let layouts = layouts();
+        let latest = &layouts[usize::from(VERSION)];
+        assert_eq!(
+            migrate(&mut Entries::new(), VERSION + 1),
+            Err(MigrateError::TooNew)
+        );
+        assert_eq!(
+            load(&record(VERSION + 1, latest)),
+            (Settings::default(), Loaded::TooNew)
+        );
+
+        assert_eq!(Entries::parse(&[1, 5, 0]), Err(MigrateError::Malformed));
+        assert_eq!(Entries::parse(&[1]), Err(MigrateError::Malformed));
+        assert_eq!(load(&record(0, &[1, 5, 0])).1, Loaded::Corrupt);
+    }
+
+    #[test]
+    fn entries_edit_in_place() {
+        let mut entries = Entries::new();
+        entries.set(1, b"a").unwrap();
+        entries.set(2, b"bb").unwrap();
+        entries.set(1, b"ccc").unwrap();
+        assert_eq!(entries.as_bytes(), &layout(&[(1, b"ccc"), (2, b"bb")])[..]);
+        entries.remove(1);
+        entries.remove(7);
+        assert_eq!(entries.as_bytes(), &layout(&[(2, b"bb")])[..]);
+        assert_eq!(entries.set(3, &[0; 256]), Err(MigrateError::TooLarge));
+    }
+}
@@ -0,0 +1,277 @@
+//! 设置在 flash 里的格式。
+//!
+//! 设置单独占一个擦除扇区，每次保存先擦再从扇区开头写一条记录：
+//!
+//! ```text
+//! magic: u32 | len: u16 | version: u16 | 条目 ... | crc32: u32
+//! ```
+//!
+//! 每个条目是 `编号: u8 | 长度: u8 | 值`，编号见 [`Key::id`]。数字按 `u32` 存，其它按文本存，
+//! 读回来时和 `config set` 一样检查。不认识的编号跳过，检查不通过的那一项用默认值，
+//! 这样加减设置项不会让整份设置作废。整个记录按 flash 的写入单位补 `0xFF`，多字节都是小端。
+//!
+//! `version` 是条目格式的版本，旧版本的记录读的时候先迁移，见 [`schema`](super::schema)。
+
+use embedded_storage::nor_flash::NorFlash;
+
+use super::schema::{self, Entries, VERSION};
+use super::{Key, Settings, Storage, Value};
+use crate::link::crc::crc32;
+
+/// 记录开头的标记
+const MAGIC: u32 = u32::from_le_bytes(*b"SETS");
+/// magic、长度和版本
+const HEADER_SIZE: usize = 8;
+const CRC_SIZE: usize = 4;
+/// 一条记录最多多大，要是 flash 写入单位的整数倍
+pub const RECORD_SIZE: usize = 256;
+
+/// 上电读设置的结果
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Loaded {
+    /// 读到了保存过的设置
+    Saved,
+    /// 扇区是空的，从没保存过或者刚 `config reset`
+    Empty,
+    /// 记录坏了（CRC 不对、写到一半掉电），用的是默认值
+    Corrupt,
+    /// 记录是更新的固件写的，不认，用的是默认值
+    TooNew,
+}
+
+impl Loaded {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Loaded::Saved => "saved",
+            Loaded::Empty => "defaults",
+            Loaded::Corrupt => "defaults (stored settings corrupt)",
+            Loaded::TooNew => "defaults (stored settings from newer firmware)",
+        }
+    }
+}
+
+/// 保存设置失败的原因
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum StorageError {
+    /// 板级代码没有提供 flash
+    NotInstalled,
+    /// 设置太多，一条记录放不下
+    TooLarge,
+    /// 擦写 flash 出错
+    Flash,
+}
+
+impl StorageError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageError::NotInstalled => "no settings storage on this board",
+            StorageError::TooLarge => "settings too large",
+            StorageError::Flash => "flash error",
+        }
+    }
+}
+
+/// 在 `flash` 的 `offset` 处（要对齐擦除扇区）读写设置
+pub struct FlashStore<F> {
+    flash: F,
+    offset: u32,
+}
+
+impl<F: NorFlash> FlashStore<F> {
+    pub fn new(flash: F, offset: u32) -> Self {
+        Self { flash, offset }
+    }
+
+    pub fn flash(&self) -> &F {
+        &self.flash
+    }
+
+    /// 读出保存的设置，读不出来就用默认值
+    pub fn load(&mut self) -> (Settings, Loaded) {
+        let mut buf = [0; RECORD_SIZE];
+        if self.flash.read(self.offset, &mut buf).is_err() {
+            return (Settings::default(), Loaded::Corrupt);
+        }
+        match decode(&buf) {
+            Ok(settings) => (settings, Loaded::Saved),
+            Err(loaded) => (Settings::default(), loaded),
+        }
+    }
+
+    /// 擦掉扇区再写一条记录
+    pub fn write(&mut self, settings: &Settings) -> Result<(), StorageError> {
+        let mut buf = [0xFF; RECORD_SIZE];
+        let len = encode(settings, &mut buf)?;
+        let len = len.next_multiple_of(F::WRITE_SIZE);
+        if len > RECORD_SIZE {
+            return Err(StorageError::TooLarge);
+        }
+        self.erase_sector()?;
+        self.flash
+            .write(self.offset, &buf[..len])
+            .map_err(|_| StorageError::Flash)
+    }
+
+    fn erase_sector(&mut self) -> Result<(), StorageError> {
+        self.flash
+            .erase(self.offset, self.offset + F::ERASE_SIZE as u32)
+            .map_err(|_| StorageError::Flash)
+    }
+}
+
+impl<F: NorFlash> Storage for FlashStore<F> {
+    fn save(&mut self, settings: &Settings) -> Result<(), StorageError> {
+        self.write(settings)
+    }
+
+    fn erase(&mut self) -> Result<(), StorageError> {
+        self.erase_sector()
+    }
+}
+
+/// 编码成一条记录，返回不含填充的长度
+pub(super) fn encode(
+    settings: &Settings,
+    buf: &mut [u8; RECORD_SIZE],
+) -> Result<usize, StorageError> {
+    let mut entries = Entries::new();
+    for key in Key::ALL {
+        let added = match settings.get(key) {
+            Value::Uint(n) => entries.set(key.id(), &n.to_le_bytes()),
+            Value::Str(s) => entries.set(key.id(), s.as_bytes()),
+        };
+        added.map_err(|_| StorageError::TooLarge)?;
+    }
+
+    let pos = HEADER_SIZE + entries.as_bytes().len();
+    if pos + CRC_SIZE > RECORD_SIZE {
+        return Err(StorageError::TooLarge);
+    }
+    buf[..4].copy_from_slice(&MAGIC.to_le_bytes());
+    buf[4..6].copy_from_slice(&((pos - HEADER_SIZE) as u16).to_le_bytes());
+    buf[6..8].copy_from_slice(&VERSION.to_le_bytes());
+    buf[HEADER_SIZE..pos].copy_from_slice(entries.as_bytes());
+    let crc = crc32(&buf[..pos]);
+    buf[pos..pos + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+    Ok(pos + CRC_SIZE)
+}
+
+pub(super) fn decode(buf: &[u8; RECORD_SIZE]) -> Result<Settings, Loaded> {
+    let magic = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    if magic == u32::MAX {
+        return Err(Loaded::Empty);
+    }
+    let len = usize::from(u16::from_le_bytes([buf[4], buf[5]]));
+    let end = HEADER_SIZE + len;
+    if magic != MAGIC || end + CRC_SIZE > RECORD_SIZE {
+        return Err(Loaded::Corrupt);
+    }
+    let crc = u32::from_le_bytes([buf[end], buf[end + 1], buf[end + 2], buf[end + 3]]);
+    if crc != crc32(&buf[..end]) {
+        return Err(Loaded::Corrupt);
+    }
+    let version = u16::from_le_bytes([buf[6], buf[7]]);
+    if version > VERSION {
+        return Err(Loaded::TooNew);
+    }
+
+    let mut entries = Entries::parse(&buf[HEADER_SIZE..end]).map_err(|_| Loaded::Corrupt)?;
+    schema::migrate(&mut entries, version).map_err(|_| Loaded::Corrupt)?;
+    Ok(apply(&entries))
+}
+
+/// 把当前版本的条目填进默认设置
+fn apply(entries: &Entries) -> Settings {
+    let mut settings = Settings::default();
+    for (id, value) in entries.iter() {
+        let Some(key) = Key::from_id(id) else {
+            continue;
+        };
+        if matches!(settings.get(key), Value::Uint(_)) {
+            let Ok(bytes) = <[u8; 4]>::try_from(value) else {
+                continue;
+            };
+            let mut text = heapless::String::<10>::new();
+            let _ = core::fmt::write(&mut text, format_args!("{}", u32::from_le_bytes(bytes)));
+            let _ = settings.set(key, &text);
+        } else if let Ok(text) = core::str::from_utf8(value) {
+            let _ = settings.set(key, text);
+        }
+    }
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{LedMode, MemFlash};
+
+    type Flash = MemFlash<4096, 32, 1024>;
+
+    fn saved() -> Settings {
+        let mut settings = Settings::default();
+        settings.set(Key::UartBaud, "9600").unwrap();
+        settings.set(Key::LedMode, "fast").unwrap();
+        settings.set(Key::HelloText, "hi there").unwrap();
+        settings
+    }
+
+    /// 复制一份 flash 的内容，像重新上电一样读
+    fn reboot(store: &FlashStore<Flash>, offset: u32) -> FlashStore<Flash> {
+        let mut flash = Flash::new();
+        flash.mem = store.flash().mem;
+        FlashStore::new(flash, offset)
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut store = FlashStore::new(Flash::new(), 1024);
+        assert_eq!(store.load(), (Settings::default(), Loaded::Empty));
+
+        store.save(&saved()).unwrap();
+        assert_eq!(reboot(&store, 1024).load(), (saved(), Loaded::Saved));
+        assert_eq!(store.load().0.led_mode, LedMode::Fast);
+
+        // 每次保存都先擦整个扇区，别的扇区不动
+        store.save(&saved()).unwrap();
+        assert_eq!(store.flash().erases, 2);
+        assert!(store.flash().mem[..1024].iter().all(|&b| b == 0xFF));
+        assert!(store.flash().mem[2048..].iter().all(|&b| b == 0xFF));
+
+        store.erase().unwrap();
+        assert_eq!(store.load(), (Settings::default(), Loaded::Empty));
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut store = FlashStore::new(Flash::new(), 0);
+        store.save(&saved()).unwrap();
+
+        let mut flipped = reboot(&store, 0);
+        flipped.flash.mem[12] ^= 1;
+        assert_eq!(flipped.load(), (Settings::default(), Loaded::Corrupt));
+
+        let mut magic = reboot(&store, 0);
+        magic.flash.mem[0] = 0;
+        assert_eq!(magic.load().1, Loaded::Corrupt);
+
+        // 长度超出记录
+        let mut len = reboot(&store, 0);
+        len.flash.mem[4..6].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(len.load().1, Loaded::Corrupt);
+    }
+
+    #[test]
+    fn bad_value_falls_back_to_default() {
+        // 老固件存下的非标准波特率：这一项用默认值，其它照常读
+        let mut settings = saved();
+        settings.baudrate = 12345;
+        let mut store = FlashStore::new(Flash::new(), 0);
+        store.save(&settings).unwrap();
+
+        let (loaded, state) = store.load();
+        assert_eq!(state, Loaded::Saved);
+        assert_eq!(loaded.baudrate, Settings::default().baudrate);
+        assert_eq!(loaded.greeting, "hi there");
+    }
+}
@@ -0,0 +1,297 @@
+//! 命令行拆词和参数解析。
+//!
+//! 只处理 `&str`，不依赖外设，可以在主机上测试。
+//!
+//! 词之间用空白分隔；用双引号或单引号括起来的部分算一个词，里面可以有空格，
+//! 引号本身不算在词里（不支持转义）。数字可以写成十进制、`0x` 十六进制或 `0b` 二进制，
+//! 前面可以带 `-`。时间写成 `500ms`、`2s`，不带单位的按秒算。
+
+use core::fmt;
+
+use embassy_time::Duration;
+
+/// 参数错误
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ArgError {
+    /// 引号没有配对
+    UnterminatedQuote,
+    /// 缺少某个参数
+    Missing(&'static str),
+    /// 某个参数的格式不对或者超出范围
+    Invalid(&'static str),
+    /// 多出来的参数
+    TooMany,
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::UnterminatedQuote => f.write_str("unterminated quote"),
+            ArgError::Missing(name) => write!(f, "missing <{}>", name),
+            ArgError::Invalid(name) => write!(f, "invalid <{}>", name),
+            ArgError::TooMany => f.write_str("too many arguments"),
+        }
+    }
+}
+
+/// 按空白和引号拆词
+#[derive(Clone)]
+pub struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(line: &'a str) -> Self {
+        Self { rest: line }
+    }
+
+    /// 还没拆的部分，开头的空白已经去掉
+    pub fn rest(&self) -> &'a str {
+        self.rest.trim_start()
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Result<&'a str, ArgError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = self.rest.trim_start();
+        let quote = match s.chars().next()? {
+            q @ ('"' | '\'') => q,
+            _ => {
+                let end = s.find(char::is_whitespace).unwrap_or(s.len());
+                self.rest = &s[end..];
+                return Some(Ok(&s[..end]));
+            }
+        };
+
+        let body = &s[1..];
+        match body.find(quote) {
+            Some(end) => {
+                self.rest = &body[end + 1..];
+                Some(Ok(&body[..end]))
+            }
+            None => {
+                self.rest = "";
+                Some(Err(ArgError::UnterminatedQuote))
+            }
+        }
+    }
+}
+
+/// 解析整数，支持 `-`、`0x`、`0b`，超出 `T` 的范围算错
+pub fn parse_int<T: TryFrom<i64>>(s: &str) -> Option<T> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let (radix, digits) = if let Some(d) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        (16, d)
+    } else if let Some(d) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B")) {
+        (2, d)
+    } else {
+        (10, s)
+    };
+    // from_str_radix 自己也认 `+`/`-`，这里已经处理过符号了，不能再出现
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+
+    let magnitude = i64::from_str_radix(digits, radix).ok()?;
+    let value = if negative {
+        magnitude.checked_neg()?
+    } else {
+        magnitude
+    };
+    T::try_from(value).ok()
+}
+
+/// 解析时间：`500ms`、`2s` 或者 `2`（秒），不能是 0
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let (n, ms) = if let Some(n) = s.strip_suffix("ms") {
+        (n, true)
+    } else {
+        (s.strip_suffix('s').unwrap_or(s), false)
+    };
+    let n: u32 = n.parse().ok().filter(|&n| n > 0)?;
+    Some(if ms {
+        Duration::from_millis(n.into())
+    } else {
+        Duration::from_secs(n.into())
+    })
+}
+
+/// 解析布尔值：`true`/`false`、`on`/`off`、`yes`/`no`、`1`/`0`，不区分大小写
+pub fn parse_bool(s: &str) -> Option<bool> {
+    const TRUE: [&str; 4] = ["true", "on", "yes", "1"];
+    const FALSE: [&str; 4] = ["false", "off", "no", "0"];
+
+    if TRUE.iter().any(|t| s.eq_ignore_ascii_case(t)) {
+        Some(true)
+    } else if FALSE.iter().any(|t| s.eq_ignore_ascii_case(t)) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// 命令名后面的参数，按顺序一个一个取。`name` 只用来生成错误信息
+#[derive(Clone)]
+pub struct Args<'a> {
+    tokens: Tokens<'a>,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(args: &'a str) -> Self {
+        Self {
+            tokens: Tokens::new(args),
+        }
+    }
+
+    /// 下一个词，没有了返回 `None`
+    pub fn next_str(&mut self) -> Result<Option<&'a str>, ArgError> {
+        self.tokens.next().transpose()
+    }
+
+    /// 必须有的下一个词
+    pub fn str(&mut self, name: &'static str) -> Result<&'a str, ArgError> {
+        self.next_str()?.ok_or(ArgError::Missing(name))
+    }
+
+    pub fn int<T: TryFrom<i64>>(&mut self, name: &'static str) -> Result<T, ArgError> {
+        parse_int(self.str(name)?).ok_or(ArgError::Invalid(name))
+    }
+
+    /// 可以省略的整数
+    pub fn opt_int<T: TryFrom<i64>>(&mut self, name: &'static str) -> Result<Option<T>, ArgError> {
+        match self.next_str()? {
+            Some(s) => parse_int(s).map(Some).ok_or(ArgError::Invalid(name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn bool(&mut self, name: &'static str) -> Result<bool, ArgError> {
+        parse_bool(self.str(name)?).ok_or(ArgError::Invalid(name))
+    }
+
+    pub fn duration(&mut self, name: &'static str) -> Result<Duration, ArgError> {
+        parse_duration(self.str(name)?).ok_or(ArgError::Invalid(name))
+    }
+
+    /// 剩下的原始文本，比如 `watch 1 uart stats` 里要再当成命令执行的部分
+    pub fn rest(&self) -> &'a str {
+        self.tokens.rest()
+    }
+
+    /// 参数取完了，后面不能再有东西
+    pub fn finish(mut self) -> Result<(), ArgError> {
+        match self.next_str()? {
+            Some(_) => Err(ArgError::TooMany),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::fmt::Write as _;
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let words: Vec<_> = Tokens::new(r#"  set "a b" 'c"d' x  "" "#).collect();
+        assert_eq!(words, [Ok("set"), Ok("a b"), Ok("c\"d"), Ok("x"), Ok("")]);
+
+        let mut tokens = Tokens::new(r#"a "oops"#);
+        assert_eq!(tokens.next(), Some(Ok("a")));
+        assert_eq!(tokens.next(), Some(Err(ArgError::UnterminatedQuote)));
+        assert_eq!(tokens.next(), None);
+
+        assert_eq!(Tokens::new(" \t ").next(), None);
+    }
+
+    #[test]
+    fn ints() {
+        assert_eq!(parse_int::<u32>("0xFFFFFFFF"), Some(u32::MAX));
+        assert_eq!(parse_int::<u32>("0XfF"), Some(255));
+        assert_eq!(parse_int::<u8>("0b101"), Some(5));
+        assert_eq!(parse_int::<i32>("-12"), Some(-12));
+        assert_eq!(parse_int::<i8>("-0x80"), Some(i8::MIN));
+
+        assert_eq!(parse_int::<u32>("0x100000000"), None);
+        assert_eq!(parse_int::<u32>("-1"), None);
+        assert_eq!(parse_int::<i32>("--1"), None);
+        assert_eq!(parse_int::<i32>("+1"), None);
+        assert_eq!(parse_int::<i32>("-+1"), None);
+        assert_eq!(parse_int::<i32>("0x"), None);
+        assert_eq!(parse_int::<i32>("0b2"), None);
+        assert_eq!(parse_int::<i32>(""), None);
+    }
+
+    #[test]
+    fn bools() {
+        assert_eq!(parse_bool("ON"), Some(true));
+        assert_eq!(parse_bool("Yes"), Some(true));
+        assert_eq!(parse_bool("0"), Some(false));
+        assert_eq!(parse_bool("nah"), None);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_duration("2"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_duration("0"), None);
+        assert_eq!(parse_duration("0ms"), None);
+        assert_eq!(parse_duration("1.5s"), None);
+        assert_eq!(parse_duration("x"), None);
+        assert_eq!(
+            Args::new("1m").duration("interval"),
+            Err(ArgError::Invalid("interval"))
+        );
+    }
+
+    #[test]
+    fn args() {
+        let mut args = Args::new("0x10 true  rest of 'it'");
+        assert_eq!(args.int::<u16>("addr"), Ok(16));
+        assert_eq!(args.bool("enable"), Ok(true));
+        assert_eq!(args.rest(), "rest of 'it'");
+        assert_eq!(args.clone().finish(), Err(ArgError::TooMany));
+        assert_eq!(args.str("a"), Ok("rest"));
+        assert_eq!(args.next_str(), Ok(Some("of")));
+        assert_eq!(args.str("b"), Ok("it"));
+        assert_eq!(args.opt_int::<u8>("n"), Ok(None));
+        assert_eq!(args.finish(), Ok(()));
+    }
+
+    #[test]
+    fn arg_errors() {
+        assert_eq!(Args::new("").int::<u8>("n"), Err(ArgError::Missing("n")));
+        assert_eq!(Args::new("256").int::<u8>("n"), Err(ArgError::Invalid("n")));
+        assert_eq!(
+            Args::new("z").opt_int::<u8>("n"),
+            Err(ArgError::Invalid("n"))
+        );
+        assert_eq!(Args::new("maybe").bool("on"), Err(ArgError::Invalid("on")));
+        assert_eq!(
+            Args::new("'open").str("s"),
+            Err(ArgError::UnterminatedQuote)
+        );
+        assert_eq!(Args::new("x 'open").finish(), Err(ArgError::TooMany));
+
+        let mut text = heapless::String::<32>::new();
+        write!(
+            text,
+            "{}, {}",
+            ArgError::Missing("pin"),
+            ArgError::Invalid("baud")
+        )
+        .unwrap();
+        assert_eq!(text, "missing <pin>, invalid <baud>");
+    }
+}
@@ -0,0 +1,102 @@
+//! Ctrl-C：取消正在执行的命令。
+//!
+//! 命令在处理那一边执行时，接收那一边还在继续收字节，收到 [`CTRL_C`] 就调用
+//! [`Cancel::request`]。命令自己决定在哪里响应：等待的地方用
+//! [`Reply::cancellable`](super::output::Reply::cancellable) 包一下，取消时返回
+//! [`CommandError::Cancelled`](super::commands::CommandError::Cancelled)，用 `?` 一路返回即可。
+//!
+//! 命令借用的外设状态（改过的引脚、开着的定时器之类）要放在 `Drop` 里恢复的守卫里，
+//! 这样不管是正常结束、被取消还是连接断开整个 future 被丢掉，都会收拾干净。
+//!
+//! 没有命令在执行时 `request` 什么也不做，Ctrl-C 照常交给行编辑器，清掉正在输入的一行。
+
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+
+use embassy_sync::waitqueue::AtomicWaker;
+
+/// Ctrl-C 发过来的字节
+pub const CTRL_C: u8 = 0x03;
+
+/// 一个终端的取消信号
+pub struct Cancel {
+    /// 有命令正在执行
+    running: AtomicBool,
+    requested: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl Cancel {
+    pub const fn new() -> Self {
+        Self {
+            running: AtomicBool::new(false),
+            requested: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// 命令开始执行，之前的取消请求作废。返回的守卫丢掉时算执行完，
+    /// 命令的 future 执行到一半被丢掉（比如连接断开）也一样
+    pub fn start(&self) -> Running<'_> {
+        self.requested.store(false, Ordering::Relaxed);
+        self.running.store(true, Ordering::Release);
+        Running { cancel: self }
+    }
+
+    /// 命令执行完了
+    fn finish(&self) {
+        self.running.store(false, Ordering::Release);
+        self.requested.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    /// 接收端收到了 Ctrl-C。有命令在执行就通知它并返回 `true`，
+    /// 这个字节不用再交给行编辑器了
+    pub fn request(&self) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+        self.requested.store(true, Ordering::Release);
+        self.waker.wake();
+        true
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+
+    /// 等到有人按 Ctrl-C。同一时间只能有一个地方在等
+    pub async fn wait(&self) {
+        poll_fn(|cx| {
+            self.waker.register(cx.waker());
+            if self.is_requested() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// [`Cancel::start`] 返回的守卫，活着的时候算有命令在执行
+#[must_use = "丢掉守卫就算命令执行完了"]
+pub struct Running<'a> {
+    cancel: &'a Cancel,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.cancel.finish();
+    }
+}
+
+impl Default for Cancel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
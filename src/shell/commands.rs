@@ -0,0 +1,597 @@
+//! shell 的命令表。
+//!
+//! 每条命令在 [`COMMANDS`] 里有一项：名字、用法和一句说明，以及执行它的 [`Handler`]。
+//! 处理函数是 `async fn`，不同函数返回的 future 类型不一样，没有堆就放不进同一个函数指针数组，
+//! 所以用 [`Handler`] 枚举在 [`Handler::call`] 里分派。新增一条命令：
+//!
+//! 1. 写一个 `async fn xxx<W: Write>(out: &mut Reply<'_, W>, args: Args<'_>) -> Result<(), CommandError<W::Error>>`，
+//!    给人看的文本用 [`Reply::text`]，机器模式的结果填到 [`Reply::data`]（见 [`output`](super::output)）；
+//!    要等很久的地方用 [`Reply::cancellable`] 包起来，这样可以用 Ctrl-C 取消（见 [`cancel`](super::cancel)）；
+//! 2. 给 [`Handler`] 加一个变体，在 `call` 里调用它；
+//! 3. 在 [`COMMANDS`] 里加一项，参数需要 Tab 补全的话再写一个 [`Completer`]。
+
+use embassy_time::Timer;
+use embedded_io_async::Write;
+
+use super::args::{ArgError, Args, Tokens};
+use super::cancel::Cancel;
+use super::complete::{Candidates, Completer};
+use super::output::{Mode, Reply, Status};
+use super::watch;
+use super::{config, gpio, memory};
+use crate::settings;
+use crate::usart::line::{self, LineConfig, LineRequest};
+use crate::usart::stats;
+
+/// 命令执行失败的原因
+#[derive(Debug)]
+pub enum CommandError<E> {
+    /// 写应答失败，shell 会把它交给上层
+    Io(E),
+    /// 参数不对，shell 会打印错误和用法（[`Status::BadArgument`]）
+    Arg(ArgError),
+    /// 参数组合不对，shell 只打印用法（[`Status::Usage`]）
+    Usage,
+    /// 执行失败，shell 打印 `Error: ...`（[`Status::Failed`]）
+    Failed(&'static str),
+    /// 要用的资源被别的驱动占着，shell 打印 `Error: in use by ...`（[`Status::Failed`]）
+    InUse(&'static str),
+    /// 被 Ctrl-C 取消了，见 [`cancel`](super::cancel)（[`Status::Cancelled`]）
+    Cancelled,
+}
+
+impl<E> From<ArgError> for CommandError<E> {
+    fn from(e: ArgError) -> Self {
+        CommandError::Arg(e)
+    }
+}
+
+/// 执行命令的函数，见模块说明
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Handler {
+    Help,
+    Hello,
+    Mode,
+    Sleep,
+    Uart,
+    Watch,
+    Repeat,
+    Peek,
+    Poke,
+    Hexdump,
+    Gpio,
+    Config,
+}
+
+impl Handler {
+    pub async fn call<W: Write>(
+        self,
+        out: &mut Reply<'_, W>,
+        args: Args<'_>,
+    ) -> Result<(), CommandError<W::Error>> {
+        match self {
+            Handler::Watch => watch::watch(out, args).await,
+            Handler::Repeat => watch::repeat(out, args).await,
+            _ => self.call_simple(out, args).await,
+        }
+    }
+
+    /// 执行 `watch`/`repeat` 之外的命令。`watch` 里面用这个，免得 async fn 递归
+    pub async fn call_simple<W: Write>(
+        self,
+        out: &mut Reply<'_, W>,
+        args: Args<'_>,
+    ) -> Result<(), CommandError<W::Error>> {
+        match self {
+            Handler::Help => help(out, args).await,
+            Handler::Hello => hello(out, args).await,
+            Handler::Mode => mode(out, args).await,
+            Handler::Sleep => sleep(out, args).await,
+            Handler::Uart => uart(out, args).await,
+            Handler::Peek => memory::peek(out, args).await,
+            Handler::Poke => memory::poke(out, args).await,
+            Handler::Hexdump => memory::hexdump(out, args).await,
+            Handler::Gpio => gpio::gpio(out, args).await,
+            Handler::Config => config::config(out, args).await,
+            Handler::Watch | Handler::Repeat => {
+                Err(CommandError::Failed("cannot nest watch or repeat"))
+            }
+        }
+    }
+
+    /// 反复执行别的命令的命令，见 [`watch`](super::watch)
+    pub fn is_meta(self) -> bool {
+        matches!(self, Handler::Watch | Handler::Repeat)
+    }
+}
+
+/// 命令表的一项
+pub struct Command {
+    pub name: &'static str,
+    /// 用法，比如 `uart [set <baud> [8N1]]`
+    pub usage: &'static str,
+    /// 一句话说明
+    pub help: &'static str,
+    pub handler: Handler,
+    /// 参数怎么补全，`None` 表示不补全参数
+    pub complete: Option<Completer>,
+}
+
+/// 所有命令，`help` 按这个顺序列出
+pub static COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        usage: "help [command]",
+        help: "list commands or show usage of one",
+        handler: Handler::Help,
+        complete: Some(complete_help),
+    },
+    Command {
+        name: "hello",
+        usage: "hello [all]",
+        help: "say hello",
+        handler: Handler::Hello,
+        complete: Some(complete_hello),
+    },
+    Command {
+        name: "mode",
+        usage: "mode [human|plain|json|cbor]",
+        help: "show or change the output format of this terminal",
+        handler: Handler::Mode,
+        complete: Some(complete_mode),
+    },
+    Command {
+        name: "sleep",
+        usage: "sleep <ms>",
+        help: "wait, Ctrl-C to stop early",
+        handler: Handler::Sleep,
+        complete: None,
+    },
+    Command {
+        name: "repeat",
+        usage: "repeat <count> <command...>",
+        help: "run a command several times",
+        handler: Handler::Repeat,
+        complete: Some(watch::complete_repeat),
+    },
+    Command {
+        name: "uart",
+        usage: "uart [set <baud> [8N1] | autobaud | stats [reset]]",
+        help: "show or change console line settings",
+        handler: Handler::Uart,
+        complete: Some(complete_uart),
+    },
+    Command {
+        name: "peek",
+        usage: "peek <addr> [8|16|32]",
+        help: "read a register or memory word",
+        handler: Handler::Peek,
+        complete: Some(memory::complete_peek),
+    },
+    Command {
+        name: "poke",
+        usage: "poke <addr> <value> [8|16|32]",
+        help: "write a register or memory word",
+        handler: Handler::Poke,
+        complete: Some(memory::complete_poke),
+    },
+    Command {
+        name: "hexdump",
+        usage: "hexdump <addr> [len]",
+        help: "dump up to 256 bytes of memory",
+        handler: Handler::Hexdump,
+        complete: Some(memory::complete_hexdump),
+    },
+    Command {
+        name: "gpio",
+        usage: "gpio [mode <pin> <mode> | set <pin> <0|1> | get|toggle|watch|release <pin>]",
+        help: "configure and drive pins not used by other drivers",
+        handler: Handler::Gpio,
+        complete: Some(gpio::complete_gpio),
+    },
+    Command {
+        name: "config",
+        usage: "config [list | get <key> | set <key> <value> | save | reset]",
+        help: "show or change settings, save them to flash",
+        handler: Handler::Config,
+        complete: Some(config::complete_config),
+    },
+    Command {
+        name: "watch",
+        usage: "watch <interval> <command...>",
+        help: "run a command periodically until Ctrl-C, e.g. watch 1s uart stats",
+        handler: Handler::Watch,
+        complete: Some(watch::complete_watch),
+    },
+];
+
+/// 按名字找命令，名字要完全一致
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// 按 `mode` 格式执行一行命令，参数错误、未知命令等都作为应答写回去，只有写失败才返回 `Err`。
+/// 命令要求切换格式时（`mode json` 之类）改写 `mode`。执行期间 `cancel` 收到的 Ctrl-C 交给命令
+pub async fn execute<W: Write>(
+    tx: &mut W,
+    mode: &mut Mode,
+    cancel: &Cancel,
+    line: &str,
+) -> Result<(), W::Error> {
+    let mut out = Reply::new(tx, *mode, cancel);
+    let mut tokens = Tokens::new(line);
+    let name = match tokens.next() {
+        // 机器模式下空行也回一个结果，脚本可以拿它来同步
+        None if out.is_human() => return Ok(()),
+        None => return out.finish(Status::Ok, None, None).await,
+        Some(Ok(name)) => name,
+        Some(Err(e)) => {
+            let message = format_args!("{}", e);
+            return out.finish(Status::BadArgument, Some(message), None).await;
+        }
+    };
+    let Some(command) = find(name) else {
+        let message = format_args!("unknown command '{}', try 'help'", name);
+        return out
+            .finish(Status::UnknownCommand, Some(message), None)
+            .await;
+    };
+
+    let args = Args::new(tokens.rest());
+    let running = cancel.start();
+    let result = command.handler.call(&mut out, args).await;
+    drop(running);
+    conclude(&mut out, command, result).await?;
+
+    if let Some(next) = out.next_mode() {
+        *mode = next;
+    }
+    Ok(())
+}
+
+/// 按命令的执行结果写出应答，只有写失败才返回 `Err`
+pub async fn conclude<W: Write>(
+    out: &mut Reply<'_, W>,
+    command: &Command,
+    result: Result<(), CommandError<W::Error>>,
+) -> Result<(), W::Error> {
+    match result {
+        Ok(()) => out.finish(Status::Ok, None, None).await,
+        Err(CommandError::Io(e)) => Err(e),
+        Err(CommandError::Arg(e)) => {
+            let message = format_args!("{}", e);
+            out.finish(Status::BadArgument, Some(message), Some(command.usage))
+                .await
+        }
+        Err(CommandError::Usage) => out.finish(Status::Usage, None, Some(command.usage)).await,
+        Err(CommandError::Failed(msg)) => {
+            let message = format_args!("{}", msg);
+            out.finish(Status::Failed, Some(message), None).await
+        }
+        Err(CommandError::InUse(owner)) => {
+            let message = format_args!("in use by {}", owner);
+            out.finish(Status::Failed, Some(message), None).await
+        }
+        Err(CommandError::Cancelled) => {
+            let message = format_args!("cancelled");
+            out.finish(Status::Cancelled, Some(message), None).await
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// 内置命令
+// ---------------------------------------------------------------------------
+
+/// `help`：列出所有命令；`help <command>`：显示一条命令的用法
+async fn help<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    let name = args.next_str()?;
+    args.finish()?;
+
+    match name {
+        None if out.is_human() => {
+            for c in COMMANDS {
+                out.text_fmt(format_args!("{:<8}{}\r\n", c.name, c.help))
+                    .await?;
+            }
+        }
+        // 机器模式只列名字和用法，说明太长了放不进 payload，要看的话用 `help <command>`
+        None => {
+            let data = out.data();
+            data.key("commands").list();
+            for c in COMMANDS {
+                data.map();
+                data.key("name").str(c.name);
+                data.key("usage").str(c.usage);
+                data.end();
+            }
+            data.end();
+        }
+        Some(name) => {
+            let c = find(name).ok_or(CommandError::Failed("no such command"))?;
+            out.text_fmt(format_args!("Usage: {}\r\n{}\r\n", c.usage, c.help))
+                .await?;
+            let data = out.data();
+            data.key("name").str(c.name);
+            data.key("usage").str(c.usage);
+            data.key("help").str(c.help);
+        }
+    }
+    Ok(())
+}
+
+fn complete_help(args: Args<'_>, candidates: &mut Candidates<'_>) {
+    if args.rest().is_empty() {
+        for c in COMMANDS {
+            candidates.add(c.name);
+        }
+    }
+}
+
+/// `hello`：回一句 “你好！”；`hello all`：回一句 “您们好！”。
+/// 回答可以用 `config set hello.text`/`hello.all` 改
+async fn hello<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    let all = match args.next_str()? {
+        None => false,
+        Some("all") => true,
+        Some(_) => return Err(CommandError::Usage),
+    };
+    args.finish()?;
+
+    let reply = settings::with(|s| match all {
+        false => s.greeting.clone(),
+        true => s.greeting_all.clone(),
+    });
+    out.text_fmt(format_args!("{}\r\n", reply)).await?;
+    out.data().key("greeting").str(&reply);
+    Ok(())
+}
+
+fn complete_hello(args: Args<'_>, candidates: &mut Candidates<'_>) {
+    if args.rest().is_empty() {
+        candidates.add("all");
+    }
+}
+
+/// `mode`：当前终端的输出格式；`mode <human|plain|json|cbor>`：切换，从下一条命令开始生效
+async fn mode<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    let next = match args.next_str()? {
+        None => None,
+        Some(s) => Some(Mode::parse(s).ok_or(ArgError::Invalid("mode"))?),
+    };
+    args.finish()?;
+
+    match next {
+        None => {
+            let current = out.mode();
+            out.data().key("mode").str(current.as_str());
+            out.text_fmt(format_args!("{}\r\n", current.as_str())).await
+        }
+        Some(next) => {
+            out.set_mode(next);
+            out.data().key("mode").str(next.as_str());
+            out.text("OK\r\n").await
+        }
+    }
+}
+
+fn complete_mode(args: Args<'_>, candidates: &mut Candidates<'_>) {
+    if args.rest().is_empty() {
+        for m in [Mode::Human, Mode::Plain, Mode::Json, Mode::Cbor] {
+            candidates.add(m.as_str());
+        }
+    }
+}
+
+/// `sleep <ms>`：等一会儿再回应，脚本里用来等外部的动作完成。可以用 Ctrl-C 提前结束
+async fn sleep<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    let ms: u32 = args.int("ms")?;
+    args.finish()?;
+
+    out.cancellable(Timer::after_millis(ms.into())).await
+}
+
+/// `uart`：查看串口参数；`uart set <波特率> [8N1]`：修改；`uart autobaud`：自动检测波特率；
+/// `uart stats [reset]`：查看或清零收包和错误统计。
+///
+/// 应答用旧参数发完才会请求切换，切换后 10 秒内要用新参数发一行过来，否则自动退回。
+async fn uart<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    let current = line::current();
+
+    let req = match args.next_str()? {
+        None => {
+            line_config(out, &current);
+            return out.text_fmt(format_args!("{}\r\n", current)).await;
+        }
+        Some("set") => {
+            let baudrate = args.str("baud")?;
+            let format = args.next_str()?;
+            args.finish()?;
+            let config = current
+                .parse(baudrate, format)
+                .map_err(|e| CommandError::Failed(e.as_str()))?;
+            line_config(out, &config);
+            out.text_fmt(format_args!("OK, switching to {}", config))
+                .await?;
+            LineRequest::Set(config)
+        }
+        Some("stats") => {
+            let reset = match args.next_str()? {
+                None => false,
+                Some("reset") => true,
+                Some(_) => return Err(CommandError::Usage),
+            };
+            args.finish()?;
+            if !reset {
+                return uart_stats(out).await;
+            }
+            stats::CONSOLE.reset();
+            return out.text("OK\r\n").await;
+        }
+        Some("autobaud") => {
+            args.finish()?;
+            out.text("OK, send CR at the new baud rate").await?;
+            LineRequest::AutoBaud
+        }
+        Some(_) => return Err(CommandError::Usage),
+    };
+
+    let timeout = line::FALLBACK_TIMEOUT.as_secs();
+    out.text_fmt(format_args!(", confirm within {} s\r\n", timeout))
+        .await?;
+    out.data().key("confirm_within").uint(timeout);
+    // 应答必须在切换之前发完
+    out.send().await?;
+    line::request(req);
+    Ok(())
+}
+
+/// 机器模式下的串口参数：`{"baud":115200,"format":"8N1"}`
+fn line_config<W: Write>(out: &mut Reply<'_, W>, config: &LineConfig) {
+    let mut s = heapless::String::<24>::new();
+    let _ = core::fmt::write(&mut s, format_args!("{}", config));
+    let format = s.split_once(' ').map_or("", |(_, f)| f);
+    let data = out.data();
+    data.key("baud").uint(config.baudrate.into());
+    data.key("format").str(format);
+}
+
+fn complete_uart(mut args: Args<'_>, candidates: &mut Candidates<'_>) {
+    let sub = args.next_str().ok().flatten();
+    let nth = Tokens::new(args.rest()).count();
+    match (sub, nth) {
+        (None, _) => {
+            for s in ["set", "autobaud", "stats"] {
+                candidates.add(s);
+            }
+        }
+        (Some("set"), 0) => {
+            for baudrate in line::STANDARD_BAUDRATES {
+                candidates.add_fmt(format_args!("{}", baudrate));
+            }
+        }
+        (Some("set"), 1) => {
+            for format in ["8N1", "8E1", "8O1", "7E1", "7O1", "8N2"] {
+                candidates.add(format);
+            }
+        }
+        (Some("stats"), 0) => candidates.add("reset"),
+        _ => {}
+    }
+}
+
+async fn uart_stats<W: Write>(out: &mut Reply<'_, W>) -> Result<(), CommandError<W::Error>> {
+    let s = stats::CONSOLE.get();
+    out.text_fmt(format_args!(
+        "rx: {} frames, {} bytes\r\n\
+         errors: overrun {}, framing {}, noise {}, parity {}, other {}\r\n\
+         recoveries: {}\r\n",
+        s.rx_frames, s.rx_bytes, s.overrun, s.framing, s.noise, s.parity, s.other, s.recoveries,
+    ))
+    .await?;
+
+    let data = out.data();
+    for (key, value) in [
+        ("rx_frames", s.rx_frames),
+        ("rx_bytes", s.rx_bytes),
+        ("overrun", s.overrun),
+        ("framing", s.framing),
+        ("noise", s.noise),
+        ("parity", s.parity),
+        ("other", s.other),
+        ("recoveries", s.recoveries),
+    ] {
+        data.key(key).uint(value.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use embassy_futures::join::join;
+    use embassy_futures::select::select;
+    use embassy_futures::{block_on, yield_now};
+
+    use super::*;
+    use crate::testing::{globals, MockTx};
+
+    fn run(line: &str) -> String {
+        let mut tx = MockTx::default();
+        let mut mode = Mode::default();
+        block_on(execute(&mut tx, &mut mode, &Cancel::new(), line)).unwrap();
+        String::from_utf8(tx.0).unwrap()
+    }
+
+    #[test]
+    fn dispatch() {
+        // `hello` 的回答在设置里
+        let _globals = globals();
+        assert_eq!(run("hello"), "你好！\r\n");
+        assert_eq!(run("  hello   all "), "您们好！\r\n");
+        assert_eq!(run(""), "");
+    }
+
+    #[test]
+    fn dropped_command_finishes() {
+        let cancel = Cancel::new();
+        let mut tx = MockTx::default();
+        let mut mode = Mode::default();
+        // 命令还在睡的时候整个 future 被丢掉
+        let sleeping = execute(&mut tx, &mut mode, &cancel, "sleep 1000");
+        block_on(select(sleeping, async {
+            yield_now().await;
+            assert!(cancel.is_running());
+        }));
+        assert!(!cancel.is_running());
+        assert!(!cancel.request());
+    }
+
+    #[test]
+    fn ctrl_c_cancels_command() {
+        let cancel = Cancel::new();
+        let mut tx = MockTx::default();
+        let mut mode = Mode::default();
+        let sleeping = execute(&mut tx, &mut mode, &cancel, "sleep 1000");
+        let (result, ()) = block_on(join(sleeping, async {
+            yield_now().await;
+            assert!(cancel.request());
+        }));
+        result.unwrap();
+        assert!(!cancel.is_running());
+        assert_eq!(tx.0, b"^C\r\n");
+    }
+
+    #[test]
+    fn argument_errors() {
+        assert_eq!(
+            run("hellox"),
+            "Error: unknown command 'hellox', try 'help'\r\n"
+        );
+        assert_eq!(run("\"hel"), "Error: unterminated quote\r\n");
+        assert_eq!(run("hello x"), "Usage: hello [all]\r\n");
+        assert_eq!(
+            run("hello all x"),
+            "Error: too many arguments\r\nUsage: hello [all]\r\n"
+        );
+        assert_eq!(run("help nope"), "Error: no such command\r\n");
+    }
+}
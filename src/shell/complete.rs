@@ -0,0 +1,298 @@
+//! Tab 补全。
+//!
+//! 光标前的第一个词按 [`COMMANDS`](super::commands::COMMANDS) 补全命令名，后面的词交给
+//! 这条命令自己的 [`Completer`]。只有一个候选时补全整个词并加一个空格；有多个候选时先补到
+//! 它们的公共前缀，没有可补的就把所有候选列出来。
+//!
+//! 和 [`args`](super::args) 一样只处理 `&str`，可以在主机上测试。引号里的空格不特殊处理。
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use super::args::Args;
+use super::commands::COMMANDS;
+use super::LINE_SIZE;
+
+/// 参数补全函数：`args` 是当前词前面已经输完的参数（不含命令名），
+/// 把可能的下一个词交给 `candidates.add`，不用自己按前缀过滤
+pub type Completer = fn(args: Args<'_>, candidates: &mut Candidates<'_>);
+
+/// 收集和当前词前缀匹配的候选
+pub struct Candidates<'a> {
+    prefix: &'a str,
+    count: usize,
+    /// 所有候选的公共前缀
+    common: String<LINE_SIZE>,
+    /// 第二遍时把候选列到这里
+    list: Option<&'a mut dyn Write>,
+}
+
+impl<'a> Candidates<'a> {
+    fn new(prefix: &'a str, list: Option<&'a mut dyn Write>) -> Self {
+        Self {
+            prefix,
+            count: 0,
+            common: String::new(),
+            list,
+        }
+    }
+
+    /// 正在补全的词已经输入的部分
+    pub fn prefix(&self) -> &str {
+        self.prefix
+    }
+
+    pub fn add(&mut self, candidate: &str) {
+        if !candidate.starts_with(self.prefix) {
+            return;
+        }
+        self.count += 1;
+        if self.count == 1 {
+            let _ = self.common.push_str(candidate);
+        } else {
+            let len = common_prefix(&self.common, candidate);
+            self.common.truncate(len);
+        }
+        if let Some(list) = self.list.as_mut() {
+            let _ = write!(list, "{}  ", candidate);
+        }
+    }
+
+    /// 候选是数字之类需要格式化的，先格式化再加进来
+    pub fn add_fmt(&mut self, args: core::fmt::Arguments<'_>) {
+        let mut s = String::<32>::new();
+        if s.write_fmt(args).is_ok() {
+            self.add(&s);
+        }
+    }
+}
+
+/// 两个字符串公共前缀的长度，落在字符边界上
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i)
+}
+
+/// 列候选用的缓冲区。放不下的候选不列了，最后加上 `...` 表示还有
+pub struct CandidateList<const N: usize> {
+    buf: String<N>,
+    full: bool,
+}
+
+impl<const N: usize> CandidateList<N> {
+    /// 留给结尾 `  ...` 的位置
+    const MORE: &'static str = "  ...";
+
+    pub const fn new() -> Self {
+        Self {
+            buf: String::new(),
+            full: false,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.buf
+    }
+}
+
+impl<const N: usize> Default for CandidateList<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Write for CandidateList<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if self.full {
+            return Ok(());
+        }
+        if self.buf.len() + s.len() + Self::MORE.len() <= N {
+            let _ = self.buf.push_str(s);
+            return Ok(());
+        }
+        self.full = true;
+        let more = match self.buf.ends_with("  ") {
+            true => "...",
+            false => Self::MORE,
+        };
+        let _ = self.buf.push_str(more);
+        Ok(())
+    }
+}
+
+/// 补全的结果
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Completion {
+    /// 没有候选
+    Nothing,
+    /// 把这段文本插入到光标处
+    Insert(String<LINE_SIZE>),
+    /// 候选已经列到 `list` 里了，调用方要重新显示提示符和当前行
+    Listed,
+}
+
+/// 补全光标前的文本 `before`
+pub fn complete(before: &str, list: &mut dyn Write) -> Completion {
+    let word_start = before
+        .rfind(|c: char| c.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    let prefix = &before[word_start..];
+    let words = &before[..word_start];
+
+    let mut candidates = Candidates::new(prefix, None);
+    candidates_for(words, &mut candidates);
+
+    match candidates.count {
+        0 => Completion::Nothing,
+        1 => {
+            let mut insert = String::new();
+            let _ = insert.push_str(&candidates.common[prefix.len()..]);
+            let _ = insert.push(' ');
+            Completion::Insert(insert)
+        }
+        _ if candidates.common.len() > prefix.len() => {
+            let mut insert = String::new();
+            let _ = insert.push_str(&candidates.common[prefix.len()..]);
+            Completion::Insert(insert)
+        }
+        _ => {
+            let mut candidates = Candidates::new(prefix, Some(list));
+            candidates_for(words, &mut candidates);
+            Completion::Listed
+        }
+    }
+}
+
+/// `words` 是当前词前面的部分：空的就补命令名，否则交给命令的补全函数
+fn candidates_for(words: &str, candidates: &mut Candidates<'_>) {
+    let mut args = Args::new(words);
+    match args.next_str() {
+        Ok(None) => {
+            for c in COMMANDS {
+                candidates.add(c.name);
+            }
+        }
+        Ok(Some(name)) => {
+            let command = COMMANDS.iter().find(|c| c.name == name);
+            if let Some(complete) = command.and_then(|c| c.complete) {
+                complete(args, candidates);
+            }
+        }
+        Err(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+
+    use super::*;
+    use crate::gpio::{install, PinId, PinPool};
+    use crate::testing::{globals, FakePin};
+
+    /// 补全 `before`，列出来的候选放在第二项
+    fn tab(before: &str) -> (Completion, std::string::String) {
+        let mut list = CandidateList::<512>::new();
+        let completion = complete(before, &mut list);
+        (completion, list.as_str().into())
+    }
+
+    fn insert(text: &str) -> (Completion, std::string::String) {
+        (
+            Completion::Insert(String::try_from(text).unwrap()),
+            "".into(),
+        )
+    }
+
+    fn listed(list: &str) -> (Completion, std::string::String) {
+        (Completion::Listed, list.into())
+    }
+
+    #[test]
+    fn commands() {
+        // 只有一个候选：补全并加空格
+        assert_eq!(tab("con"), insert("fig "));
+        assert_eq!(tab("config"), insert(" "));
+        // 几个候选：先补到公共前缀，补不动了再列出来
+        assert_eq!(tab("h"), insert("e"));
+        assert_eq!(tab("he"), listed("help  hello  hexdump  "));
+        assert_eq!(tab("p"), listed("peek  poke  "));
+        assert_eq!(tab("xyz"), (Completion::Nothing, "".into()));
+        // 没有补全函数的命令
+        assert_eq!(tab("sleep "), (Completion::Nothing, "".into()));
+    }
+
+    #[test]
+    fn config_keys() {
+        assert_eq!(tab("config "), listed("list  get  set  save  reset  "));
+        assert_eq!(
+            tab("config get "),
+            listed("uart.baud  led.mode  hello.text  hello.all  ")
+        );
+        assert_eq!(tab("config set u"), insert("art.baud "));
+        assert_eq!(tab("config set hello."), listed("hello.text  hello.all  "));
+        assert_eq!(tab("config set led.mode f"), insert("ast "));
+        // 没有固定取值的设置项
+        assert_eq!(
+            tab("config set uart.baud "),
+            (Completion::Nothing, "".into())
+        );
+    }
+
+    #[test]
+    fn uart_values() {
+        assert_eq!(tab("uart s"), listed("set  stats  "));
+        assert_eq!(tab("uart set 9"), listed("9600  921600  "));
+        assert_eq!(tab("uart set 11"), insert("5200 "));
+        assert_eq!(tab("uart set 115200 7"), listed("7E1  7O1  "));
+        assert_eq!(tab("uart stats "), insert("reset "));
+    }
+
+    #[test]
+    fn gpio_pins() {
+        let _globals = globals();
+        let pool = Box::leak(Box::new(PinPool::<FakePin>::new()));
+        pool.reserve(PinId::new(3, 8), "usart3");
+        pool.add(PinId::new(1, 0), FakePin::default());
+        pool.add(PinId::new(2, 13), FakePin::default());
+        pool.add(PinId::new(2, 14), FakePin::default());
+        install(pool);
+
+        assert_eq!(tab("gpio m"), insert("ode "));
+        assert_eq!(tab("gpio t"), insert("oggle "));
+        // 别人占着的 PD8 不列；大小写跟着已经输入的部分
+        assert_eq!(tab("gpio get "), insert("P"));
+        assert_eq!(tab("gpio get P"), listed("PB0  PC13  PC14  "));
+        assert_eq!(tab("gpio get p"), listed("pb0  pc13  pc14  "));
+        assert_eq!(tab("gpio get Pc"), insert("1"));
+        assert_eq!(tab("gpio get pB"), insert("0 "));
+        assert_eq!(tab("gpio get pd"), (Completion::Nothing, "".into()));
+        assert_eq!(tab("gpio mode pb0 in"), listed("in  in-up  in-down  "));
+        assert_eq!(tab("gpio mode pb0 o"), listed("out  od  "));
+        assert_eq!(tab("gpio mode pb0 a"), insert("nalog "));
+        assert_eq!(tab("gpio set pb0 "), listed("0  1  "));
+        assert_eq!(tab("gpio set pb0 1 "), (Completion::Nothing, "".into()));
+    }
+
+    #[test]
+    fn list_overflow() {
+        let mut list = CandidateList::<16>::new();
+        let mut candidates = Candidates::new("", Some(&mut list));
+        for c in ["one", "two", "three", "four"] {
+            candidates.add(c);
+        }
+        assert_eq!(list.as_str(), "one  two  ...");
+
+        let mut list = CandidateList::<16>::new();
+        let mut candidates = Candidates::new("", Some(&mut list));
+        for c in ["eleven", "twelve"] {
+            candidates.add(c);
+        }
+        assert_eq!(list.as_str(), "eleven  ...");
+    }
+}
@@ -0,0 +1,191 @@
+//! `config`：查看和修改掉电不丢的设置，见 [`crate::settings`]。
+//!
+//! `config set` 只改内存里的值（有的设置要下次上电才生效），`config save` 才写到 flash。
+
+use embedded_io_async::Write;
+
+use super::args::{ArgError, Args};
+use super::commands::CommandError;
+use super::complete::Candidates;
+use super::output::Reply;
+use crate::settings::{self, Key, Value};
+
+fn key(args: &mut Args<'_>) -> Result<Key, ArgError> {
+    Key::parse(args.str("key")?).ok_or(ArgError::Invalid("key"))
+}
+
+/// `config [list]`、`config get <key>`、`config set <key> <value>`、`config save`、`config reset`
+pub(super) async fn config<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    match args.next_str()? {
+        None | Some("list") => {
+            args.finish()?;
+            let current = settings::with(|s| s.clone());
+            for key in Key::ALL {
+                show(out, key, current.get(key), true).await?;
+            }
+            Ok(())
+        }
+        Some("get") => {
+            let key = key(&mut args)?;
+            args.finish()?;
+            let current = settings::with(|s| s.clone());
+            show(out, key, current.get(key), false).await
+        }
+        Some("set") => {
+            let key = key(&mut args)?;
+            let value = args.str("value")?;
+            args.finish()?;
+            settings::with(|s| s.set(key, value)).map_err(|e| CommandError::Failed(e.as_str()))?;
+            out.text("OK\r\n").await
+        }
+        Some("save") => {
+            args.finish()?;
+            settings::save()
+                .await
+                .map_err(|e| CommandError::Failed(e.as_str()))?;
+            out.text("OK\r\n").await
+        }
+        Some("reset") => {
+            args.finish()?;
+            settings::reset()
+                .await
+                .map_err(|e| CommandError::Failed(e.as_str()))?;
+            out.text("OK, defaults restored\r\n").await
+        }
+        Some(_) => Err(CommandError::Usage),
+    }
+}
+
+/// 输出一项设置，`named` 时前面带上名字
+async fn show<W: Write>(
+    out: &mut Reply<'_, W>,
+    key: Key,
+    value: Value<'_>,
+    named: bool,
+) -> Result<(), CommandError<W::Error>> {
+    let data = out.data().key(key.name());
+    match value {
+        Value::Uint(n) => data.uint(n.into()),
+        Value::Str(s) => data.str(s),
+    }
+    if named {
+        out.text_fmt(format_args!("{:<12}{}\r\n", key.name(), value))
+            .await
+    } else {
+        out.text_fmt(format_args!("{}\r\n", value)).await
+    }
+}
+
+pub(super) fn complete_config(mut args: Args<'_>, candidates: &mut Candidates<'_>) {
+    let (Ok(sub), Ok(key), Ok(rest)) = (args.next_str(), args.next_str(), args.next_str()) else {
+        return;
+    };
+    match (sub, key, rest) {
+        (None, _, _) => {
+            for sub in ["list", "get", "set", "save", "reset"] {
+                candidates.add(sub);
+            }
+        }
+        (Some("get" | "set"), None, _) => {
+            for key in Key::ALL {
+                candidates.add(key.name());
+            }
+        }
+        (Some("set"), Some(key), None) => {
+            for choice in Key::parse(key).map_or(&[][..], Key::choices) {
+                candidates.add(choice);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::string::String;
+    use std::sync::Mutex;
+
+    use embassy_futures::block_on;
+
+    use crate::settings::{self, LedMode, Settings, Storage, StorageError};
+    use crate::shell::cancel::Cancel;
+    use crate::shell::commands::execute;
+    use crate::shell::output::Mode;
+    use crate::testing::{globals, MockTx};
+
+    /// 最近一次保存的设置，`None` 是擦掉了
+    static SAVED: Mutex<Option<Settings>> = Mutex::new(None);
+
+    struct Recorder;
+
+    impl Storage for Recorder {
+        fn save(&mut self, settings: &Settings) -> Result<(), StorageError> {
+            *SAVED.lock().unwrap() = Some(settings.clone());
+            Ok(())
+        }
+
+        fn erase(&mut self) -> Result<(), StorageError> {
+            *SAVED.lock().unwrap() = None;
+            Ok(())
+        }
+    }
+
+    fn exec(mode: Mode, line: &str) -> String {
+        let mut tx = MockTx::default();
+        let mut mode = mode;
+        block_on(execute(&mut tx, &mut mode, &Cancel::new(), line)).unwrap();
+        String::from_utf8(tx.0).unwrap()
+    }
+
+    #[test]
+    fn config() {
+        let _globals = globals();
+        block_on(settings::install(
+            Settings::default(),
+            Box::leak(Box::new(Recorder)),
+        ));
+        let human = |line| exec(Mode::Human, line);
+
+        assert_eq!(
+            human("config"),
+            "uart.baud   115200\r\nled.mode    slow\r\nhello.text  你好！\r\nhello.all   您们好！\r\n"
+        );
+        assert_eq!(human("config set hello.text hey"), "OK\r\n");
+        assert_eq!(human("hello"), "hey\r\n");
+        assert_eq!(human("config set led.mode off"), "OK\r\n");
+        assert_eq!(SAVED.lock().unwrap().as_ref(), None);
+        assert_eq!(human("config save"), "OK\r\n");
+        let saved = SAVED.lock().unwrap().clone().unwrap();
+        assert_eq!(saved.led_mode, LedMode::Off);
+        assert_eq!(saved.greeting, "hey");
+
+        assert_eq!(
+            exec(Mode::Json, "config get led.mode"),
+            "{\"status\":\"ok\",\"code\":0,\"payload\":{\"led.mode\":\"off\"}}\r\n"
+        );
+        assert_eq!(human("config reset"), "OK, defaults restored\r\n");
+        assert_eq!(human("config get led.mode"), "slow\r\n");
+        assert_eq!(SAVED.lock().unwrap().as_ref(), None);
+    }
+
+    #[test]
+    fn errors() {
+        let _globals = globals();
+        let human = |line| exec(Mode::Human, line);
+
+        assert!(human("config get nope").starts_with("Error: invalid <key>"));
+        assert!(human("config frob").starts_with("Usage: config"));
+        assert_eq!(human("config set uart.baud x"), "Error: invalid value\r\n");
+        assert_eq!(
+            human("config set uart.baud 12345"),
+            "Error: invalid value\r\n"
+        );
+        assert_eq!(human("config get uart.baud"), "115200\r\n");
+    }
+}
@@ -0,0 +1,499 @@
+//! 终端里的行编辑：回显、退格/删除、左右移动光标、Ctrl-A/E/U/W/C 和上下翻历史。
+//!
+//! 原来 shell 一次收到多少就当成一条命令，在 minicom、picocom 里敲字时命令被拆得七零八落，
+//! 也看不到自己敲了什么。现在接收到的字节逐个交给 [`LineEditor::push`]，它维护当前行，
+//! 并把终端需要的回显和 VT100 控制序列写到 `out` 里，按下回车时返回 [`Event::Line`]，
+//! 按下 Tab 时返回 [`Event::Complete`]。
+//!
+//! 行里的内容始终是合法的 UTF-8，多字节字符要等所有字节都到齐才会插入，被拆在两次接收里
+//! 也没关系；不合法的字节按 [`Invalid`] 处理，默认换成 �。
+//! 中文这样的宽字符在终端里占两列，移动光标时按两列算。
+//! 不处理超过终端宽度的折行。
+
+use core::fmt::Write;
+
+use heapless::{Deque, String, Vec};
+
+use crate::usart::utf8::{Invalid, Utf8Decoder};
+
+/// [`LineEditor::push`] 的结果
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Event {
+    /// 按下了回车，用 [`LineEditor::line`] 取出这一行
+    Line,
+    /// 按下了 Tab，调用方对光标前的内容做补全，见 [`complete`](super::complete)
+    Complete,
+    /// 按下了 Ctrl-C，正在输入的一行已经丢掉了，调用方重新显示提示符
+    Interrupt,
+}
+
+/// 转义序列解析到哪一步了
+#[derive(Clone, Copy)]
+enum Escape {
+    None,
+    /// 收到了 ESC
+    Esc,
+    /// 收到了 `ESC [`，后面跟着数字参数
+    Csi(u8),
+    /// 收到了 `ESC O`（有的终端 Home/End 这样发）
+    Ss3,
+}
+
+const BELL: &str = "\x07";
+
+/// 最多 `N` 字节一行、记住最近 `H` 行历史的行编辑器
+pub struct LineEditor<const N: usize, const H: usize> {
+    /// 当前行，始终是合法的 UTF-8
+    buf: Vec<u8, N>,
+    /// 光标位置（字节下标，总是在字符边界上）
+    cursor: usize,
+    /// 凑了一半的多字节字符
+    utf8: Utf8Decoder,
+    escape: Escape,
+    /// 上一个字节是 `\r`，紧跟的 `\n` 不再算一次回车
+    after_cr: bool,
+    /// 上一行已经交出去了，下次输入之前清空
+    done: bool,
+    history: Deque<String<N>, H>,
+    /// 正在看第几条历史，0 是最近的一条
+    browsing: Option<usize>,
+    /// 开始翻历史之前正在编辑的内容，翻回来时恢复
+    draft: String<N>,
+}
+
+impl<const N: usize, const H: usize> LineEditor<N, H> {
+    pub const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            cursor: 0,
+            utf8: Utf8Decoder::new(Invalid::Replace),
+            escape: Escape::None,
+            after_cr: false,
+            done: false,
+            history: Deque::new(),
+            browsing: None,
+            draft: String::new(),
+        }
+    }
+
+    /// 当前行的内容。收到 [`Event::Line`] 之后、下一次 `push` 之前，这里是刚输入完的那一行
+    pub fn line(&self) -> &str {
+        text(&self.buf)
+    }
+
+    /// 不合法的 UTF-8 字节怎么显示
+    pub fn set_invalid(&mut self, policy: Invalid) {
+        self.utf8.set_policy(policy);
+    }
+
+    /// 光标前面有几个字节
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// 处理终端发来的一个字节，回显和光标移动写到 `out`
+    pub fn push(&mut self, byte: u8, out: &mut impl Write) -> Option<Event> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        if byte == b'\n' && after_cr {
+            return None;
+        }
+        if self.done {
+            self.done = false;
+            self.buf.clear();
+            self.cursor = 0;
+        }
+
+        match self.escape {
+            Escape::None => {}
+            Escape::Esc => {
+                self.escape = match byte {
+                    b'[' => Escape::Csi(0),
+                    b'O' => Escape::Ss3,
+                    _ => Escape::None,
+                };
+                return None;
+            }
+            Escape::Csi(param) => {
+                if byte.is_ascii_digit() {
+                    let param = param.saturating_mul(10).saturating_add(byte - b'0');
+                    self.escape = Escape::Csi(param);
+                } else {
+                    self.escape = Escape::None;
+                    self.csi(param, byte, out);
+                }
+                return None;
+            }
+            Escape::Ss3 => {
+                self.escape = Escape::None;
+                self.csi(0, byte, out);
+                return None;
+            }
+        }
+
+        // 多字节字符交给解码器。解码器复制一份出来用，闭包里才能调用 self.insert
+        if !byte.is_ascii() {
+            let mut utf8 = self.utf8;
+            utf8.push(byte, |c| self.insert(c, out));
+            self.utf8 = utf8;
+            return None;
+        }
+        // 没凑齐就来了 ASCII 字节，凑了一半的按不合法处理
+        if self.utf8.is_pending() {
+            let mut utf8 = self.utf8;
+            utf8.finish(|c| self.insert(c, out));
+            self.utf8 = utf8;
+        }
+
+        match byte {
+            b'\r' | b'\n' => return Some(self.enter(out)),
+            0x1B => self.escape = Escape::Esc,
+            0x01 => self.home(out),
+            0x05 => self.end(out),
+            0x15 => self.kill_to_start(out),
+            0x17 => self.kill_word(out),
+            0x08 | 0x7F => self.backspace(out),
+            b'\t' => return Some(Event::Complete),
+            0x03 => return Some(self.interrupt(out)),
+            0x20..=0x7E => self.insert(byte as char, out),
+            // 其他控制字符
+            _ => {}
+        }
+        None
+    }
+
+    fn csi(&mut self, param: u8, byte: u8, out: &mut impl Write) {
+        match (byte, param) {
+            (b'A', _) => self.history_prev(out),
+            (b'B', _) => self.history_next(out),
+            (b'C', _) => self.right(out),
+            (b'D', _) => self.left(out),
+            (b'H', _) | (b'~', 1 | 7) => self.home(out),
+            (b'F', _) | (b'~', 4 | 8) => self.end(out),
+            (b'~', 3) => self.delete(out),
+            _ => {}
+        }
+    }
+
+    fn enter(&mut self, out: &mut impl Write) -> Event {
+        let _ = out.write_str("\r\n");
+        self.escape = Escape::None;
+        self.browsing = None;
+        self.done = true;
+
+        let line = text(&self.buf);
+        let repeated = self.history.back().map(|s| s.as_str()) == Some(line);
+        if !line.trim().is_empty() && !repeated {
+            if self.history.is_full() {
+                self.history.pop_front();
+            }
+            let mut entry = String::new();
+            let _ = entry.push_str(line);
+            let _ = self.history.push_back(entry);
+        }
+        Event::Line
+    }
+
+    fn interrupt(&mut self, out: &mut impl Write) -> Event {
+        let _ = out.write_str("^C\r\n");
+        self.buf.clear();
+        self.cursor = 0;
+        self.browsing = None;
+        Event::Interrupt
+    }
+
+    fn insert(&mut self, c: char, out: &mut impl Write) {
+        let mut bytes = [0; 4];
+        self.insert_str(c.encode_utf8(&mut bytes), out);
+    }
+
+    /// 在光标处插入一段文本（比如补全的结果），放不下就整段不插并响铃
+    pub fn insert_str(&mut self, s: &str, out: &mut impl Write) {
+        if self.buf.len() + s.len() > N {
+            let _ = out.write_str(BELL);
+            return;
+        }
+        let start = self.cursor;
+        let _ = self.buf.extend_from_slice(s.as_bytes());
+        self.buf[start..].rotate_right(s.len());
+        self.cursor += s.len();
+        let _ = out.write_str(&self.line()[start..]);
+        back(out, width(&self.line()[self.cursor..]));
+    }
+
+    /// 重新显示整行并把光标放回原处，用在提示符被别的输出冲掉之后
+    pub fn redraw(&self, out: &mut impl Write) {
+        let _ = out.write_str(self.line());
+        back(out, width(&self.line()[self.cursor..]));
+    }
+
+    /// 删掉 `start..end`，光标移到 `start`，重画后半行
+    fn remove(&mut self, start: usize, end: usize, out: &mut impl Write) {
+        back(out, width(&self.line()[start..self.cursor]));
+        self.buf.copy_within(end.., start);
+        self.buf.truncate(self.buf.len() - (end - start));
+        self.cursor = start;
+        self.redraw_tail(out);
+    }
+
+    /// 从光标处重画到行尾，清掉行尾多出来的字符，再把光标移回来
+    fn redraw_tail(&self, out: &mut impl Write) {
+        let tail = &self.line()[self.cursor..];
+        let _ = out.write_str(tail);
+        let _ = out.write_str("\x1b[K");
+        back(out, width(tail));
+    }
+
+    fn prev_boundary(&self) -> Option<usize> {
+        self.line()[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map(|(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> Option<usize> {
+        let c = self.line()[self.cursor..].chars().next()?;
+        Some(self.cursor + c.len_utf8())
+    }
+
+    fn backspace(&mut self, out: &mut impl Write) {
+        if let Some(start) = self.prev_boundary() {
+            self.remove(start, self.cursor, out);
+        }
+    }
+
+    fn delete(&mut self, out: &mut impl Write) {
+        if let Some(end) = self.next_boundary() {
+            self.remove(self.cursor, end, out);
+        }
+    }
+
+    fn left(&mut self, out: &mut impl Write) {
+        if let Some(start) = self.prev_boundary() {
+            back(out, width(&self.line()[start..self.cursor]));
+            self.cursor = start;
+        }
+    }
+
+    fn right(&mut self, out: &mut impl Write) {
+        if let Some(end) = self.next_boundary() {
+            forward(out, width(&self.line()[self.cursor..end]));
+            self.cursor = end;
+        }
+    }
+
+    fn home(&mut self, out: &mut impl Write) {
+        back(out, width(&self.line()[..self.cursor]));
+        self.cursor = 0;
+    }
+
+    fn end(&mut self, out: &mut impl Write) {
+        forward(out, width(&self.line()[self.cursor..]));
+        self.cursor = self.buf.len();
+    }
+
+    /// Ctrl-U：删掉光标前面的所有内容
+    fn kill_to_start(&mut self, out: &mut impl Write) {
+        self.remove(0, self.cursor, out);
+    }
+
+    /// Ctrl-W：删掉光标前面的一个词（连同它后面的空白）
+    fn kill_word(&mut self, out: &mut impl Write) {
+        let before = self.line()[..self.cursor].trim_end();
+        // 空白不一定是一个字节，比如全角空格 U+3000
+        let start = before
+            .char_indices()
+            .rfind(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        self.remove(start, self.cursor, out);
+    }
+
+    /// 用 `line` 替换整行，光标放到行尾
+    fn replace(&mut self, line: &str, out: &mut impl Write) {
+        self.home(out);
+        let _ = out.write_str("\x1b[K");
+        self.buf.clear();
+        let _ = self.buf.extend_from_slice(line.as_bytes());
+        self.cursor = self.buf.len();
+        let _ = out.write_str(line);
+    }
+
+    /// 第 `index` 条历史，0 是最近的一条
+    fn entry(&self, index: usize) -> String<N> {
+        self.history
+            .iter()
+            .rev()
+            .nth(index)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn history_prev(&mut self, out: &mut impl Write) {
+        let index = self.browsing.map_or(0, |i| i + 1);
+        if index >= self.history.len() {
+            let _ = out.write_str(BELL);
+            return;
+        }
+        if self.browsing.is_none() {
+            self.draft.clear();
+            let _ = self.draft.push_str(text(&self.buf));
+        }
+        self.browsing = Some(index);
+        let entry = self.entry(index);
+        self.replace(&entry, out);
+    }
+
+    fn history_next(&mut self, out: &mut impl Write) {
+        match self.browsing {
+            None => {
+                let _ = out.write_str(BELL);
+            }
+            Some(0) => {
+                self.browsing = None;
+                let draft = self.draft.clone();
+                self.replace(&draft, out);
+            }
+            Some(i) => {
+                self.browsing = Some(i - 1);
+                let entry = self.entry(i - 1);
+                self.replace(&entry, out);
+            }
+        }
+    }
+}
+
+impl<const N: usize, const H: usize> Default for LineEditor<N, H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 行缓冲区里的内容，插入删除都按整个字符进行，所以总是合法的 UTF-8
+fn text(buf: &[u8]) -> &str {
+    core::str::from_utf8(buf).unwrap_or_default()
+}
+
+/// 字符串在终端里占几列：中日韩文字和全角符号占两列，其他占一列
+pub fn width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// 光标左移 `cols` 列
+fn back(out: &mut impl Write, cols: usize) {
+    if cols > 0 {
+        let _ = write!(out, "\x1b[{}D", cols);
+    }
+}
+
+/// 光标右移 `cols` 列
+fn forward(out: &mut impl Write, cols: usize) {
+    if cols > 0 {
+        let _ = write!(out, "\x1b[{}C", cols);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    /// 逐字节喂进去，返回回显和最后一个事件
+    fn feed<const N: usize, const H: usize>(
+        editor: &mut LineEditor<N, H>,
+        bytes: &[u8],
+    ) -> (String, Option<Event>) {
+        let mut out = String::new();
+        let mut event = None;
+        for &byte in bytes {
+            event = editor.push(byte, &mut out).or(event);
+        }
+        (out, event)
+    }
+
+    #[test]
+    fn editing() {
+        let mut editor = LineEditor::<32, 3>::new();
+        assert_eq!(feed(&mut editor, b"helo"), ("helo".into(), None));
+        assert_eq!(feed(&mut editor, b"\x1b[D").0, "\x1b[1D");
+        assert_eq!(feed(&mut editor, b"l").0, "lo\x1b[1D");
+        assert_eq!(editor.line(), "hello");
+        assert_eq!(feed(&mut editor, b"\x7f").0, "\x1b[1Do\x1b[K\x1b[1D");
+        assert_eq!(editor.line(), "helo");
+        feed(&mut editor, b"\x05 world foo\x17");
+        assert_eq!(editor.line(), "helo world ");
+        assert_eq!(
+            feed(&mut editor, b"\r\n"),
+            ("\r\n".into(), Some(Event::Line))
+        );
+        assert_eq!(editor.line(), "helo world ");
+    }
+
+    #[test]
+    fn wide_chars() {
+        let mut editor = LineEditor::<32, 3>::new();
+        let text = "你好！".as_bytes();
+        assert_eq!(feed(&mut editor, &text[..4]).0, "你");
+        assert_eq!(feed(&mut editor, &text[4..]).0, "好！");
+        assert_eq!(feed(&mut editor, b"\x1b[D\x1b[D").0, "\x1b[2D\x1b[2D");
+        assert_eq!(feed(&mut editor, b"\x1b[3~").0, "！\x1b[K\x1b[2D");
+        assert_eq!(editor.line(), "你！");
+        assert_eq!(feed(&mut editor, b"\x01").0, "\x1b[2D");
+        feed(&mut editor, b"\x1b[F\x15");
+        assert_eq!(editor.line(), "");
+    }
+
+    #[test]
+    fn kill_word_after_wide_space() {
+        let mut editor = LineEditor::<32, 3>::new();
+        feed(&mut editor, "你\u{3000}好".as_bytes());
+        feed(&mut editor, b"\x17");
+        assert_eq!(editor.line(), "你\u{3000}");
+        feed(&mut editor, b"\x17");
+        assert_eq!(editor.line(), "");
+
+        feed(&mut editor, "ab \u{3000} cd\u{3000}".as_bytes());
+        feed(&mut editor, b"\x17");
+        assert_eq!(editor.line(), "ab \u{3000} ");
+    }
+
+    #[test]
+    fn history() {
+        let mut editor = LineEditor::<32, 3>::new();
+        feed(&mut editor, b"one\rtwo\r");
+        feed(&mut editor, b"dr\x1b[A");
+        assert_eq!(editor.line(), "two");
+        feed(&mut editor, b"\x1b[A");
+        assert_eq!(editor.line(), "one");
+        assert_eq!(feed(&mut editor, b"\x1b[A").0, "\x07");
+        feed(&mut editor, b"\x1b[B\x1b[B");
+        assert_eq!(editor.line(), "dr");
+    }
+
+    #[test]
+    fn full_line() {
+        let mut editor = LineEditor::<4, 1>::new();
+        assert_eq!(feed(&mut editor, b"abcde").0, "abcd\x07");
+    }
+}
@@ -0,0 +1,302 @@
+//! `gpio`：在 shell 里配置和读写引脚。
+//!
+//! 只能动板级代码放进引脚池的引脚（见 [`crate::gpio`]），被串口、USB、以太网占着的引脚
+//! 会报 `Error: in use by ...`。引脚先用 `gpio mode` 配置，之后才能 `set`/`get`/`toggle`/`watch`，
+//! 用完 `gpio release` 放回池子。
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::Write;
+
+use super::args::{ArgError, Args};
+use super::commands::CommandError;
+use super::complete::Candidates;
+use super::output::{Reply, Status};
+use crate::gpio::{self, GpioError, PinId, PinMode, PinState};
+
+/// 子命令，后面都跟着 `<pin>`
+const SUBCOMMANDS: [&str; 6] = ["mode", "set", "get", "toggle", "watch", "release"];
+
+/// `gpio watch` 多久看一次电平
+const WATCH_POLL: Duration = Duration::from_millis(1);
+
+impl<E> From<GpioError> for CommandError<E> {
+    fn from(e: GpioError) -> Self {
+        match e {
+            GpioError::InUse(owner) => CommandError::InUse(owner),
+            e => CommandError::Failed(e.as_str()),
+        }
+    }
+}
+
+fn pin(args: &mut Args<'_>) -> Result<PinId, ArgError> {
+    PinId::parse(args.str("pin")?).ok_or(ArgError::Invalid("pin"))
+}
+
+/// `gpio`：列出 shell 配置过的和被占用的引脚；
+/// `gpio mode <pin> <mode>`、`gpio set <pin> <0|1>`、`gpio get|toggle|watch|release <pin>`
+pub(super) async fn gpio<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    let Some(sub) = args.next_str()? else {
+        return list(out).await;
+    };
+    // 先认子命令，`gpio foo` 该报用法而不是缺 `<pin>`
+    if !SUBCOMMANDS.contains(&sub) {
+        return Err(CommandError::Usage);
+    }
+    let id = pin(&mut args)?;
+
+    match sub {
+        "mode" => {
+            let mode = PinMode::parse(args.str("mode")?).ok_or(ArgError::Invalid("mode"))?;
+            args.finish()?;
+            gpio::with(|b| b.configure(id, mode))?;
+            out.text("OK\r\n").await
+        }
+        "set" => {
+            let high = args.bool("level")?;
+            args.finish()?;
+            gpio::with(|b| b.write(id, high))?;
+            out.text("OK\r\n").await
+        }
+        "get" => {
+            args.finish()?;
+            let level = gpio::with(|b| b.read(id))?;
+            level_reply(out, id, level).await
+        }
+        "toggle" => {
+            args.finish()?;
+            let level = gpio::with(|b| b.toggle(id))?;
+            level_reply(out, id, level).await
+        }
+        "release" => {
+            args.finish()?;
+            gpio::with(|b| b.release(id))?;
+            out.text("OK\r\n").await
+        }
+        "watch" => {
+            args.finish()?;
+            watch(out, id).await
+        }
+        _ => Err(CommandError::Usage),
+    }
+}
+
+async fn level_reply<W: Write>(
+    out: &mut Reply<'_, W>,
+    id: PinId,
+    level: bool,
+) -> Result<(), CommandError<W::Error>> {
+    let data = out.data();
+    data.key("pin").str_fmt(format_args!("{}", id));
+    data.key("level").bool(level);
+    out.text_fmt(format_args!("{}: {}\r\n", id, u8::from(level)))
+        .await
+}
+
+async fn list<W: Write>(out: &mut Reply<'_, W>) -> Result<(), CommandError<W::Error>> {
+    out.data().key("pins").list();
+    for id in PinId::all() {
+        let (state, level) = gpio::with(|b| match b.state(id) {
+            Some(PinState::Claimed(mode)) => Ok((PinState::Claimed(mode), b.read(id).ok())),
+            state => Ok((state.unwrap_or(PinState::Free), None)),
+        })?;
+        match state {
+            PinState::Free => continue,
+            PinState::Owned(owner) => {
+                out.text_fmt(format_args!("{:<6}{}\r\n", id, owner)).await?;
+                let data = out.data();
+                data.map();
+                data.key("pin").str_fmt(format_args!("{}", id));
+                data.key("owner").str(owner);
+                data.end();
+            }
+            PinState::Claimed(mode) => {
+                let level = u8::from(level.unwrap_or(false));
+                out.text_fmt(format_args!("{:<6}{:<8}{}\r\n", id, mode.as_str(), level))
+                    .await?;
+                let data = out.data();
+                data.map();
+                data.key("pin").str_fmt(format_args!("{}", id));
+                data.key("mode").str(mode.as_str());
+                data.key("level").bool(level != 0);
+                data.end();
+            }
+        }
+    }
+    out.data().end();
+    Ok(())
+}
+
+/// 电平一变就输出一行（机器模式下一个结果对象），直到按 Ctrl-C
+async fn watch<W: Write>(out: &mut Reply<'_, W>, id: PinId) -> Result<(), CommandError<W::Error>> {
+    let start = Instant::now();
+    let mut last = None;
+    loop {
+        let level = gpio::with(|b| b.read(id))?;
+        if last != Some(level) {
+            last = Some(level);
+            change(out, id, level, start.elapsed()).await?;
+        }
+        out.cancellable(Timer::after(WATCH_POLL)).await?;
+    }
+}
+
+async fn change<W: Write>(
+    out: &mut Reply<'_, W>,
+    id: PinId,
+    level: bool,
+    elapsed: Duration,
+) -> Result<(), CommandError<W::Error>> {
+    let mode = out.mode();
+    let cancel = out.cancel_signal();
+    let mut tx = out.nested();
+    let mut event = Reply::new(&mut tx, mode, cancel);
+
+    let ms = elapsed.as_millis();
+    event
+        .text_fmt(format_args!(
+            "{:>8} ms  {}: {}\r\n",
+            ms,
+            id,
+            u8::from(level)
+        ))
+        .await?;
+    let data = event.data();
+    data.key("pin").str_fmt(format_args!("{}", id));
+    data.key("level").bool(level);
+    data.key("ms").uint(ms);
+    event
+        .finish(Status::Ok, None, None)
+        .await
+        .map_err(CommandError::Io)
+}
+
+pub(super) fn complete_gpio(mut args: Args<'_>, candidates: &mut Candidates<'_>) {
+    let (Ok(sub), Ok(pin), Ok(rest)) = (args.next_str(), args.next_str(), args.next_str()) else {
+        return;
+    };
+    match (sub, pin, rest) {
+        (None, _, _) => {
+            for sub in SUBCOMMANDS {
+                candidates.add(sub);
+            }
+        }
+        (Some(_), None, _) => complete_pin(candidates),
+        (Some("mode"), Some(_), None) => {
+            for mode in PinMode::ALL {
+                candidates.add(mode.as_str());
+            }
+        }
+        (Some("set"), Some(_), None) => {
+            candidates.add("0");
+            candidates.add("1");
+        }
+        _ => {}
+    }
+}
+
+/// 补全池子里的引脚名，大小写跟着已经输入的部分（`pc`、`PC`、`Pc` 都行）
+fn complete_pin(candidates: &mut Candidates<'_>) {
+    let prefix = candidates.prefix().as_bytes();
+    let p = char::from(prefix.first().copied().unwrap_or(b'P'));
+    let lower = prefix
+        .get(1)
+        .map_or(p.is_ascii_lowercase(), u8::is_ascii_lowercase);
+    for id in PinId::all() {
+        let usable = gpio::with(|b| {
+            Ok(matches!(
+                b.state(id),
+                Some(PinState::Free | PinState::Claimed(_))
+            ))
+        });
+        if usable != Ok(true) {
+            continue;
+        }
+        let port = match lower {
+            true => id.port_name().to_ascii_lowercase(),
+            false => id.port_name(),
+        };
+        candidates.add_fmt(format_args!("{}{}{}", p, port, id.pin()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::string::String;
+
+    use embassy_futures::block_on;
+
+    use crate::gpio::{install, PinId, PinPool};
+    use crate::shell::cancel::Cancel;
+    use crate::shell::commands::execute;
+    use crate::shell::output::Mode;
+    use crate::testing::{globals, FakePin, MockTx};
+
+    fn exec(mode: Mode, line: &str) -> String {
+        let mut tx = MockTx::default();
+        let mut mode = mode;
+        block_on(execute(&mut tx, &mut mode, &Cancel::new(), line)).unwrap();
+        String::from_utf8(tx.0).unwrap()
+    }
+
+    fn install_pool() {
+        let pool = Box::leak(Box::new(PinPool::<FakePin>::new()));
+        pool.reserve(PinId::new(3, 8), "usart3");
+        pool.reserve(PinId::new(3, 9), "usart3");
+        pool.add(PinId::new(1, 0), FakePin::default());
+        pool.add(PinId::new(2, 13), FakePin::default());
+        install(pool);
+    }
+
+    #[test]
+    fn commands() {
+        let _globals = globals();
+        install_pool();
+        let human = |line| exec(Mode::Human, line);
+
+        assert_eq!(human("gpio mode PD8 out"), "Error: in use by usart3\r\n");
+        assert_eq!(human("gpio get pb1"), "Error: no such pin\r\n");
+        assert_eq!(
+            human("gpio get pb0"),
+            "Error: pin not configured, use gpio mode first\r\n"
+        );
+        assert_eq!(human("gpio mode pb0 out"), "OK\r\n");
+        assert_eq!(human("gpio set pb0 1"), "OK\r\n");
+        assert_eq!(human("gpio get pb0"), "PB0: 1\r\n");
+        assert_eq!(human("gpio toggle pb0"), "PB0: 0\r\n");
+        assert_eq!(human("gpio mode pc13 in-up"), "OK\r\n");
+        assert_eq!(human("gpio set pc13 1"), "Error: pin is not an output\r\n");
+        assert_eq!(
+            human("gpio"),
+            "PB0   out     0\r\nPC13  in-up   1\r\nPD8   usart3\r\nPD9   usart3\r\n"
+        );
+        assert_eq!(
+            exec(Mode::Json, "gpio get pc13"),
+            "{\"status\":\"ok\",\"code\":0,\"payload\":{\"pin\":\"PC13\",\"level\":true}}\r\n"
+        );
+        assert_eq!(human("gpio release pc13"), "OK\r\n");
+        assert_eq!(
+            human("gpio release pc13"),
+            "Error: pin not configured, use gpio mode first\r\n"
+        );
+    }
+
+    #[test]
+    fn argument_errors() {
+        let _globals = globals();
+        install_pool();
+        let human = |line| exec(Mode::Human, line);
+
+        // 不认识的子命令报用法，不管后面有没有引脚
+        assert!(human("gpio foo").starts_with("Usage: gpio"));
+        assert!(human("gpio frob pc13").starts_with("Usage: gpio"));
+        assert!(human("gpio get").starts_with("Error: missing <pin>"));
+        assert!(human("gpio get PA+1").starts_with("Error: invalid <pin>"));
+        assert!(human("gpio mode pc13 bogus").starts_with("Error: invalid <mode>"));
+    }
+}
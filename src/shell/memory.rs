@@ -0,0 +1,445 @@
+//! `peek`、`poke`、`hexdump`：不接调试器直接看寄存器和内存。
+//!
+//! 只能访问 [`REGIONS`] 里列出的地址：`memory.x` 里的 FLASH、RAM、RAM_D3，以及各条总线上
+//! 外设占用的地址范围。FLASH 只读（写 FLASH 要经过 FLASH 控制器）。
+//!
+//! 外设地址范围里还有没挂外设的空洞，时钟没开的外设也可能回总线错误，所以真正访问时
+//! 先屏蔽总线错误（`FAULTMASK` 加 `CCR.BFHFNMIGN`），访问完看 `BFSR` 有没有记下错误，
+//! 有的话返回 `Error: bus fault`，不会进 HardFault。
+//!
+//! 注意有些寄存器读一下就有副作用（比如串口的 `RDR`、一些状态寄存器的读清零位），
+//! `hexdump` 按 32 位字读，外设区里也别随便扫。
+
+use core::fmt::Write as _;
+
+use embedded_io_async::Write;
+use heapless::String;
+
+use super::args::{ArgError, Args};
+use super::commands::CommandError;
+use super::complete::Candidates;
+use super::output::Reply;
+
+/// 一段可以访问的地址
+pub struct Region {
+    pub name: &'static str,
+    pub start: u32,
+    /// 字节数
+    pub len: u32,
+    pub writable: bool,
+}
+
+impl Region {
+    /// `[addr, addr + len)` 整个落在这一段里
+    fn contains(&self, addr: u32, len: u32) -> bool {
+        let offset = addr.wrapping_sub(self.start);
+        addr >= self.start && offset <= self.len && len <= self.len - offset
+    }
+}
+
+const fn region(name: &'static str, start: u32, end: u32, writable: bool) -> Region {
+    Region {
+        name,
+        start,
+        len: end - start,
+        writable,
+    }
+}
+
+/// 允许访问的地址，前三段和 `memory.x` 一致（FLASH 包括留给设置的最后两个扇区），
+/// 外设部分按 RM0433 的地址表
+pub static REGIONS: &[Region] = &[
+    region("FLASH", 0x0800_0000, 0x0800_0000 + 2048 * 1024, false),
+    region("RAM", 0x2400_0000, 0x2400_0000 + 512 * 1024, true),
+    region("RAM_D3", 0x3800_0000, 0x3800_0000 + 128 * 1024, true),
+    region("APB1", 0x4000_0000, 0x4000_D400, true),
+    region("APB2", 0x4001_0000, 0x4001_8000, true),
+    region("AHB1", 0x4002_0000, 0x4008_0000, true),
+    region("AHB2", 0x4802_0000, 0x4802_2C00, true),
+    region("APB3", 0x5000_0000, 0x5000_4000, true),
+    region("AHB3", 0x5200_0000, 0x5200_9000, true),
+    region("APB4", 0x5800_0000, 0x5800_6C00, true),
+    region("AHB4", 0x5802_0000, 0x5802_6800, true),
+    region("DBGMCU", 0x5C00_1000, 0x5C00_1400, true),
+    // Cortex-M7 自己的 SysTick、NVIC、SCB 等
+    region("PPB", 0xE000_0000, 0xE010_0000, true),
+];
+
+/// `[addr, addr + len)` 所在的那一段，跨了两段或者不在任何一段里都不行
+pub fn find_region(addr: u32, len: u32) -> Option<&'static Region> {
+    REGIONS.iter().find(|r| r.contains(addr, len))
+}
+
+/// 一次访问几位
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Width {
+    W8,
+    W16,
+    W32,
+}
+
+impl Width {
+    /// `8`、`16` 或 `32`
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "8" => Some(Width::W8),
+            "16" => Some(Width::W16),
+            "32" => Some(Width::W32),
+            _ => None,
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        self.bytes() * 8
+    }
+
+    pub fn bytes(self) -> u32 {
+        match self {
+            Width::W8 => 1,
+            Width::W16 => 2,
+            Width::W32 => 4,
+        }
+    }
+
+    fn max(self) -> u32 {
+        u32::MAX >> (32 - self.bits())
+    }
+}
+
+/// 检查地址能不能按 `width` 访问，返回给人看的错误
+fn check(addr: u32, width: Width, write: bool) -> Result<(), &'static str> {
+    if !addr.is_multiple_of(width.bytes()) {
+        return Err("address not aligned");
+    }
+    let region = find_region(addr, width.bytes()).ok_or("address not in any memory region")?;
+    if write && !region.writable {
+        return Err("region is read-only");
+    }
+    Ok(())
+}
+
+/// 读一个值，地址要先用 [`check`] 检查过
+fn read(addr: u32, width: Width) -> Result<u32, &'static str> {
+    // SAFETY: 地址在允许的范围里并且对齐了，总线错误由 `guarded` 接住
+    guarded(|| unsafe {
+        match width {
+            Width::W8 => u32::from(core::ptr::read_volatile(addr as *const u8)),
+            Width::W16 => u32::from(core::ptr::read_volatile(addr as *const u16)),
+            Width::W32 => core::ptr::read_volatile(addr as *const u32),
+        }
+    })
+}
+
+/// 写一个值，地址要先用 [`check`] 检查过
+fn write(addr: u32, width: Width, value: u32) -> Result<(), &'static str> {
+    // SAFETY: 同 `read`；写到哪里是用户自己决定的
+    guarded(|| unsafe {
+        match width {
+            Width::W8 => core::ptr::write_volatile(addr as *mut u8, value as u8),
+            Width::W16 => core::ptr::write_volatile(addr as *mut u16, value as u16),
+            Width::W32 => core::ptr::write_volatile(addr as *mut u32, value),
+        }
+    })
+}
+
+/// 屏蔽总线错误执行 `access`，出了总线错误返回 `Err`。
+/// 执行期间连 HardFault 以外的异常都被屏蔽了，`access` 要尽量短
+#[cfg(target_os = "none")]
+fn guarded<T>(access: impl FnOnce() -> T) -> Result<T, &'static str> {
+    use cortex_m::peripheral::SCB;
+
+    /// `CCR.BFHFNMIGN`：优先级为 -1 的代码忽略数据访问的总线错误
+    const BFHFNMIGN: u32 = 1 << 8;
+    /// `CFSR` 里的 `BFSR`，写 1 清零
+    const BFSR: u32 = 0xFF << 8;
+
+    // SAFETY: 只在这段代码里临时改 CCR 和 FAULTMASK，返回前恢复
+    unsafe {
+        let scb = &*SCB::PTR;
+        scb.cfsr.write(BFSR);
+        core::arch::asm!("cpsid f");
+        scb.ccr.modify(|r| r | BFHFNMIGN);
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+
+        let value = access();
+        // 等写缓冲里的写操作真正完成，不精确的总线错误也要在这里记下来
+        cortex_m::asm::dsb();
+
+        scb.ccr.modify(|r| r & !BFHFNMIGN);
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+        core::arch::asm!("cpsie f");
+
+        if scb.cfsr.read() & BFSR != 0 {
+            scb.cfsr.write(BFSR);
+            return Err("bus fault");
+        }
+        Ok(value)
+    }
+}
+
+/// 主机上没有这些地址，一律当访问失败
+#[cfg(not(target_os = "none"))]
+fn guarded<T>(_access: impl FnOnce() -> T) -> Result<T, &'static str> {
+    Err("bus fault")
+}
+
+fn opt_width(args: &mut Args<'_>) -> Result<Width, ArgError> {
+    match args.next_str()? {
+        None => Ok(Width::W32),
+        Some(s) => Width::parse(s).ok_or(ArgError::Invalid("width")),
+    }
+}
+
+/// `peek <addr> [8|16|32]`：读一个值，默认 32 位
+pub(super) async fn peek<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    let addr: u32 = args.int("addr")?;
+    let width = opt_width(&mut args)?;
+    args.finish()?;
+
+    check(addr, width, false).map_err(CommandError::Failed)?;
+    let value = read(addr, width).map_err(CommandError::Failed)?;
+
+    let digits = width.bytes() as usize * 2;
+    out.text_fmt(format_args!(
+        "{:#010x}: {:#0w$x}\r\n",
+        addr,
+        value,
+        w = digits + 2
+    ))
+    .await?;
+    let data = out.data();
+    data.key("addr").uint(addr.into());
+    data.key("width").uint(width.bits().into());
+    data.key("value").uint(value.into());
+    Ok(())
+}
+
+/// `poke <addr> <value> [8|16|32]`：写一个值，默认 32 位
+pub(super) async fn poke<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    let addr: u32 = args.int("addr")?;
+    let value: u32 = args.int("value")?;
+    let width = opt_width(&mut args)?;
+    args.finish()?;
+
+    if value > width.max() {
+        return Err(ArgError::Invalid("value").into());
+    }
+    check(addr, width, true).map_err(CommandError::Failed)?;
+    write(addr, width, value).map_err(CommandError::Failed)?;
+
+    out.text("OK\r\n").await
+}
+
+/// `hexdump` 默认读多少字节
+const DUMP_DEFAULT: u32 = 64;
+/// `hexdump` 最多读多少字节，机器模式下的十六进制字符串要放得进 payload
+const DUMP_MAX: u32 = 256;
+
+/// `hexdump <addr> [len]`：按 32 位字读一段，每行 16 字节，后面附上 ASCII。
+/// 地址要 4 字节对齐，长度向上凑成 4 的倍数
+pub(super) async fn hexdump<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    let addr: u32 = args.int("addr")?;
+    let len: u32 = args.opt_int("len")?.unwrap_or(DUMP_DEFAULT);
+    args.finish()?;
+
+    if len == 0 || len > DUMP_MAX {
+        return Err(ArgError::Invalid("len").into());
+    }
+    let len = len.next_multiple_of(4);
+    if !addr.is_multiple_of(4) {
+        return Err(CommandError::Failed("address not aligned"));
+    }
+    find_region(addr, len).ok_or(CommandError::Failed("address not in any memory region"))?;
+
+    // 先全部读出来，出错时什么都不输出
+    let mut bytes = [0u8; DUMP_MAX as usize];
+    let bytes = &mut bytes[..len as usize];
+    for (i, word) in bytes.chunks_exact_mut(4).enumerate() {
+        let value = read(addr + i as u32 * 4, Width::W32).map_err(CommandError::Failed)?;
+        word.copy_from_slice(&value.to_le_bytes());
+    }
+
+    for (i, row) in bytes.chunks(16).enumerate() {
+        let mut line = String::<80>::new();
+        let _ = write!(line, "{:08x}:", addr + i as u32 * 16);
+        for b in row {
+            let _ = write!(line, " {:02x}", b);
+        }
+        for _ in row.len()..16 {
+            let _ = line.push_str("   ");
+        }
+        let _ = line.push_str("  |");
+        for &b in row {
+            let c = if b.is_ascii_graphic() || b == b' ' {
+                char::from(b)
+            } else {
+                '.'
+            };
+            let _ = line.push(c);
+        }
+        let _ = line.push_str("|\r\n");
+        out.text(&line).await?;
+    }
+
+    let mut hex = String::<{ DUMP_MAX as usize * 2 }>::new();
+    for b in bytes.iter() {
+        let _ = write!(hex, "{:02x}", b);
+    }
+    let data = out.data();
+    data.key("addr").uint(addr.into());
+    data.key("len").uint(len.into());
+    data.key("hex").str(&hex);
+    Ok(())
+}
+
+/// 地址补全成各段的起始地址
+fn complete_addr(candidates: &mut Candidates<'_>) {
+    for r in REGIONS {
+        candidates.add_fmt(format_args!("{:#010x}", r.start));
+    }
+}
+
+/// 已经输完了几个参数
+fn count(mut args: Args<'_>) -> Option<usize> {
+    let mut n = 0;
+    while args.next_str().ok()?.is_some() {
+        n += 1;
+    }
+    Some(n)
+}
+
+fn complete_width(candidates: &mut Candidates<'_>) {
+    for w in ["8", "16", "32"] {
+        candidates.add(w);
+    }
+}
+
+pub(super) fn complete_peek(args: Args<'_>, candidates: &mut Candidates<'_>) {
+    match count(args) {
+        Some(0) => complete_addr(candidates),
+        Some(1) => complete_width(candidates),
+        _ => {}
+    }
+}
+
+pub(super) fn complete_poke(args: Args<'_>, candidates: &mut Candidates<'_>) {
+    match count(args) {
+        Some(0) => complete_addr(candidates),
+        Some(2) => complete_width(candidates),
+        _ => {}
+    }
+}
+
+pub(super) fn complete_hexdump(args: Args<'_>, candidates: &mut Candidates<'_>) {
+    if count(args) == Some(0) {
+        complete_addr(candidates);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::shell::cancel::Cancel;
+    use crate::shell::commands::execute;
+    use crate::shell::output::Mode;
+    use crate::testing::MockTx;
+
+    fn human(line: &str) -> String {
+        let mut tx = MockTx::default();
+        let mut mode = Mode::Human;
+        block_on(execute(&mut tx, &mut mode, &Cancel::new(), line)).unwrap();
+        String::from_utf8(tx.0).unwrap()
+    }
+
+    #[test]
+    fn regions() {
+        let ram_end = 0x2400_0000 + 512 * 1024;
+        assert_eq!(find_region(0x2400_0000, 4).unwrap().name, "RAM");
+        assert_eq!(find_region(ram_end - 4, 4).unwrap().name, "RAM");
+        // 越过一段的末尾
+        assert!(find_region(ram_end - 2, 4).is_none());
+        assert!(find_region(ram_end, 1).is_none());
+        // APB1 和 APB2 之间有空洞，跨过去也不行
+        assert!(find_region(0x4000_D3FC, 8).is_none());
+        // 从一段开头往前多读一点
+        assert!(find_region(0x4000_FFFC, 8).is_none());
+        assert!(find_region(0xE00F_FFFC, 8).is_none());
+        // 长度大到会绕回去
+        assert!(find_region(0x2400_0000, u32::MAX).is_none());
+        assert!(find_region(0, 4).is_none());
+    }
+
+    #[test]
+    fn checks() {
+        assert_eq!(check(0x2400_0001, Width::W8, true), Ok(()));
+        assert_eq!(
+            check(0x2400_0001, Width::W16, false),
+            Err("address not aligned")
+        );
+        assert_eq!(check(0x2400_0002, Width::W16, true), Ok(()));
+        assert_eq!(
+            check(0x2400_0002, Width::W32, false),
+            Err("address not aligned")
+        );
+        assert_eq!(check(0x2400_0004, Width::W32, true), Ok(()));
+
+        assert_eq!(check(0x0800_0000, Width::W32, false), Ok(()));
+        assert_eq!(
+            check(0x0800_0000, Width::W32, true),
+            Err("region is read-only")
+        );
+        assert_eq!(
+            check(0x1000_0000, Width::W8, false),
+            Err("address not in any memory region")
+        );
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(human("poke 0x08000000 1"), "Error: region is read-only\r\n");
+        assert_eq!(human("peek 0x24000002"), "Error: address not aligned\r\n");
+        assert_eq!(
+            human("hexdump 0x2407FFC0 128"),
+            "Error: address not in any memory region\r\n"
+        );
+        assert_eq!(
+            human("hexdump 0x24000002"),
+            "Error: address not aligned\r\n"
+        );
+        // 主机上检查通过之后的访问一律失败
+        assert_eq!(human("peek 0x24000000 8"), "Error: bus fault\r\n");
+        assert_eq!(human("hexdump 0x24000000 256"), "Error: bus fault\r\n");
+    }
+
+    #[test]
+    fn argument_errors() {
+        assert!(human("peek").starts_with("Error: missing <addr>"));
+        assert!(human("peek 0x24000000 12").starts_with("Error: invalid <width>"));
+        assert!(human("peek zz").starts_with("Error: invalid <addr>"));
+        assert!(human("peek 0x24000000 8 8").starts_with("Error: too many arguments"));
+        assert!(human("poke 0x24000000").starts_with("Error: missing <value>"));
+        assert!(human("poke 0x24000000 0x100 8").starts_with("Error: invalid <value>"));
+        assert!(human("poke 0x24000000 1 32 x").starts_with("Error: too many arguments"));
+        assert!(human("hexdump").starts_with("Error: missing <addr>"));
+        assert!(human("hexdump 0x24000000 0").starts_with("Error: invalid <len>"));
+        assert!(human("hexdump 0x24000000 257").starts_with("Error: invalid <len>"));
+        assert!(human("hexdump 0x24000000 4 4").starts_with("Error: too many arguments"));
+    }
+}
@@ -0,0 +1,94 @@
+//! 收到一帧数据之后怎么回应。
+//!
+//! 只依赖 `embedded_io_async`，串口、USB、TCP 或者主机上的模拟输入输出都可以用，
+//! 每个连接一个 [`session::Session`]。一行文本怎么拆成命令和参数见 [`args`]，
+//! 有哪些命令见 [`commands`]。
+
+pub mod args;
+pub mod cancel;
+pub mod commands;
+pub mod complete;
+pub mod config;
+pub mod editor;
+pub mod gpio;
+pub mod memory;
+pub mod output;
+pub mod session;
+pub mod watch;
+
+use core::fmt::Write as _;
+
+use embedded_io_async::Write;
+
+use self::cancel::Cancel;
+use self::editor::LineEditor;
+use self::output::Status;
+use self::session::Terminal;
+use crate::usart::FramePipeline;
+
+/// 一行命令最多多少字节
+pub const LINE_SIZE: usize = 128;
+/// 记住最近几条命令
+pub const HISTORY: usize = 8;
+/// shell 用的行编辑器
+pub type Editor = LineEditor<LINE_SIZE, HISTORY>;
+
+/// 提示符
+pub const PROMPT: &str = "> ";
+
+/// 原样回显一帧，前面加上 `Echo: `
+pub async fn echo<W: Write>(tx: &mut W, data: &[u8]) -> Result<(), W::Error> {
+    tx.write_all(b"Echo: ").await?;
+    tx.write_all(data).await?;
+    tx.write_all(b"\r\n").await
+}
+
+/// 等接收任务送来一帧，交给 [`session::input`] 处理，返回这一帧的长度。
+///
+/// 帧是独占的，处理时不用持锁；处理完缓冲区自动还回池里。
+/// 流水线是 [`Policy::SignalBusy`](crate::pipeline::Policy::SignalBusy) 时，
+/// 先告诉对端刚才有几段输入因为太忙被丢掉了（机器模式下是一个 [`Status::Busy`] 结果）。
+/// 命令执行时的 Ctrl-C 由接收任务交给 `cancel`，不经过流水线。
+pub async fn process<W: Write>(
+    tx: &mut W,
+    pipeline: &FramePipeline,
+    term: &mut Terminal,
+    cancel: &Cancel,
+) -> Result<usize, W::Error> {
+    let frame = pipeline.receive().await;
+
+    let busy = pipeline.take_busy();
+    if busy > 0 {
+        match term.mode.format() {
+            None => {
+                let mut out = heapless::String::<48>::new();
+                let _ = write!(out, "\r\nError: busy, {} frame(s) dropped\r\n", busy);
+                tx.write_all(out.as_bytes()).await?;
+            }
+            Some(format) => {
+                let mut payload = output::Encoder::<24>::new(format);
+                payload.key("dropped").uint(busy.into());
+                let message = format_args!("busy");
+                output::respond(tx, format, Status::Busy, Some(message), payload.finish()).await?;
+            }
+        }
+    }
+
+    session::input(tx, term, cancel, &frame).await?;
+    Ok(frame.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::testing::MockTx;
+
+    #[test]
+    fn echo_wraps_frame() {
+        let mut tx = MockTx::default();
+        block_on(echo(&mut tx, b"hi")).unwrap();
+        assert_eq!(tx.0, b"Echo: hi\r\n");
+    }
+}
@@ -0,0 +1,776 @@
+//! 命令的输出格式：给人看的文本，或者给测试脚本解析的 JSON / CBOR。
+//!
+//! 测试台原来靠匹配 shell 打印的英文来判断结果，措辞一改就坏。现在每个终端有自己的
+//! [`Mode`]，用 `mode json` / `mode cbor` 切换，默认还是 [`Mode::Human`]。
+//! 终端不认 ANSI 控制序列时用 `mode plain`，`watch` 之类就不再原地重画。机器模式下不回显、
+//! 不显示提示符、不补全，输入仍然是一行一条命令，每条命令回一个结果对象：
+//!
+//! ```text
+//! {"status":"ok","code":0,"payload":{...}}
+//! {"status":"error","code":2,"message":"invalid <baud>","payload":{"usage":"uart ..."}}
+//! ```
+//!
+//! JSON 每个结果一行，以 `\r\n` 结尾；CBOR 每个结果是一个完整的 map，本身就能确定长度，
+//! 前后不加别的字节。`code` 的含义见 [`Status`]。
+//!
+//! 编码全部写进固定大小的缓冲区，不需要堆。处理函数在文本模式下用
+//! [`Reply::text`] 打印，在机器模式下用 [`Reply::data`] 填 payload。
+
+use core::fmt::{self, Write as _};
+use core::future::Future;
+
+use embassy_futures::select::{select, Either};
+use embedded_io_async::Write;
+use heapless::{String, Vec};
+
+use super::cancel::Cancel;
+use super::commands::CommandError;
+use super::LINE_SIZE;
+
+/// 一条命令的 payload 最多多少字节，超过了回 [`Status::Overflow`]
+pub const PAYLOAD_SIZE: usize = 1024;
+
+/// 输出格式
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum Mode {
+    /// 给人看的文本，带回显和提示符，终端支持 ANSI 控制序列
+    #[default]
+    Human,
+    /// 和 `Human` 一样，只是不用 ANSI 控制序列重画屏幕，适合简陋的终端和抓日志
+    Plain,
+    /// 每个结果一行 JSON
+    Json,
+    /// 每个结果一个 CBOR map
+    Cbor,
+}
+
+impl Mode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "human" => Some(Mode::Human),
+            "plain" => Some(Mode::Plain),
+            "json" => Some(Mode::Json),
+            "cbor" => Some(Mode::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Human => "human",
+            Mode::Plain => "plain",
+            Mode::Json => "json",
+            Mode::Cbor => "cbor",
+        }
+    }
+
+    /// 给人看的文本（`Human` 或者 `Plain`）
+    pub fn is_text(&self) -> bool {
+        self.format().is_none()
+    }
+
+    /// 机器模式用的编码，文本模式返回 `None`
+    pub fn format(&self) -> Option<Format> {
+        match self {
+            Mode::Human | Mode::Plain => None,
+            Mode::Json => Some(Format::Json),
+            Mode::Cbor => Some(Format::Cbor),
+        }
+    }
+}
+
+/// 结果码，测试脚本应该看 `code` 而不是 `message`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Status {
+    Ok = 0,
+    /// 没有这条命令
+    UnknownCommand = 1,
+    /// 参数不对，`payload.usage` 是用法
+    BadArgument = 2,
+    /// 参数组合不对，`payload.usage` 是用法
+    Usage = 3,
+    /// 命令执行失败
+    Failed = 4,
+    /// payload 超过了 [`PAYLOAD_SIZE`]
+    Overflow = 5,
+    /// 处理不过来，丢掉了一些输入，`payload.dropped` 是丢了几段
+    Busy = 6,
+    /// 被 Ctrl-C 取消了
+    Cancelled = 7,
+}
+
+impl Status {
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
+
+/// 机器模式的编码
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Format {
+    Json,
+    Cbor,
+}
+
+/// 最多嵌套几层 map / list
+const MAX_DEPTH: u8 = 16;
+
+/// 往固定大小的缓冲区里写 JSON 或 CBOR。
+///
+/// 一开始就在一个 map 里，用 [`key`](Self::key) 写键，紧跟着写值；list 里直接写值。
+/// CBOR 的 map 和 list 都用不定长编码，所以不用事先知道有几项。放不下时后面的内容都丢掉，
+/// [`overflowed`](Self::overflowed) 返回 `true`。
+pub struct Encoder<const N: usize> {
+    format: Format,
+    buf: Vec<u8, N>,
+    overflow: bool,
+    /// 当前在第几层，0 是最外面的 map
+    depth: u8,
+    /// 第几层已经写过东西了，JSON 要在下一项前面加逗号
+    items: u16,
+    /// 第几层是 list，JSON 结束时用 `]`
+    lists: u16,
+    /// 刚写完键，下一个值前面不加逗号
+    after_key: bool,
+}
+
+impl<const N: usize> Encoder<N> {
+    pub fn new(format: Format) -> Self {
+        let mut e = Self {
+            format,
+            buf: Vec::new(),
+            overflow: false,
+            depth: 0,
+            items: 0,
+            lists: 0,
+            after_key: false,
+        };
+        e.raw(match format {
+            Format::Json => b"{",
+            Format::Cbor => &[0xBF],
+        });
+        e
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn overflowed(&self) -> bool {
+        self.overflow
+    }
+
+    /// 写 map 的键
+    pub fn key(&mut self, key: &str) -> &mut Self {
+        self.separator();
+        self.text(key);
+        if self.format == Format::Json {
+            self.raw(b":");
+        }
+        self.after_key = true;
+        self
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.separator();
+        self.text(value);
+    }
+
+    /// 格式化之后作为字符串写进去，不经过中间缓冲区
+    pub fn str_fmt(&mut self, args: fmt::Arguments<'_>) {
+        self.separator();
+        match self.format {
+            Format::Json => {
+                self.raw(b"\"");
+                let _ = fmt::write(&mut Escape(self), args);
+                self.raw(b"\"");
+            }
+            Format::Cbor => {
+                // CBOR 要先写长度，所以格式化两遍
+                let mut len = Len(0);
+                let _ = fmt::write(&mut len, args);
+                self.cbor_head(3, len.0 as u64);
+                let _ = fmt::write(&mut Escape(self), args);
+            }
+        }
+    }
+
+    pub fn uint(&mut self, value: u64) {
+        self.separator();
+        match self.format {
+            Format::Json => self.json_fmt(format_args!("{}", value)),
+            Format::Cbor => self.cbor_head(0, value),
+        }
+    }
+
+    pub fn int(&mut self, value: i64) {
+        if value >= 0 {
+            return self.uint(value as u64);
+        }
+        self.separator();
+        match self.format {
+            Format::Json => self.json_fmt(format_args!("{}", value)),
+            // 负数编码成 -1 - n
+            Format::Cbor => self.cbor_head(1, !(value as u64)),
+        }
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.separator();
+        match (self.format, value) {
+            (Format::Json, true) => self.raw(b"true"),
+            (Format::Json, false) => self.raw(b"false"),
+            (Format::Cbor, true) => self.raw(&[0xF5]),
+            (Format::Cbor, false) => self.raw(&[0xF4]),
+        }
+    }
+
+    /// 开始一个 map，用 [`end`](Self::end) 结束
+    pub fn map(&mut self) {
+        self.open(false);
+    }
+
+    /// 开始一个 list，用 [`end`](Self::end) 结束
+    pub fn list(&mut self) {
+        self.open(true);
+    }
+
+    /// 结束最近的 map 或 list
+    pub fn end(&mut self) {
+        if self.depth == 0 {
+            return;
+        }
+        let bit = 1 << self.depth;
+        match self.format {
+            Format::Json if self.lists & bit != 0 => self.raw(b"]"),
+            Format::Json => self.raw(b"}"),
+            Format::Cbor => self.raw(&[0xFF]),
+        }
+        self.depth -= 1;
+    }
+
+    /// 结束所有还开着的 map / list（包括最外面的），返回编码好的内容
+    pub fn finish(&mut self) -> &[u8] {
+        while self.depth > 0 {
+            self.end();
+        }
+        self.raw(match self.format {
+            Format::Json => b"}",
+            Format::Cbor => &[0xFF],
+        });
+        &self.buf
+    }
+
+    fn open(&mut self, list: bool) {
+        self.separator();
+        if self.depth + 1 >= MAX_DEPTH {
+            self.overflow = true;
+            return;
+        }
+        match (self.format, list) {
+            (Format::Json, true) => self.raw(b"["),
+            (Format::Json, false) => self.raw(b"{"),
+            (Format::Cbor, true) => self.raw(&[0x9F]),
+            (Format::Cbor, false) => self.raw(&[0xBF]),
+        }
+        self.depth += 1;
+        let bit = 1 << self.depth;
+        self.items &= !bit;
+        if list {
+            self.lists |= bit;
+        } else {
+            self.lists &= !bit;
+        }
+    }
+
+    fn separator(&mut self) {
+        if core::mem::take(&mut self.after_key) {
+            return;
+        }
+        let bit = 1 << self.depth;
+        if self.format == Format::Json && self.items & bit != 0 {
+            self.raw(b",");
+        }
+        self.items |= bit;
+    }
+
+    fn text(&mut self, s: &str) {
+        match self.format {
+            Format::Json => {
+                self.raw(b"\"");
+                self.escaped(s);
+                self.raw(b"\"");
+            }
+            Format::Cbor => {
+                self.cbor_head(3, s.len() as u64);
+                self.raw(s.as_bytes());
+            }
+        }
+    }
+
+    /// 字符串的内容，JSON 要转义，CBOR 原样写
+    fn escaped(&mut self, s: &str) {
+        match self.format {
+            Format::Json => {
+                for c in s.chars() {
+                    match c {
+                        '"' => self.raw(b"\\\""),
+                        '\\' => self.raw(b"\\\\"),
+                        '\n' => self.raw(b"\\n"),
+                        '\r' => self.raw(b"\\r"),
+                        '\t' => self.raw(b"\\t"),
+                        c if (c as u32) < 0x20 => {
+                            self.json_fmt(format_args!("\\u{:04x}", c as u32))
+                        }
+                        c => {
+                            let mut bytes = [0; 4];
+                            self.raw(c.encode_utf8(&mut bytes).as_bytes());
+                        }
+                    }
+                }
+            }
+            Format::Cbor => self.raw(s.as_bytes()),
+        }
+    }
+
+    fn json_fmt(&mut self, args: fmt::Arguments<'_>) {
+        let mut s = String::<24>::new();
+        let _ = s.write_fmt(args);
+        self.raw(s.as_bytes());
+    }
+
+    /// CBOR 的类型和长度（或者整数的值）
+    fn cbor_head(&mut self, major: u8, value: u64) {
+        let major = major << 5;
+        match value {
+            0..=23 => self.raw(&[major | value as u8]),
+            24..=0xFF => self.raw(&[major | 24, value as u8]),
+            0x100..=0xFFFF => {
+                self.raw(&[major | 25]);
+                self.raw(&(value as u16).to_be_bytes());
+            }
+            0x1_0000..=0xFFFF_FFFF => {
+                self.raw(&[major | 26]);
+                self.raw(&(value as u32).to_be_bytes());
+            }
+            _ => {
+                self.raw(&[major | 27]);
+                self.raw(&value.to_be_bytes());
+            }
+        }
+    }
+
+    fn raw(&mut self, bytes: &[u8]) {
+        if self.overflow || self.buf.extend_from_slice(bytes).is_err() {
+            self.overflow = true;
+        }
+    }
+}
+
+/// 格式化的内容直接写进 [`Encoder`]，见 [`Encoder::str_fmt`]
+struct Escape<'a, const N: usize>(&'a mut Encoder<N>);
+
+impl<const N: usize> fmt::Write for Escape<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.escaped(s);
+        Ok(())
+    }
+}
+
+/// 只数格式化之后有多少字节
+struct Len(usize);
+
+impl fmt::Write for Len {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// 结果对象里 `payload` 前面的部分最多多少字节。`message` 里最长的是带着整行命令的，
+/// 按一行全是要转义的字符算
+const HEAD_SIZE: usize = 2 * LINE_SIZE + 64;
+
+/// 把一个结果对象写到 `tx`：`status`、`code`、可选的 `message`，最后是编码好的 `payload`
+pub async fn respond<W: Write>(
+    tx: &mut W,
+    format: Format,
+    status: Status,
+    message: Option<fmt::Arguments<'_>>,
+    payload: &[u8],
+) -> Result<(), W::Error> {
+    let head = |message: Option<fmt::Arguments<'_>>| {
+        let mut head = Encoder::<HEAD_SIZE>::new(format);
+        head.key("status")
+            .str(if status == Status::Ok { "ok" } else { "error" });
+        head.key("code").uint(status.code().into());
+        if let Some(message) = message {
+            head.key("message").str_fmt(message);
+        }
+        head.key("payload");
+        head
+    };
+    let mut encoded = head(message);
+    if encoded.overflowed() {
+        // 写一半的 message 会让整个对象解析不了，宁可换成固定的说明
+        encoded = head(Some(format_args!("message too long")));
+    }
+
+    tx.write_all(&encoded.buf).await?;
+    tx.write_all(payload).await?;
+    match format {
+        Format::Json => tx.write_all(b"}\r\n").await,
+        Format::Cbor => tx.write_all(&[0xFF]).await,
+    }
+}
+
+/// 命令处理函数写应答的地方
+pub struct Reply<'a, W> {
+    tx: &'a mut W,
+    mode: Mode,
+    cancel: &'a Cancel,
+    /// 命令执行完之后换成这个格式，见 [`set_mode`](Self::set_mode)
+    next_mode: Option<Mode>,
+    data: Encoder<PAYLOAD_SIZE>,
+    /// 已经提前发出去了，见 [`send`](Self::send)
+    sent: bool,
+}
+
+impl<'a, W: Write> Reply<'a, W> {
+    pub fn new(tx: &'a mut W, mode: Mode, cancel: &'a Cancel) -> Self {
+        Self {
+            tx,
+            mode,
+            cancel,
+            next_mode: None,
+            data: Encoder::new(mode.format().unwrap_or(Format::Json)),
+            sent: false,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// 文本模式，见 [`Mode::is_text`]
+    pub fn is_human(&self) -> bool {
+        self.mode.is_text()
+    }
+
+    /// 可以用 ANSI 控制序列重画屏幕
+    pub fn ansi(&self) -> bool {
+        self.mode == Mode::Human
+    }
+
+    /// 这条命令的结果还用原来的格式，之后的命令换成 `mode`
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.next_mode = Some(mode);
+    }
+
+    pub fn next_mode(&self) -> Option<Mode> {
+        self.next_mode
+    }
+
+    /// 这条命令的取消信号，在里面再执行命令时交给里面的 [`Reply`]
+    pub fn cancel_signal(&self) -> &'a Cancel {
+        self.cancel
+    }
+
+    /// 在这条命令里面再执行命令（`watch`、`repeat`）时用：输出直接写到终端，
+    /// 同时数一下写了几行
+    pub fn nested(&mut self) -> LineCount<'_, W> {
+        LineCount {
+            inner: self.tx,
+            lines: 0,
+        }
+    }
+
+    /// 用户按了 Ctrl-C
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_requested()
+    }
+
+    /// 等 `fut` 完成，中途按了 Ctrl-C 就丢掉它并返回 [`CommandError::Cancelled`]。
+    /// 长时间等待的地方（定时器、外设事件）都应该这样包一下
+    pub async fn cancellable<F: Future>(
+        &self,
+        fut: F,
+    ) -> Result<F::Output, CommandError<W::Error>> {
+        match select(fut, self.cancel.wait()).await {
+            Either::First(output) => Ok(output),
+            Either::Second(()) => Err(CommandError::Cancelled),
+        }
+    }
+
+    /// 给人看的文本，机器模式下忽略
+    pub async fn text(&mut self, s: &str) -> Result<(), CommandError<W::Error>> {
+        if self.is_human() && !self.sent {
+            self.tx
+                .write_all(s.as_bytes())
+                .await
+                .map_err(CommandError::Io)?;
+        }
+        Ok(())
+    }
+
+    /// 格式化之后作为文本输出，最多 192 字节
+    pub async fn text_fmt(
+        &mut self,
+        args: fmt::Arguments<'_>,
+    ) -> Result<(), CommandError<W::Error>> {
+        if !self.is_human() {
+            return Ok(());
+        }
+        let mut s = String::<192>::new();
+        let _ = s.write_fmt(args);
+        self.text(&s).await
+    }
+
+    /// 机器模式的 payload，最外层已经是一个 map 了
+    pub fn data(&mut self) -> &mut Encoder<PAYLOAD_SIZE> {
+        &mut self.data
+    }
+
+    /// 马上把成功的结果发出去并等它发完，之后的输出都忽略。
+    /// 用在执行完就会断开或者换波特率的命令里
+    pub async fn send(&mut self) -> Result<(), CommandError<W::Error>> {
+        self.finish(Status::Ok, None, None)
+            .await
+            .map_err(CommandError::Io)?;
+        self.tx.flush().await.map_err(CommandError::Io)
+    }
+
+    /// 命令执行完了，写出结果。人看的模式下成功时什么都不用写，出错时打印
+    /// `Error: ...` 和 `Usage: ...`
+    pub async fn finish(
+        &mut self,
+        status: Status,
+        message: Option<fmt::Arguments<'_>>,
+        usage: Option<&str>,
+    ) -> Result<(), W::Error> {
+        if core::mem::replace(&mut self.sent, true) {
+            return Ok(());
+        }
+
+        let Some(format) = self.mode.format() else {
+            if status == Status::Cancelled {
+                return self.tx.write_all(b"^C\r\n").await;
+            }
+            if let Some(message) = message {
+                self.tx.write_all(b"Error: ").await?;
+                write_args(self.tx, message).await?;
+                self.tx.write_all(b"\r\n").await?;
+            }
+            if let Some(usage) = usage {
+                self.tx.write_all(b"Usage: ").await?;
+                self.tx.write_all(usage.as_bytes()).await?;
+                self.tx.write_all(b"\r\n").await?;
+            }
+            return Ok(());
+        };
+
+        if status == Status::Ok && self.data.overflowed() {
+            let mut empty = Encoder::<2>::new(format);
+            let payload = empty.finish();
+            let message = format_args!("payload too large");
+            return respond(self.tx, format, Status::Overflow, Some(message), payload).await;
+        }
+        if status != Status::Ok {
+            // 出错时前面填了一半的 payload 不要了
+            self.data = Encoder::new(format);
+        }
+        if let Some(usage) = usage {
+            self.data.key("usage").str(usage);
+        }
+        respond(self.tx, format, status, message, self.data.finish()).await
+    }
+}
+
+/// 把 `args` 分段格式化之后写到 `tx`，多长都不会截断。
+/// 每段都从头格式化一遍，跳过已经写出去的部分，只适合短文本
+async fn write_args<W: Write>(tx: &mut W, args: fmt::Arguments<'_>) -> Result<(), W::Error> {
+    let mut written = 0;
+    loop {
+        let mut chunk = Chunk {
+            skip: written,
+            buf: Vec::new(),
+        };
+        let more = fmt::write(&mut chunk, args).is_err();
+        tx.write_all(&chunk.buf).await?;
+        written += chunk.buf.len();
+        if !more || chunk.buf.is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// [`write_args`] 的一段：跳过前 `skip` 字节，放满了就返回错误让格式化停下
+struct Chunk {
+    skip: usize,
+    buf: Vec<u8, 64>,
+}
+
+impl fmt::Write for Chunk {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let skip = self.skip.min(s.len());
+        self.skip -= skip;
+        let rest = &s.as_bytes()[skip..];
+        let take = rest.len().min(self.buf.capacity() - self.buf.len());
+        let _ = self.buf.extend_from_slice(&rest[..take]);
+        if take < rest.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// 数一下写了几行的 `Write`，`watch` 重画之前要知道上一次的输出占了几行
+pub struct LineCount<'a, W> {
+    inner: &'a mut W,
+    lines: usize,
+}
+
+impl<W> LineCount<'_, W> {
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+}
+
+impl<W: Write> embedded_io_async::ErrorType for LineCount<'_, W> {
+    type Error = W::Error;
+}
+
+impl<W: Write> Write for LineCount<'_, W> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, W::Error> {
+        let n = self.inner.write(buf).await?;
+        self.lines += buf[..n].iter().filter(|&&b| b == b'\n').count();
+        Ok(n)
+    }
+
+    async fn flush(&mut self) -> Result<(), W::Error> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::testing::MockTx;
+
+    fn respond_to(format: Format, message: fmt::Arguments<'_>) -> Vec<u8> {
+        let mut tx = MockTx::default();
+        let mut payload = Encoder::<2>::new(format);
+        let payload = payload.finish();
+        block_on(respond(
+            &mut tx,
+            format,
+            Status::UnknownCommand,
+            Some(message),
+            payload,
+        ))
+        .unwrap();
+        tx.0
+    }
+
+    #[test]
+    fn long_message_json() {
+        let name = "a".repeat(100);
+        let out = respond_to(
+            Format::Json,
+            format_args!("unknown command '{}', try 'help'", name),
+        );
+        let expected = std::format!(
+            "{{\"status\":\"error\",\"code\":1,\"message\":\"unknown command '{}', try 'help'\",\"payload\":{{}}}}\r\n",
+            name
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn long_message_cbor() {
+        let name = "a".repeat(30);
+        let out = respond_to(
+            Format::Cbor,
+            format_args!("unknown command '{}', try 'help'", name),
+        );
+        let message = std::format!("unknown command '{}', try 'help'", name);
+        assert_eq!(message.len(), 60);
+
+        let mut expected = std::vec![0xBF];
+        expected.extend_from_slice(b"\x66status\x65error\x64code\x01\x67message\x78\x3C");
+        expected.extend_from_slice(message.as_bytes());
+        expected.extend_from_slice(b"\x67payload\xBF\xFF\xFF");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn message_escaped_in_json() {
+        let out = respond_to(Format::Json, format_args!("bad \"{}\"", "a\\b"));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"status\":\"error\",\"code\":1,\"message\":\"bad \\\"a\\\\b\\\"\",\"payload\":{}}\r\n"
+        );
+    }
+
+    #[test]
+    fn too_long_message_is_replaced() {
+        let name = "a".repeat(HEAD_SIZE);
+        let out = respond_to(Format::Json, format_args!("{}", name));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"status\":\"error\",\"code\":1,\"message\":\"message too long\",\"payload\":{}}\r\n"
+        );
+    }
+
+    #[test]
+    fn str_fmt_is_not_truncated() {
+        let value = "x".repeat(100);
+        let mut e = Encoder::<256>::new(Format::Json);
+        e.key("v").str_fmt(format_args!("{}", value));
+        assert!(!e.overflowed());
+        assert_eq!(
+            e.finish(),
+            std::format!("{{\"v\":\"{}\"}}", value).as_bytes()
+        );
+
+        let mut e = Encoder::<256>::new(Format::Cbor);
+        e.key("v").str_fmt(format_args!("{}", value));
+        let mut expected = std::vec![0xBF, 0x61, b'v', 0x78, 100];
+        expected.extend_from_slice(value.as_bytes());
+        expected.push(0xFF);
+        assert_eq!(e.finish(), &expected[..]);
+    }
+
+    #[test]
+    fn str_fmt_overflow() {
+        for format in [Format::Json, Format::Cbor] {
+            let mut e = Encoder::<16>::new(format);
+            e.key("v").str_fmt(format_args!("{}", "x".repeat(20)));
+            assert!(e.overflowed());
+        }
+    }
+
+    #[test]
+    fn long_error_in_human_mode() {
+        let name = "b".repeat(150);
+        let cancel = Cancel::new();
+        let mut tx = MockTx::default();
+        let mut reply = Reply::new(&mut tx, Mode::Human, &cancel);
+        let message = format_args!("unknown command '{}', try 'help'", name);
+        block_on(reply.finish(Status::UnknownCommand, Some(message), Some("help"))).unwrap();
+        assert_eq!(
+            String::from_utf8(tx.0).unwrap(),
+            std::format!(
+                "Error: unknown command '{}', try 'help'\r\nUsage: help\r\n",
+                name
+            )
+        );
+    }
+}
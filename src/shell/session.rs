@@ -0,0 +1,385 @@
+//! 和传输方式无关的 shell 会话。
+//!
+//! 原来 shell 直接绑在 `UartRx<'static, Async>`/`UartTx<'static, Async>` 上。现在一个
+//! [`Session`] 只要求输入实现 `embedded_io_async::Read`、输出实现 `embedded_io_async::Write`，
+//! 调试串口、USB 虚拟串口和 TCP 连接都可以各开一个。每个会话有自己的行缓冲、历史和输出格式
+//! （[`Terminal`]），命令表 [`COMMANDS`](super::commands::COMMANDS) 是大家共用的。
+//!
+//! 会话里收和处理是分开的：命令执行时接收那一边还在读，这样才能收到 Ctrl-C
+//! （见 [`cancel`](super::cancel)）。
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::pipe::Pipe;
+use embedded_io_async::{Read, Write};
+
+use super::cancel::{Cancel, CTRL_C};
+use super::complete::{self, CandidateList, Completion};
+use super::editor::Event;
+use super::output::Mode;
+use super::{commands, Editor, LINE_SIZE, PROMPT};
+
+/// 会话结束的原因
+#[derive(Debug, defmt::Format)]
+pub enum SessionError<R, W> {
+    /// 读输入出错，比如 USB 被拔掉
+    Read(R),
+    /// 写输出出错
+    Write(W),
+}
+
+/// 一个终端的状态：正在编辑的行、历史和输出格式。
+/// 收到不合法的 UTF-8 怎么显示也是每个终端自己定的，见 [`LineEditor::set_invalid`](super::editor::LineEditor::set_invalid)
+pub struct Terminal {
+    pub editor: Editor,
+    pub mode: Mode,
+}
+
+impl Terminal {
+    pub const fn new() -> Self {
+        Self {
+            editor: Editor::new(),
+            mode: Mode::Human,
+        }
+    }
+
+    /// 给人看的模式下显示提示符
+    pub async fn prompt<W: Write>(&self, tx: &mut W) -> Result<(), W::Error> {
+        if self.mode.is_text() {
+            tx.write_all(PROMPT.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 一个终端连接上的 shell
+pub struct Session<R, W> {
+    rx: R,
+    tx: W,
+    term: Terminal,
+    cancel: Cancel,
+}
+
+impl<R, W> Session<R, W> {
+    pub fn new(rx: R, tx: W) -> Self {
+        Self {
+            rx,
+            tx,
+            term: Terminal::new(),
+            cancel: Cancel::new(),
+        }
+    }
+
+    pub fn rx_mut(&mut self) -> &mut R {
+        &mut self.rx
+    }
+
+    pub fn tx_mut(&mut self) -> &mut W {
+        &mut self.tx
+    }
+
+    pub fn terminal_mut(&mut self) -> &mut Terminal {
+        &mut self.term
+    }
+}
+
+impl<R: Read, W: Write> Session<R, W> {
+    /// 显示提示符，然后一直读输入、执行命令。输入读到结尾（比如 TCP 对端关闭）时，
+    /// 把已经收到的输入处理完再返回 `Ok`；这时正在执行的命令会被中断。
+    ///
+    /// 出错返回之后行缓冲、历史和输出格式都还在，可以对同一个会话再次调用 `run`。
+    pub async fn run(&mut self) -> Result<(), SessionError<R::Error, W::Error>> {
+        let Self {
+            rx,
+            tx,
+            term,
+            cancel,
+        } = self;
+        term.prompt(tx).await.map_err(SessionError::Write)?;
+
+        let pipe = Pipe::new();
+        match select(receive(rx, &pipe, cancel), process(tx, term, &pipe, cancel)).await {
+            Either::First(Ok(())) => {
+                let mut buf = [0; 64];
+                while let Ok(n) = pipe.try_read(&mut buf) {
+                    input(tx, term, cancel, &buf[..n])
+                        .await
+                        .map_err(SessionError::Write)?;
+                }
+                Ok(())
+            }
+            Either::First(Err(e)) => Err(SessionError::Read(e)),
+            Either::Second(Err(e)) => Err(SessionError::Write(e)),
+        }
+    }
+}
+
+/// 收到的字节先放进这里，命令执行时多敲的内容也存在这里
+type InputPipe = Pipe<NoopRawMutex, 64>;
+
+/// 接收的一边：命令执行时收到 Ctrl-C 就通知它，其余的字节交给处理的一边
+async fn receive<R: Read>(rx: &mut R, pipe: &InputPipe, cancel: &Cancel) -> Result<(), R::Error> {
+    let mut buf = [0; 64];
+    loop {
+        let n = rx.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        let mut start = 0;
+        for i in 0..=n {
+            if i < n && !(buf[i] == CTRL_C && cancel.request()) {
+                continue;
+            }
+            let piece = &buf[start..i];
+            start = i + 1;
+            if cancel.is_running() {
+                // 命令执行时多敲的内容存满了就丢掉，不能卡在这里，否则收不到 Ctrl-C
+                let _ = pipe.try_write(piece);
+            } else {
+                let mut piece = piece;
+                while !piece.is_empty() {
+                    let written = pipe.write(piece).await;
+                    piece = &piece[written..];
+                }
+            }
+        }
+    }
+}
+
+/// 处理的一边：交给行编辑器，执行命令
+async fn process<W: Write>(
+    tx: &mut W,
+    term: &mut Terminal,
+    pipe: &InputPipe,
+    cancel: &Cancel,
+) -> Result<core::convert::Infallible, W::Error> {
+    let mut buf = [0; 64];
+    loop {
+        let n = pipe.read(&mut buf).await;
+        input(tx, term, cancel, &buf[..n]).await?;
+    }
+}
+
+/// 把收到的字节逐个交给行编辑器，回显写回终端，敲了回车就执行这一行。
+/// 机器模式下不回显、不显示提示符，Tab 也不补全。
+///
+/// 命令执行期间要有别人盯着输入，收到 Ctrl-C 时调用 `cancel` 的 [`Cancel::request`]
+pub async fn input<W: Write>(
+    tx: &mut W,
+    term: &mut Terminal,
+    cancel: &Cancel,
+    bytes: &[u8],
+) -> Result<(), W::Error> {
+    // 一个字节最多引起整行重画，攒够一行的余量就先发出去
+    let mut echo = heapless::String::<{ 2 * LINE_SIZE }>::new();
+    for &byte in bytes {
+        if echo.capacity() - echo.len() < LINE_SIZE + 16 {
+            flush_echo(tx, term, &mut echo).await?;
+        }
+        match term.editor.push(byte, &mut echo) {
+            Some(Event::Line) => {
+                flush_echo(tx, term, &mut echo).await?;
+                commands::execute(tx, &mut term.mode, cancel, term.editor.line()).await?;
+                term.prompt(tx).await?;
+            }
+            Some(Event::Interrupt) => {
+                flush_echo(tx, term, &mut echo).await?;
+                term.prompt(tx).await?;
+            }
+            Some(Event::Complete) if term.mode.is_text() => {
+                tab(tx, &mut term.editor, &mut echo).await?
+            }
+            Some(Event::Complete) | None => {}
+        }
+    }
+    flush_echo(tx, term, &mut echo).await
+}
+
+async fn flush_echo<W: Write>(
+    tx: &mut W,
+    term: &Terminal,
+    echo: &mut heapless::String<{ 2 * LINE_SIZE }>,
+) -> Result<(), W::Error> {
+    if term.mode.is_text() {
+        tx.write_all(echo.as_bytes()).await?;
+    }
+    echo.clear();
+    Ok(())
+}
+
+/// 按下 Tab：补全光标前的词，或者在下一行列出所有候选再重新显示提示符和当前行。
+/// 候选太多一行放不下时只列前面的，后面用 `...` 表示
+async fn tab<W: Write>(
+    tx: &mut W,
+    editor: &mut Editor,
+    echo: &mut heapless::String<{ 2 * LINE_SIZE }>,
+) -> Result<(), W::Error> {
+    let before = &editor.line()[..editor.cursor()];
+    let mut list = CandidateList::<512>::new();
+    match complete::complete(before, &mut list) {
+        Completion::Nothing => {
+            let _ = echo.push('\x07');
+        }
+        Completion::Insert(s) => editor.insert_str(&s, echo),
+        Completion::Listed => {
+            tx.write_all(echo.as_bytes()).await?;
+            echo.clear();
+            tx.write_all(b"\r\n").await?;
+            tx.write_all(list.as_str().as_bytes()).await?;
+            tx.write_all(b"\r\n").await?;
+            tx.write_all(PROMPT.as_bytes()).await?;
+            editor.redraw(echo);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::collections::VecDeque;
+    use std::string::String;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_time::{Duration, Instant, Timer};
+
+    use super::*;
+    use crate::gpio::{install, PinId, PinPool};
+    use crate::testing::{globals, FakePin, MockRx, MockTx};
+    use crate::usart::utf8::Invalid;
+
+    /// 每次 `read` 之前等 10 ms 的假输入，按 `chunks` 一块一块地来，最后读到结尾
+    struct Typing(MockRx);
+
+    impl Typing {
+        fn new(chunks: &[&[u8]]) -> Self {
+            let mut chunks: VecDeque<Vec<u8>> = chunks.iter().map(|c| c.to_vec()).collect();
+            chunks.push_back(Vec::new());
+            Self(MockRx { chunks })
+        }
+    }
+
+    impl embedded_io_async::ErrorType for Typing {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for Typing {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            Timer::after_millis(10).await;
+            self.0.read(buf).await
+        }
+    }
+
+    fn output(session: Session<Typing, MockTx>) -> String {
+        String::from_utf8(session.tx.0).unwrap()
+    }
+
+    #[test]
+    fn invalid_policy_per_terminal() {
+        let mut replace = Terminal::new();
+        let mut hex = Terminal::new();
+        hex.editor.set_invalid(Invalid::Hex);
+        for (term, echo) in [(&mut replace, "a\u{FFFD}好"), (&mut hex, "a\\xFF好")] {
+            let mut tx = MockTx::default();
+            // “好”拆在两次输入里
+            block_on(input(&mut tx, term, &Cancel::new(), b"a\xFF\xE5")).unwrap();
+            block_on(input(&mut tx, term, &Cancel::new(), b"\xA5\xBD")).unwrap();
+            assert_eq!(tx.0, echo.as_bytes());
+            assert_eq!(term.editor.line(), echo);
+        }
+    }
+
+    #[test]
+    fn long_candidate_list_is_marked() {
+        let _globals = globals();
+        // 所有引脚都空着，列出来要九百多字节
+        let pool = Box::leak(Box::new(PinPool::<FakePin>::new()));
+        for id in PinId::all() {
+            pool.add(id, FakePin::default());
+        }
+        install(pool);
+
+        let mut tx = MockTx::default();
+        let mut term = Terminal::new();
+        block_on(input(&mut tx, &mut term, &Cancel::new(), b"gpio get P\t")).unwrap();
+        let out = String::from_utf8(tx.0).unwrap();
+        let (list, rest) = out["gpio get P\r\n".len()..].split_once("\r\n").unwrap();
+        assert!(list.starts_with("PA0  PA1  "), "{}", list);
+        assert!(list.ends_with("  ..."), "{}", list);
+        assert!(list.len() <= 512);
+        assert_eq!(rest, "> gpio get P");
+    }
+
+    #[test]
+    fn line_to_reply() {
+        let _globals = globals();
+        // 一行拆在两次输入里，读到结尾之前收到的都要处理完
+        let mut session = Session::new(Typing::new(&[b"hel", b"lo\r"]), MockTx::default());
+        block_on(session.run()).unwrap();
+        assert_eq!(output(session), "> hello\r\n你好！\r\n> ");
+    }
+
+    #[test]
+    fn eof_returns() {
+        let _globals = globals();
+        // 输入一下子就读到了结尾，处理的一边还没来得及跑，返回之前也要把收到的处理完
+        let mut rx = MockRx::chunked(b"hello\r", 64);
+        rx.chunks.push_back(Vec::new());
+        let mut session = Session::new(rx, MockTx::default());
+        block_on(session.run()).unwrap();
+        // 再来一次也是马上返回，会话的状态还在
+        session.rx_mut().chunks.push_back(Vec::new());
+        block_on(session.run()).unwrap();
+        let output = String::from_utf8(session.tx.0).unwrap();
+        assert_eq!(output, "> hello\r\n你好！\r\n> > ");
+    }
+
+    #[test]
+    fn ctrl_c_reaches_command() {
+        let mut session = Session::new(Typing::new(&[b"sleep 5000\r", b"\x03"]), MockTx::default());
+        let start = Instant::now();
+        block_on(session.run()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(!session.cancel.is_running());
+        assert_eq!(output(session), "> sleep 5000\r\n^C\r\n> ");
+    }
+
+    #[test]
+    fn sessions_are_independent() {
+        let _globals = globals();
+        // 两个会话交替输入：A 的半行、B 的输出格式和各自的历史互不影响
+        let mut a = Session::new(
+            Typing::new(&[b"hel", b"lo\r", b"\x1b[A"]),
+            MockTx::default(),
+        );
+        let mut b = Session::new(
+            Typing::new(&[b"mode json\r", b"hello\r", b"sleep 1\r", b"\x1b[A"]),
+            MockTx::default(),
+        );
+        let (ra, rb) = block_on(join(a.run(), b.run()));
+        ra.unwrap();
+        rb.unwrap();
+
+        assert_eq!(a.term.mode, Mode::Human);
+        assert_eq!(a.term.editor.line(), "hello");
+        assert_eq!(b.term.mode, Mode::Json);
+        assert_eq!(b.term.editor.line(), "sleep 1");
+        assert_eq!(output(a), "> hello\r\n你好！\r\n> \x1b[Khello");
+        assert_eq!(
+            output(b),
+            "> mode json\r\nOK\r\n\
+             {\"status\":\"ok\",\"code\":0,\"payload\":{\"greeting\":\"你好！\"}}\r\n\
+             {\"status\":\"ok\",\"code\":0,\"payload\":{}}\r\n"
+        );
+    }
+}
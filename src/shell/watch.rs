@@ -0,0 +1,212 @@
+//! `watch` 和 `repeat`：反复执行另一条命令。
+//!
+//! `watch 1s uart stats` 每秒执行一次 `uart stats`，直到按 Ctrl-C；`repeat 10 hello`
+//! 连续执行 10 次。终端支持 ANSI 控制序列时（[`Mode::Human`](super::output::Mode::Human)）
+//! `watch` 每次把光标移回上一次输出的开头再重画，`plain` 模式下接着往下打印。
+//! 机器模式下里面的命令每执行一次回一个完整的结果对象，最后再回 `watch`/`repeat` 自己的结果。
+//!
+//! async fn 不能直接递归，所以里面的命令通过
+//! [`Handler::call_simple`](super::commands::Handler::call_simple) 执行，不能再是 `watch` 或 `repeat`。
+
+use embassy_futures::yield_now;
+use embassy_time::Ticker;
+use embedded_io_async::Write;
+
+use super::args::Args;
+use super::commands::{conclude, find, Command, CommandError, COMMANDS};
+use super::complete::Candidates;
+use super::output::Reply;
+
+/// `watch <interval> <command...>`：每隔一段时间执行一次，按 Ctrl-C 结束
+pub(super) async fn watch<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    // 原样留着，文本模式下的标题里用
+    let interval = args.clone().str("interval")?;
+    let period = args.duration("interval")?;
+    let line = args.rest();
+    let (command, rest) = inner(&mut args)?;
+
+    let mut ticker = Ticker::every(period);
+    let mut lines = 0;
+    loop {
+        if out.ansi() && lines > 0 {
+            // 回到上一次输出的第一行，清掉到屏幕末尾
+            out.text_fmt(format_args!("\x1b[{}A\r\x1b[J", lines))
+                .await?;
+        }
+        lines = run(out, command, rest, Some((interval, line))).await?;
+        out.cancellable(ticker.next()).await?;
+    }
+}
+
+/// `repeat <n> <command...>`：连续执行 `n` 次
+pub(super) async fn repeat<W: Write>(
+    out: &mut Reply<'_, W>,
+    mut args: Args<'_>,
+) -> Result<(), CommandError<W::Error>> {
+    let count: u32 = args.int("count")?;
+    let (command, rest) = inner(&mut args)?;
+
+    for _ in 0..count {
+        run(out, command, rest, None).await?;
+        // 里面的命令可能一直没让出，这里让接收那一边有机会处理 Ctrl-C
+        out.cancellable(yield_now()).await?;
+    }
+    out.data().key("runs").uint(count.into());
+    Ok(())
+}
+
+/// 取出要反复执行的命令和它的参数
+fn inner<'a, E>(args: &mut Args<'a>) -> Result<(&'static Command, &'a str), CommandError<E>> {
+    let name = args.str("command")?;
+    let command = find(name).ok_or(CommandError::Failed("unknown command"))?;
+    if command.handler.is_meta() {
+        return Err(CommandError::Failed("cannot nest watch or repeat"));
+    }
+    Ok((command, args.rest()))
+}
+
+/// 执行一次里面的命令，返回这次输出了几行。`header` 是 `watch` 的间隔和整条命令，
+/// 文本模式下先打印一行 `Every 1s: uart stats`
+async fn run<W: Write>(
+    out: &mut Reply<'_, W>,
+    command: &Command,
+    args: &str,
+    header: Option<(&str, &str)>,
+) -> Result<usize, CommandError<W::Error>> {
+    let mode = out.mode();
+    let cancel = out.cancel_signal();
+    let mut tx = out.nested();
+    let mut inner = Reply::new(&mut tx, mode, cancel);
+
+    if let Some((interval, line)) = header {
+        inner
+            .text_fmt(format_args!("Every {}: {}\r\n", interval, line))
+            .await?;
+    }
+    let result = command
+        .handler
+        .call_simple(&mut inner, Args::new(args))
+        .await;
+    // 里面的命令被取消了，整个 watch/repeat 也就结束了
+    if let Err(CommandError::Cancelled) = result {
+        return Err(CommandError::Cancelled);
+    }
+    conclude(&mut inner, command, result)
+        .await
+        .map_err(CommandError::Io)?;
+    drop(inner);
+    Ok(tx.lines())
+}
+
+pub(super) fn complete_watch(mut args: Args<'_>, candidates: &mut Candidates<'_>) {
+    match args.next_str() {
+        Ok(None) => {
+            for interval in ["500ms", "1s", "2s", "5s"] {
+                candidates.add(interval);
+            }
+        }
+        Ok(Some(_)) => complete_inner(args, candidates),
+        Err(_) => {}
+    }
+}
+
+pub(super) fn complete_repeat(mut args: Args<'_>, candidates: &mut Candidates<'_>) {
+    if let Ok(Some(_)) = args.next_str() {
+        complete_inner(args, candidates);
+    }
+}
+
+/// 补全里面的命令名，命令名之后交给那条命令自己的补全函数
+fn complete_inner(mut args: Args<'_>, candidates: &mut Candidates<'_>) {
+    match args.next_str() {
+        Ok(None) => {
+            for c in COMMANDS.iter().filter(|c| !c.handler.is_meta()) {
+                candidates.add(c.name);
+            }
+        }
+        Ok(Some(name)) => {
+            if let Some(complete) = find(name).and_then(|c| c.complete) {
+                complete(args, candidates);
+            }
+        }
+        Err(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_time::Timer;
+
+    use crate::shell::cancel::Cancel;
+    use crate::shell::commands::execute;
+    use crate::shell::output::Mode;
+    use crate::testing::{globals, MockTx};
+
+    /// 执行 `line`，`cancel_after` 毫秒之后按 Ctrl-C
+    fn exec(mode: Mode, line: &str, cancel_after: Option<u64>) -> String {
+        let mut tx = MockTx::default();
+        let mut mode = mode;
+        let cancel = Cancel::new();
+        let command = execute(&mut tx, &mut mode, &cancel, line);
+        let (result, ()) = block_on(join(command, async {
+            if let Some(ms) = cancel_after {
+                Timer::after_millis(ms).await;
+                assert!(cancel.request());
+            }
+        }));
+        result.unwrap();
+        String::from_utf8(tx.0).unwrap()
+    }
+
+    #[test]
+    fn repeat() {
+        let _globals = globals();
+        assert_eq!(
+            exec(Mode::Human, "repeat 3 hello", None),
+            "你好！\r\n你好！\r\n你好！\r\n"
+        );
+        let json = exec(Mode::Json, "repeat 2 hello", None);
+        assert_eq!(json.matches("greeting").count(), 2);
+        assert!(json.ends_with("{\"status\":\"ok\",\"code\":0,\"payload\":{\"runs\":2}}\r\n"));
+    }
+
+    #[test]
+    fn bad_arguments() {
+        let nested = exec(Mode::Human, "repeat 2 watch 1s hello", None);
+        assert!(nested.starts_with("Error: cannot nest"), "{}", nested);
+        for interval in ["0", "1h", "-1s", "ms"] {
+            let line = std::format!("watch {} hello", interval);
+            let out = exec(Mode::Human, &line, None);
+            assert!(out.starts_with("Error: invalid <interval>"), "{}", out);
+        }
+        let out = exec(Mode::Human, "watch", None);
+        assert!(out.starts_with("Error: missing <interval>"), "{}", out);
+    }
+
+    #[test]
+    fn watch_redraws() {
+        let _globals = globals();
+        let out = exec(Mode::Human, "watch 100ms hello", Some(250));
+        let frame = "Every 100ms: hello\r\n你好！\r\n";
+        assert!(out.starts_with(frame), "{:?}", out);
+        assert!(
+            out[frame.len()..].starts_with("\x1b[2A\r\x1b[JEvery"),
+            "{:?}",
+            out
+        );
+        assert!(out.ends_with("^C\r\n"), "{:?}", out);
+
+        let plain = exec(Mode::Plain, "watch 100ms hello", Some(250));
+        assert!(!plain.contains("\x1b["), "{:?}", plain);
+        assert!(plain.matches("Every 100ms").count() >= 2);
+    }
+}
@@ -0,0 +1,109 @@
+//! 主机上跑测试用的假串口和一些公共的东西。
+
+extern crate std;
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+use std::vec::Vec;
+
+use core::convert::Infallible;
+
+use crate::gpio::{PinDriver, PinMode, Pull};
+
+/// 按给定的分块返回数据的假串口，一次 `read` 最多返回一块，空的一块表示读到结尾，读完了 panic
+pub struct MockRx {
+    pub chunks: VecDeque<Vec<u8>>,
+}
+
+impl MockRx {
+    /// 把 `data` 切成每块 `size` 字节
+    pub fn chunked(data: &[u8], size: usize) -> Self {
+        Self {
+            chunks: data.chunks(size.max(1)).map(<[u8]>::to_vec).collect(),
+        }
+    }
+}
+
+impl embedded_io_async::ErrorType for MockRx {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Read for MockRx {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        let mut chunk = self.chunks.pop_front().expect("MockRx: no more data");
+        let n = chunk.len().min(buf.len());
+        buf[..n].copy_from_slice(&chunk[..n]);
+        if n < chunk.len() {
+            chunk.drain(..n);
+            self.chunks.push_front(chunk);
+        }
+        Ok(n)
+    }
+}
+
+/// 把写进来的字节都攒着的假串口
+#[derive(Default)]
+pub struct MockTx(pub Vec<u8>);
+
+impl embedded_io_async::ErrorType for MockTx {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Write for MockTx {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+/// 假引脚：输出模式下线上电平跟着输出走，上拉输入读到高电平，其余读到低电平
+#[derive(Default)]
+pub struct FakePin {
+    pub mode: Option<PinMode>,
+    pub out: bool,
+}
+
+impl PinDriver for FakePin {
+    fn set_mode(&mut self, mode: PinMode) {
+        self.mode = Some(mode);
+    }
+
+    fn set_output(&mut self, high: bool) {
+        self.out = high;
+    }
+
+    fn output(&self) -> bool {
+        self.out
+    }
+
+    fn level(&self) -> bool {
+        match self.mode {
+            Some(PinMode::Input(Pull::Up)) => true,
+            Some(PinMode::Output | PinMode::OpenDrain) => self.out,
+            _ => false,
+        }
+    }
+}
+
+static GLOBALS: Mutex<()> = Mutex::new(());
+
+/// 改全局状态（设置、引脚池、串口参数）的测试先拿这把锁，免得并行跑的时候互相影响
+pub fn globals() -> MutexGuard<'static, ()> {
+    GLOBALS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// 主机上没有 defmt 的 logger，日志直接丢掉
+#[no_mangle]
+fn _defmt_acquire() {}
+#[no_mangle]
+fn _defmt_release() {}
+#[no_mangle]
+fn _defmt_flush() {}
+#[no_mangle]
+fn _defmt_write(_: &[u8]) {}
+#[no_mangle]
+fn _defmt_timestamp(_: defmt::Formatter<'_>) {}
+#[no_mangle]
+fn _defmt_panic() -> ! {
+    panic!("defmt panic")
+}
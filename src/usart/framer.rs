@@ -0,0 +1,570 @@
+//! 分帧：把字节流切成一帧一帧。
+//!
+//! 原来 poll / block / interrupt / DMA 几种模式各自写死了“收到 `\0` 就结束一帧，
+//! 64 字节满了就先发出去”。现在换成 [`Framer`] trait，各模式接受任意一种分帧方式。
+//!
+//! 所有分帧器都是逐字节喂数据的，一帧被拆在多次 `read` 里也没关系。
+//! 以分隔符结尾的分帧器在缓冲区满时会先交出已收到的部分（和原来的行为一致），
+//! 新来的字节作为下一帧的开头。
+
+/// 分帧方式
+pub trait Framer {
+    /// 放入一个字节，返回 `true` 表示凑出了完整的一帧，可以用 [`frame`](Self::frame) 取出。
+    /// 下一次 `push` 之前 `frame` 的内容保持不变。
+    fn push(&mut self, byte: u8) -> bool;
+
+    /// 接收端检测到线路空闲时调用，返回 `true` 表示因此结束了一帧
+    fn idle(&mut self) -> bool {
+        false
+    }
+
+    /// 轮询/阻塞接收时等下一个字节超时了（见 [`timeout`](super::timeout)），返回 `true` 表示
+    /// 把收到一半的数据作为一帧交出。默认和线路空闲一样处理
+    fn timeout(&mut self) -> bool {
+        self.idle()
+    }
+
+    /// 刚凑出一帧时，分帧器里是不是已经留了下一帧的开头（缓冲区满时收到的那个字节）
+    fn carried(&self) -> bool {
+        false
+    }
+
+    /// 最近一次凑出的帧
+    fn frame(&self) -> &[u8];
+
+    /// 丢掉收到一半的数据
+    fn reset(&mut self);
+}
+
+impl<F: Framer + ?Sized> Framer for &mut F {
+    fn push(&mut self, byte: u8) -> bool {
+        (**self).push(byte)
+    }
+
+    fn idle(&mut self) -> bool {
+        (**self).idle()
+    }
+
+    fn timeout(&mut self) -> bool {
+        (**self).timeout()
+    }
+
+    fn carried(&self) -> bool {
+        (**self).carried()
+    }
+
+    fn frame(&self) -> &[u8] {
+        (**self).frame()
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+/// 各分帧器共用的缓冲区
+struct Acc<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    /// 上一帧已经交出去了，下次放数据之前要先清空
+    done: bool,
+    /// 缓冲区满时收到的那个字节，作为新一轮的第一个字节
+    carry: Option<u8>,
+}
+
+impl<const N: usize> Acc<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            done: false,
+            carry: None,
+        }
+    }
+
+    /// 每次 push 开头调用：上一帧交出去之后在这里清空
+    fn begin(&mut self) {
+        if self.done {
+            self.done = false;
+            self.len = 0;
+            if let Some(b) = self.carry.take() {
+                self.buf[0] = b;
+                self.len = 1;
+            }
+        }
+    }
+
+    /// 存一个字节；缓冲区已满时交出当前内容，这个字节留到下一帧
+    fn store(&mut self, byte: u8) -> bool {
+        if self.len < N {
+            self.buf[self.len] = byte;
+            self.len += 1;
+            false
+        } else {
+            self.carry = Some(byte);
+            self.finish()
+        }
+    }
+
+    /// 结束当前帧，空帧不算
+    fn finish(&mut self) -> bool {
+        self.done = self.len > 0;
+        self.done
+    }
+
+    fn frame(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn carried(&self) -> bool {
+        self.carry.is_some()
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.done = false;
+        self.carry = None;
+    }
+}
+
+/// 以 `\0` 结尾
+pub struct Nul<const N: usize> {
+    acc: Acc<N>,
+}
+
+impl<const N: usize> Nul<N> {
+    pub const fn new() -> Self {
+        Self { acc: Acc::new() }
+    }
+}
+
+impl<const N: usize> Default for Nul<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Framer for Nul<N> {
+    fn push(&mut self, byte: u8) -> bool {
+        self.acc.begin();
+        if byte == 0 {
+            self.acc.finish()
+        } else {
+            self.acc.store(byte)
+        }
+    }
+
+    fn timeout(&mut self) -> bool {
+        self.acc.begin();
+        self.acc.finish()
+    }
+
+    fn carried(&self) -> bool {
+        self.acc.carried()
+    }
+
+    fn frame(&self) -> &[u8] {
+        self.acc.frame()
+    }
+
+    fn reset(&mut self) {
+        self.acc.reset()
+    }
+}
+
+/// 以 `\n` 结尾，帧末尾的 `\r` 会被去掉，所以 `\r\n` 和单独的 `\n` 都可以
+pub struct CrLf<const N: usize> {
+    acc: Acc<N>,
+}
+
+impl<const N: usize> CrLf<N> {
+    pub const fn new() -> Self {
+        Self { acc: Acc::new() }
+    }
+}
+
+impl<const N: usize> Default for CrLf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Framer for CrLf<N> {
+    fn push(&mut self, byte: u8) -> bool {
+        self.acc.begin();
+        if byte == b'\n' {
+            if self.acc.frame().last() == Some(&b'\r') {
+                self.acc.len -= 1;
+            }
+            self.acc.finish()
+        } else {
+            self.acc.store(byte)
+        }
+    }
+
+    fn timeout(&mut self) -> bool {
+        self.acc.begin();
+        self.acc.finish()
+    }
+
+    fn carried(&self) -> bool {
+        self.acc.carried()
+    }
+
+    fn frame(&self) -> &[u8] {
+        self.acc.frame()
+    }
+
+    fn reset(&mut self) {
+        self.acc.reset()
+    }
+}
+
+/// 只以 `\r` 结尾，终端里按回车通常只发 `\r`。
+/// 紧跟在 `\r` 后面的 `\n` 会被丢掉，这样发 `\r\n` 的一端也不会多出空行。
+pub struct Cr<const N: usize> {
+    acc: Acc<N>,
+    /// 上一个字节是 `\r`
+    after_cr: bool,
+}
+
+impl<const N: usize> Cr<N> {
+    pub const fn new() -> Self {
+        Self {
+            acc: Acc::new(),
+            after_cr: false,
+        }
+    }
+}
+
+impl<const N: usize> Default for Cr<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Framer for Cr<N> {
+    fn push(&mut self, byte: u8) -> bool {
+        self.acc.begin();
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\r' => self.acc.finish(),
+            b'\n' if after_cr => false,
+            _ => self.acc.store(byte),
+        }
+    }
+
+    fn timeout(&mut self) -> bool {
+        self.acc.begin();
+        self.acc.finish()
+    }
+
+    fn carried(&self) -> bool {
+        self.acc.carried()
+    }
+
+    fn frame(&self) -> &[u8] {
+        self.acc.frame()
+    }
+
+    fn reset(&mut self) {
+        self.acc.reset();
+        self.after_cr = false;
+    }
+}
+
+/// 固定长度，每收满 `len` 个字节就是一帧
+pub struct Fixed<const N: usize> {
+    acc: Acc<N>,
+    len: usize,
+}
+
+impl<const N: usize> Fixed<N> {
+    /// `len` 不能超过 `N`，也不能是 0
+    pub const fn new(len: usize) -> Self {
+        assert!(len > 0 && len <= N);
+        Self {
+            acc: Acc::new(),
+            len,
+        }
+    }
+}
+
+impl<const N: usize> Framer for Fixed<N> {
+    fn push(&mut self, byte: u8) -> bool {
+        self.acc.begin();
+        self.acc.store(byte);
+        self.acc.len == self.len && self.acc.finish()
+    }
+
+    fn timeout(&mut self) -> bool {
+        // 收了一半的帧没法用，丢掉
+        self.reset();
+        false
+    }
+
+    fn frame(&self) -> &[u8] {
+        self.acc.frame()
+    }
+
+    fn reset(&mut self) {
+        self.acc.reset()
+    }
+}
+
+/// 长度前缀的宽度
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Prefix {
+    /// 1 字节长度
+    U8,
+    /// 2 字节大端长度
+    U16Be,
+}
+
+impl Prefix {
+    const fn width(self) -> usize {
+        match self {
+            Prefix::U8 => 1,
+            Prefix::U16Be => 2,
+        }
+    }
+}
+
+/// 每帧前面带长度，长度只算数据部分，`frame()` 里也只有数据部分。
+///
+/// 长度超过 `N` 的帧整帧丢掉，[`oversized`](Self::oversized) 记录丢了多少帧。
+/// 长度为 0 的帧会作为空帧交出。
+pub struct LengthPrefixed<const N: usize> {
+    acc: Acc<N>,
+    prefix: Prefix,
+    /// 已经收到的长度字节数
+    header_len: usize,
+    /// 当前帧的数据长度
+    expected: usize,
+    /// 当前帧太长，剩下还要丢掉多少字节
+    skip: usize,
+    oversized: u32,
+}
+
+impl<const N: usize> LengthPrefixed<N> {
+    pub const fn new(prefix: Prefix) -> Self {
+        Self {
+            acc: Acc::new(),
+            prefix,
+            header_len: 0,
+            expected: 0,
+            skip: 0,
+            oversized: 0,
+        }
+    }
+
+    /// 因为太长被丢掉的帧数
+    pub fn oversized(&self) -> u32 {
+        self.oversized
+    }
+
+    fn complete(&mut self) -> bool {
+        self.header_len = 0;
+        self.acc.done = true;
+        true
+    }
+}
+
+impl<const N: usize> Framer for LengthPrefixed<N> {
+    fn push(&mut self, byte: u8) -> bool {
+        self.acc.begin();
+
+        if self.skip > 0 {
+            self.skip -= 1;
+            return false;
+        }
+
+        if self.header_len < self.prefix.width() {
+            self.expected = (self.expected << 8) | byte as usize;
+            self.header_len += 1;
+            if self.header_len < self.prefix.width() {
+                return false;
+            }
+            if self.expected > N {
+                self.skip = self.expected;
+                self.expected = 0;
+                self.header_len = 0;
+                self.oversized = self.oversized.wrapping_add(1);
+                return false;
+            }
+            return self.expected == 0 && self.complete();
+        }
+
+        self.acc.store(byte);
+        if self.acc.len == self.expected {
+            self.expected = 0;
+            return self.complete();
+        }
+        false
+    }
+
+    fn timeout(&mut self) -> bool {
+        // 收了一半的帧没法用，丢掉
+        self.reset();
+        false
+    }
+
+    fn frame(&self) -> &[u8] {
+        self.acc.frame()
+    }
+
+    fn reset(&mut self) {
+        self.acc.reset();
+        self.header_len = 0;
+        self.expected = 0;
+        self.skip = 0;
+    }
+}
+
+/// 线路空闲时结束一帧，对应原来 `read_until_idle` 的行为。
+///
+/// 需要接收端在空闲时调用 [`Framer::idle`]，见 [`FramedReader`](super::FramedReader)。
+pub struct Idle<const N: usize> {
+    acc: Acc<N>,
+}
+
+impl<const N: usize> Idle<N> {
+    pub const fn new() -> Self {
+        Self { acc: Acc::new() }
+    }
+}
+
+impl<const N: usize> Default for Idle<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Framer for Idle<N> {
+    fn push(&mut self, byte: u8) -> bool {
+        self.acc.begin();
+        self.acc.store(byte)
+    }
+
+    fn idle(&mut self) -> bool {
+        self.acc.begin();
+        self.acc.finish()
+    }
+
+    fn carried(&self) -> bool {
+        self.acc.carried()
+    }
+
+    fn frame(&self) -> &[u8] {
+        self.acc.frame()
+    }
+
+    fn reset(&mut self) {
+        self.acc.reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::testing::MockRx;
+    use crate::usart::FramedReader;
+
+    /// 把 `stream` 按每次 1 字节到整段的各种大小拆开读，每种拆法都要切出 `expected`
+    fn split_reads<F: Framer>(mut new: impl FnMut() -> F, stream: &[u8], expected: &[&[u8]]) {
+        for size in 1..=stream.len() {
+            let mut reader = FramedReader::new(MockRx::chunked(stream, size), new());
+            for want in expected {
+                let got = block_on(reader.next_frame()).unwrap();
+                assert_eq!(got, *want, "read size {}", size);
+            }
+        }
+    }
+
+    #[test]
+    fn nul() {
+        split_reads(Nul::<8>::new, b"ab\0\0cd\0", &[b"ab", b"cd"]);
+        // 满了先交出一部分，剩下的作为下一帧
+        split_reads(Nul::<4>::new, b"abcdef\0", &[b"abcd", b"ef"]);
+    }
+
+    #[test]
+    fn crlf() {
+        split_reads(
+            CrLf::<8>::new,
+            b"a\r\nb\n\r\nc\rd\n",
+            &[b"a", b"b", b"c\rd"],
+        );
+    }
+
+    #[test]
+    fn cr() {
+        split_reads(Cr::<8>::new, b"a\r\nb\rc\r\n\r", &[b"a", b"b", b"c"]);
+    }
+
+    #[test]
+    fn fixed() {
+        split_reads(|| Fixed::<8>::new(3), b"abcdefg", &[b"abc", b"def"]);
+    }
+
+    #[test]
+    fn length_prefixed() {
+        let mut stream = Vec::from(*b"\x00\x02hi\x00\x00\x00\x14");
+        stream.extend([7; 20]);
+        stream.extend(b"\x00\x01x");
+        split_reads(
+            || LengthPrefixed::<8>::new(Prefix::U16Be),
+            &stream,
+            &[b"hi", b"", b"x"],
+        );
+        split_reads(
+            || LengthPrefixed::<8>::new(Prefix::U8),
+            b"\x03abc\x09123456789\x01z",
+            &[b"abc", b"z"],
+        );
+
+        let mut framer = LengthPrefixed::<4>::new(Prefix::U8);
+        for &b in b"\x09123456789\x01z" {
+            framer.push(b);
+        }
+        assert_eq!(framer.oversized(), 1);
+        assert_eq!(framer.frame(), b"z");
+    }
+
+    #[test]
+    fn idle() {
+        // 每次没读满都当成线路空闲
+        let rx = MockRx {
+            chunks: [b"abc".to_vec(), b"de".to_vec(), b"123456".to_vec()].into(),
+        };
+        let mut reader = FramedReader::new(rx, Idle::<4>::new());
+        for want in [&b"abc"[..], b"de", b"1234", b"56"] {
+            assert_eq!(block_on(reader.next_frame()).unwrap(), want);
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let mut nul = Nul::<8>::new();
+        for &b in b"ab" {
+            assert!(!nul.push(b));
+        }
+        assert!(nul.timeout());
+        assert_eq!(nul.frame(), b"ab");
+        assert!(!nul.timeout());
+
+        // 定长和长度前缀收了一半就丢掉
+        let mut fixed = Fixed::<8>::new(3);
+        fixed.push(b'a');
+        assert!(!fixed.timeout());
+        let frame: Vec<bool> = b"xyz".iter().map(|&b| fixed.push(b)).collect();
+        assert_eq!(frame, [false, false, true]);
+        assert_eq!(fixed.frame(), b"xyz");
+    }
+}
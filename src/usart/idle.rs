@@ -0,0 +1,44 @@
+//! 把 `embassy_stm32` 的 `UartRx` 包装成 `embedded_io_async::Read`。
+//!
+//! `UartRx<Async>` 本身没有实现这个 trait，库里的通用逻辑只认 trait，所以需要这一层。
+
+use embassy_stm32::mode::Async;
+use embassy_stm32::usart::{Error, UartRx};
+
+/// 每次 `read` 都用 `read_until_idle`，总线空闲就返回已收到的部分
+pub struct IdleRx<'d>(pub UartRx<'d, Async>);
+
+impl embedded_io_async::ErrorType for IdleRx<'_> {
+    type Error = Error;
+}
+
+impl embedded_io_async::Read for IdleRx<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = self.0.read_until_idle(buf).await?;
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+/// 每次 `read` 只收一个字节，对应原来 interrupt 例子里的逐字节读取
+pub struct ByteRx<'d>(pub UartRx<'d, Async>);
+
+impl embedded_io_async::ErrorType for ByteRx<'_> {
+    type Error = Error;
+}
+
+impl embedded_io_async::Read for ByteRx<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.0.read(&mut buf[..1]).await?;
+        Ok(1)
+    }
+}
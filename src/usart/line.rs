@@ -0,0 +1,218 @@
+//! 运行时修改串口的波特率、数据位、校验位和停止位。
+//!
+//! 修改由 shell 或者其他任务通过 [`request`] 发起，真正动寄存器的是拿着接收端的任务
+//! （循环 DMA 在重新配置之后要重新启动）。切换的顺序是：
+//!
+//! 1. 发起方先用旧的参数把应答发完（`flush`），再调用 [`request`]；
+//! 2. 接收任务按新参数重新配置串口；
+//! 3. [`FALLBACK_TIMEOUT`] 之内收到一帧数据就算切换成功，否则退回旧的参数，
+//!    免得两边参数对不上以后再也连不上。
+
+use core::cell::Cell;
+use core::fmt;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Duration;
+
+/// 切换之后这么久没收到数据就退回原来的参数
+pub const FALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 常用的波特率，自动检测的结果会往这里面靠
+pub const STANDARD_BAUDRATES: [u32; 12] = [
+    1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600, 1000000,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum DataBits {
+    Seven,
+    Eight,
+    Nine,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum StopBits {
+    Half,
+    One,
+    OneAndHalf,
+    Two,
+}
+
+/// 串口线路参数
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct LineConfig {
+    pub baudrate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl LineConfig {
+    /// 和 `usart::Config::default()` 一样：115200 8N1
+    pub const DEFAULT: Self = Self {
+        baudrate: 115200,
+        data_bits: DataBits::Eight,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+    };
+
+    /// 解析 `<波特率> [格式]`，格式是 `8N1`、`7E2`、`8O1.5` 这样的写法，省略时保留 `self` 的设置
+    pub fn parse(&self, baudrate: &str, format: Option<&str>) -> Result<Self, ParseError> {
+        let baudrate = baudrate.parse().map_err(|_| ParseError::Baudrate)?;
+        if baudrate == 0 {
+            return Err(ParseError::Baudrate);
+        }
+        let mut config = Self { baudrate, ..*self };
+
+        if let Some(format) = format {
+            let mut chars = format.chars();
+            config.data_bits = match chars.next() {
+                Some('7') => DataBits::Seven,
+                Some('8') => DataBits::Eight,
+                Some('9') => DataBits::Nine,
+                _ => return Err(ParseError::Format),
+            };
+            config.parity = match chars.next().map(|c| c.to_ascii_uppercase()) {
+                Some('N') => Parity::None,
+                Some('E') => Parity::Even,
+                Some('O') => Parity::Odd,
+                _ => return Err(ParseError::Format),
+            };
+            config.stop_bits = match chars.as_str() {
+                "0.5" => StopBits::Half,
+                "1" => StopBits::One,
+                "1.5" => StopBits::OneAndHalf,
+                "2" => StopBits::Two,
+                _ => return Err(ParseError::Format),
+            };
+        }
+
+        // 硬件只支持这几种字长：校验位也算在 7/8/9 位里
+        match (config.data_bits, config.parity) {
+            (DataBits::Nine, Parity::Even | Parity::Odd) => Err(ParseError::Unsupported),
+            _ => Ok(config),
+        }
+    }
+}
+
+impl fmt::Display for LineConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let data = match self.data_bits {
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+            DataBits::Nine => 9,
+        };
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+        let stop = match self.stop_bits {
+            StopBits::Half => "0.5",
+            StopBits::One => "1",
+            StopBits::OneAndHalf => "1.5",
+            StopBits::Two => "2",
+        };
+        write!(f, "{} {}{}{}", self.baudrate, data, parity, stop)
+    }
+}
+
+/// 参数解析错误
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ParseError {
+    /// 波特率不是正整数
+    Baudrate,
+    /// 格式不是 `8N1` 这样的写法
+    Format,
+    /// 硬件不支持的组合（9 位数据加校验）
+    Unsupported,
+}
+
+impl ParseError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParseError::Baudrate => "invalid baud rate",
+            ParseError::Format => "invalid format, expected e.g. 8N1",
+            ParseError::Unsupported => "9 data bits with parity is not supported",
+        }
+    }
+}
+
+/// 把测出来的波特率对齐到最近的常用值，误差超过 5% 就原样返回
+pub fn nearest_standard(baudrate: u32) -> u32 {
+    STANDARD_BAUDRATES
+        .iter()
+        .copied()
+        .min_by_key(|&b| b.abs_diff(baudrate))
+        .filter(|&b| b.abs_diff(baudrate) * 20 <= b)
+        .unwrap_or(baudrate)
+}
+
+/// 对接收任务的请求
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum LineRequest {
+    /// 换成新的参数
+    Set(LineConfig),
+    /// 用下一个收到的字符自动检测波特率，其他参数不变
+    AutoBaud,
+}
+
+static REQUEST: Signal<CriticalSectionRawMutex, LineRequest> = Signal::new();
+static CURRENT: Mutex<CriticalSectionRawMutex, Cell<LineConfig>> =
+    Mutex::new(Cell::new(LineConfig::DEFAULT));
+
+/// 请求接收任务切换参数。调用之前要先把用旧参数发的数据 `flush` 完
+pub fn request(req: LineRequest) {
+    REQUEST.signal(req);
+}
+
+/// 接收任务等待切换请求
+pub async fn wait_request() -> LineRequest {
+    REQUEST.wait().await
+}
+
+/// 当前正在使用的参数
+pub fn current() -> LineConfig {
+    CURRENT.lock(|c| c.get())
+}
+
+/// 接收任务切换成功之后记下新的参数
+pub fn set_current(config: LineConfig) {
+    CURRENT.lock(|c| c.set(config));
+}
+
+#[cfg(target_os = "none")]
+impl LineConfig {
+    /// 转成 `embassy_stm32` 的串口配置
+    pub fn to_config(&self) -> embassy_stm32::usart::Config {
+        use embassy_stm32::usart;
+
+        let mut config = usart::Config::default();
+        config.baudrate = self.baudrate;
+        config.data_bits = match self.data_bits {
+            DataBits::Seven => usart::DataBits::DataBits7,
+            DataBits::Eight => usart::DataBits::DataBits8,
+            DataBits::Nine => usart::DataBits::DataBits9,
+        };
+        config.parity = match self.parity {
+            Parity::None => usart::Parity::ParityNone,
+            Parity::Even => usart::Parity::ParityEven,
+            Parity::Odd => usart::Parity::ParityOdd,
+        };
+        config.stop_bits = match self.stop_bits {
+            StopBits::Half => usart::StopBits::STOP0P5,
+            StopBits::One => usart::StopBits::STOP1,
+            StopBits::OneAndHalf => usart::StopBits::STOP1P5,
+            StopBits::Two => usart::StopBits::STOP2,
+        };
+        config
+    }
+}
@@ -0,0 +1,190 @@
+//! 串口收包相关的公共逻辑。
+//!
+//! 这里的代码只依赖 `embedded_io_async::Read`，不关心底下是 DMA、中断还是主机上的模拟串口。
+//! 真正和 `embassy_stm32` 打交道的适配器放在 `idle` 和 `ring` 里，只在 MCU 上编译。
+
+pub mod framer;
+#[cfg(target_os = "none")]
+mod idle;
+pub mod line;
+#[cfg(target_os = "none")]
+mod ring;
+pub mod stats;
+pub mod timeout;
+pub mod utf8;
+
+pub use framer::Framer;
+#[cfg(target_os = "none")]
+pub use idle::{ByteRx, IdleRx};
+#[cfg(target_os = "none")]
+pub use ring::RingRx;
+pub use timeout::Timeouts;
+
+use embedded_io_async::Read;
+
+use crate::pipeline::{Pipeline, Sent};
+use crate::pool;
+
+/// 一帧的最大长度，和原来各个 binary 里的 `[u8; 64]` 保持一致
+pub const FRAME_SIZE: usize = 64;
+/// 最多有几帧在排队等处理
+pub const QUEUE_DEPTH: usize = 4;
+/// 队列排满时生产者手里还有一块、消费者手里还有一块，所以池要比队列多两块，
+/// 这样借缓冲区永远不会卡住，队列满了怎么办完全由 [`Pipeline`] 的策略决定
+pub const POOL_FRAMES: usize = QUEUE_DEPTH + 2;
+
+/// 接收任务和处理任务之间传递帧用的缓冲池
+pub type FramePool = pool::FramePool<FRAME_SIZE, POOL_FRAMES>;
+/// 从 [`FramePool`] 借出的一帧，处理完丢掉就还回池里
+pub type Frame = pool::Frame<'static, FRAME_SIZE, POOL_FRAMES>;
+/// 把整帧交给处理任务
+pub type FramePipeline = Pipeline<Frame, QUEUE_DEPTH>;
+
+/// [`FramedReader`] 收帧出错
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ReadError<E> {
+    /// 输入读到了结尾（`read` 返回 0，比如 TCP 对端关闭），收到一半的帧丢掉
+    Eof,
+    /// 读输入出错
+    Read(E),
+}
+
+/// 从 `Read` 里读数据交给分帧器，一次取出一帧。
+///
+/// 一次 `read` 读到的数据可能包含好几帧，没用完的部分留到下一次。
+/// `read` 没有填满缓冲区就返回时视为线路空闲，会调用 [`Framer::idle`]：
+/// `IdleRx` 正好是这样；`RingRx` 在 DMA 半满时也可能提前返回，所以配合
+/// [`framer::Idle`] 时帧边界只是近似的。
+pub struct FramedReader<R, F> {
+    rx: R,
+    framer: F,
+    chunk: [u8; FRAME_SIZE],
+    pos: usize,
+    len: usize,
+    /// 上一次 read 没读满
+    short: bool,
+}
+
+impl<R: Read, F: Framer> FramedReader<R, F> {
+    pub fn new(rx: R, framer: F) -> Self {
+        Self {
+            rx,
+            framer,
+            chunk: [0; FRAME_SIZE],
+            pos: 0,
+            len: 0,
+            short: false,
+        }
+    }
+
+    pub fn rx(&self) -> &R {
+        &self.rx
+    }
+
+    pub fn rx_mut(&mut self) -> &mut R {
+        &mut self.rx
+    }
+
+    pub fn framer(&self) -> &F {
+        &self.framer
+    }
+
+    pub fn framer_mut(&mut self) -> &mut F {
+        &mut self.framer
+    }
+
+    /// 读到下一帧为止
+    pub async fn next_frame(&mut self) -> Result<&[u8], ReadError<R::Error>> {
+        loop {
+            while self.pos < self.len {
+                let byte = self.chunk[self.pos];
+                self.pos += 1;
+                if self.framer.push(byte) {
+                    return Ok(self.framer.frame());
+                }
+            }
+
+            if core::mem::take(&mut self.short) && self.framer.idle() {
+                return Ok(self.framer.frame());
+            }
+
+            let n = self
+                .rx
+                .read(&mut self.chunk)
+                .await
+                .map_err(ReadError::Read)?;
+            if n == 0 {
+                self.framer.reset();
+                return Err(ReadError::Eof);
+            }
+            self.pos = 0;
+            self.len = n;
+            self.short = n < self.chunk.len();
+        }
+    }
+}
+
+/// 读一帧数据，拷进从池里借的缓冲区，整块发给处理任务。
+///
+/// 每一帧都有自己的缓冲区，处理任务还没处理完的帧不会被下一帧覆盖。
+/// 超过 [`FRAME_SIZE`] 的帧分几次交给处理任务，不会被截断。
+/// 处理任务跟不上时按 `pipeline` 的策略等待或者丢弃，丢了的片段数从 `Ok` 的第二项返回。
+/// `keep` 返回 `false` 的字节不交给处理任务，比如 shell 命令执行时的 Ctrl-C。
+/// 返回这一帧的字节数。
+pub async fn receive<R: Read, F: Framer>(
+    reader: &mut FramedReader<R, F>,
+    pool: &'static FramePool,
+    pipeline: &FramePipeline,
+    mut keep: impl FnMut(u8) -> bool,
+) -> Result<(usize, usize), ReadError<R::Error>> {
+    let frame = reader.next_frame().await?;
+
+    let mut dropped = 0;
+    for piece in frame.chunks(FRAME_SIZE) {
+        // 先过滤再等缓冲区和队列：`keep` 里可能要马上响应某些字节，不能等处理任务腾出地方
+        let mut kept = [0; FRAME_SIZE];
+        let mut n = 0;
+        for &byte in piece {
+            if keep(byte) {
+                kept[n] = byte;
+                n += 1;
+            }
+        }
+        if n == 0 {
+            continue;
+        }
+
+        let mut buf = pool.alloc().await;
+        buf.extend_from_slice(&kept[..n]);
+        if pipeline.send(buf).await != Sent::Queued {
+            dropped += 1;
+        }
+    }
+    Ok((frame.len(), dropped))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::framer::CrLf;
+    use super::*;
+    use crate::testing::MockRx;
+
+    #[test]
+    fn eof_mid_frame() {
+        let mut rx = MockRx::chunked(b"one\r\ntw", 64);
+        rx.chunks.push_back(Vec::new());
+        rx.chunks.push_back(Vec::new());
+        let mut reader = FramedReader::new(rx, CrLf::<16>::new());
+        assert_eq!(block_on(reader.next_frame()), Ok(&b"one"[..]));
+        // 收到一半的 "tw" 丢掉，之后再读还是结尾
+        assert_eq!(block_on(reader.next_frame()), Err(ReadError::Eof));
+        assert_eq!(block_on(reader.next_frame()), Err(ReadError::Eof));
+        assert!(reader.rx().chunks.is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+//! 基于 `RingBufferedUartRx` 的循环 DMA 接收。
+//!
+//! `read_until_idle` 每次都要重新启动 DMA，两次调用之间（比如处理任务还拿着锁的时候）
+//! 到达的字节会丢掉，缓冲区也只有调用方给的那么大。循环 DMA 在后台一直收，
+//! `read` 只是把已经收到的数据拷出来，所以不会截断也不会漏字节，
+//! 只有处理速度长期跟不上、环形缓冲区被写满时才会丢数据，这时返回 `Error::Overrun`。
+
+use embassy_stm32::mode::Async;
+use embassy_stm32::usart::{Config, ConfigError, Error, RingBufferedUartRx, UartRx};
+
+pub struct RingRx<'d> {
+    rx: RingBufferedUartRx<'d>,
+    overruns: u32,
+}
+
+impl<'d> RingRx<'d> {
+    /// `dma_buf` 的大小决定了能扛住多长的突发数据，921600 波特率下 1 KB 大约是 11 ms
+    pub fn new(rx: UartRx<'d, Async>, dma_buf: &'d mut [u8]) -> Self {
+        let mut rx = rx.into_ring_buffered(dma_buf);
+        rx.start_uart();
+        Self { rx, overruns: 0 }
+    }
+
+    /// 读出已经收到的数据，至少一个字节，最多 `buf.len()` 个。
+    ///
+    /// 返回 `Err(Error::Overrun)` 说明有数据丢了（环形缓冲区满或者 USART 本身溢出），
+    /// 接收会被停掉，下一次调用 `read` 时自动重新启动。
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self.rx.read(buf).await {
+            Err(Error::Overrun) => {
+                self.overruns = self.overruns.wrapping_add(1);
+                Err(Error::Overrun)
+            }
+            other => other,
+        }
+    }
+
+    /// 修改串口参数。整个 USART 会被重新配置（发送端也一样），然后重新启动循环 DMA。
+    ///
+    /// 配置时 USART 要先关掉，正在收的那个字节会丢。
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        self.rx.set_config(config)?;
+        // 重新配置会清掉空闲中断，要重新打开
+        self.rx.start_uart();
+        Ok(())
+    }
+
+    /// 到目前为止发生过多少次溢出
+    pub fn overruns(&self) -> u32 {
+        self.overruns
+    }
+}
+
+impl embedded_io_async::ErrorType for RingRx<'_> {
+    type Error = Error;
+}
+
+impl embedded_io_async::Read for RingRx<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        RingRx::read(self, buf).await
+    }
+}
@@ -0,0 +1,185 @@
+//! 串口接收错误的分类、计数和自动恢复策略。
+//!
+//! 原来各个接收循环遇到错误只是打一条日志然后 `continue`，分不清是溢出、帧错误、噪声还是校验错，
+//! 串口卡死以后只能断电重启。现在每个端口有一份 [`PortStats`]，接收循环用 [`ErrorMonitor`]
+//! 判断错误是否密集到需要重新初始化外设。
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+/// 接收错误的种类
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ErrorKind {
+    /// 数据没来得及取走就被新数据覆盖（USART 溢出或者 DMA 环形缓冲区满）
+    Overrun,
+    /// 没有在该出现停止位的地方检测到停止位，多半是波特率不对
+    Framing,
+    /// 采样时检测到噪声
+    Noise,
+    /// 校验位不对
+    Parity,
+    /// 其他错误
+    Other,
+}
+
+/// 一个串口的收发统计
+#[derive(Clone, Copy, Default, Debug, defmt::Format)]
+pub struct UartStats {
+    pub rx_frames: u32,
+    pub rx_bytes: u32,
+    pub overrun: u32,
+    pub framing: u32,
+    pub noise: u32,
+    pub parity: u32,
+    pub other: u32,
+    /// 因为错误太多重新初始化的次数
+    pub recoveries: u32,
+}
+
+impl UartStats {
+    pub fn errors(&self) -> u32 {
+        self.overrun
+            .wrapping_add(self.framing)
+            .wrapping_add(self.noise)
+            .wrapping_add(self.parity)
+            .wrapping_add(self.other)
+    }
+
+    fn count(&mut self, kind: ErrorKind) {
+        let counter = match kind {
+            ErrorKind::Overrun => &mut self.overrun,
+            ErrorKind::Framing => &mut self.framing,
+            ErrorKind::Noise => &mut self.noise,
+            ErrorKind::Parity => &mut self.parity,
+            ErrorKind::Other => &mut self.other,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+}
+
+/// 可以在任务之间共享的统计，放在 `static` 里
+pub struct PortStats(Mutex<CriticalSectionRawMutex, Cell<UartStats>>);
+
+impl PortStats {
+    pub const fn new() -> Self {
+        Self(Mutex::new(Cell::new(UartStats {
+            rx_frames: 0,
+            rx_bytes: 0,
+            overrun: 0,
+            framing: 0,
+            noise: 0,
+            parity: 0,
+            other: 0,
+            recoveries: 0,
+        })))
+    }
+
+    fn update(&self, f: impl FnOnce(&mut UartStats)) {
+        self.0.lock(|c| {
+            let mut stats = c.get();
+            f(&mut stats);
+            c.set(stats);
+        });
+    }
+
+    /// 收到一帧
+    pub fn frame(&self, len: usize) {
+        self.update(|s| {
+            s.rx_frames = s.rx_frames.wrapping_add(1);
+            s.rx_bytes = s.rx_bytes.wrapping_add(len as u32);
+        });
+    }
+
+    pub fn error(&self, kind: ErrorKind) {
+        self.update(|s| s.count(kind));
+    }
+
+    pub fn recovered(&self) {
+        self.update(|s| s.recoveries = s.recoveries.wrapping_add(1));
+    }
+
+    pub fn get(&self) -> UartStats {
+        self.0.lock(|c| c.get())
+    }
+
+    pub fn reset(&self) {
+        self.0.lock(|c| c.set(UartStats::default()));
+    }
+}
+
+impl Default for PortStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// shell 所在串口（USART3）的统计，`uart stats` 命令显示的就是它
+pub static CONSOLE: PortStats = PortStats::new();
+
+/// 什么时候该重新初始化外设
+#[derive(Clone, Copy, Debug, defmt::Format)]
+pub struct RecoveryPolicy {
+    /// `window` 之内出现这么多次错误就重新初始化
+    pub max_errors: u32,
+    pub window: Duration,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self {
+            max_errors: 8,
+            window: Duration::from_secs(1),
+        }
+    }
+}
+
+/// 按 [`RecoveryPolicy`] 统计一段时间内的错误次数
+pub struct ErrorMonitor {
+    policy: RecoveryPolicy,
+    window_start: Instant,
+    errors: u32,
+}
+
+impl ErrorMonitor {
+    pub const fn new(policy: RecoveryPolicy) -> Self {
+        Self {
+            policy,
+            window_start: Instant::from_ticks(0),
+            errors: 0,
+        }
+    }
+
+    /// 记一次错误，返回 `true` 表示该重新初始化了（计数随之清零）
+    pub fn record(&mut self, now: Instant) -> bool {
+        if self.errors == 0 || now.saturating_duration_since(self.window_start) > self.policy.window {
+            self.window_start = now;
+            self.errors = 0;
+        }
+        self.errors += 1;
+
+        if self.errors >= self.policy.max_errors {
+            self.errors = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(target_os = "none")]
+impl From<embassy_stm32::usart::Error> for ErrorKind {
+    fn from(e: embassy_stm32::usart::Error) -> Self {
+        use embassy_stm32::usart::Error;
+
+        match e {
+            Error::Overrun => ErrorKind::Overrun,
+            Error::Framing => ErrorKind::Framing,
+            Error::Noise => ErrorKind::Noise,
+            Error::Parity => ErrorKind::Parity,
+            _ => ErrorKind::Other,
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! 轮询/阻塞接收时的字节间超时和整条消息超时。
+//!
+//! DMA 和中断模式可以靠 `read_until_idle` 知道一条消息什么时候结束，阻塞和轮询模式原来只能等
+//! 分隔符或者缓冲区满，对端发到一半停下来，数据就一直卡在缓冲区里。这里用 `embassy_time::Instant`
+//! 计时：两个字节之间隔得太久，或者一条消息从第一个字节算起拖得太久，都算这条消息结束了，
+//! 通过 [`Framer::timeout`] 交给分帧器决定收到一半的数据怎么处理。
+
+use embassy_time::{Duration, Instant, Timer};
+
+use super::Framer;
+
+/// 超时设置，`None` 表示不限制
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct Timeouts {
+    /// 两个字节之间最多隔多久
+    pub inter_byte: Option<Duration>,
+    /// 从一条消息的第一个字节算起最多多久
+    pub message: Option<Duration>,
+}
+
+impl Timeouts {
+    /// 不超时，和原来的行为一样
+    pub const NONE: Self = Self {
+        inter_byte: None,
+        message: None,
+    };
+
+    pub const fn new(inter_byte: Duration, message: Duration) -> Self {
+        Self {
+            inter_byte: Some(inter_byte),
+            message: Some(message),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// 一条消息是怎么结束的
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Ended {
+    /// 分帧器自己凑出了完整的一帧（分隔符、长度够了、缓冲区满）
+    Frame,
+    /// 字节间超时
+    InterByte,
+    /// 整条消息超时
+    Message,
+}
+
+/// 记录当前这条消息的第一个字节和最近一个字节是什么时候收到的
+pub struct MessageTimer {
+    timeouts: Timeouts,
+    /// 当前消息第一个字节的时间，`None` 表示还没开始收
+    start: Option<Instant>,
+    last: Instant,
+}
+
+impl MessageTimer {
+    pub const fn new(timeouts: Timeouts) -> Self {
+        Self {
+            timeouts,
+            start: None,
+            last: Instant::from_ticks(0),
+        }
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    /// 收到一个字节
+    pub fn byte(&mut self, now: Instant) {
+        if self.start.is_none() {
+            self.start = Some(now);
+        }
+        self.last = now;
+    }
+
+    /// 当前消息结束了（不管是怎么结束的），重新开始计时
+    pub fn reset(&mut self) {
+        self.start = None;
+    }
+
+    /// 检查是否超时。还没收到字节的时候不会超时
+    pub fn expired(&self, now: Instant) -> Option<Ended> {
+        let start = self.start?;
+        if let Some(t) = self.timeouts.message {
+            if now.saturating_duration_since(start) >= t {
+                return Some(Ended::Message);
+            }
+        }
+        if let Some(t) = self.timeouts.inter_byte {
+            if now.saturating_duration_since(self.last) >= t {
+                return Some(Ended::InterByte);
+            }
+        }
+        None
+    }
+}
+
+/// 不停地调用 `try_read` 取字节交给 `framer`，直到凑出一帧或者超时结束了一帧。
+///
+/// `try_read` 返回 `None` 表示暂时没有数据（轮询模式的 `WouldBlock`），接收错误由它自己处理。
+/// 超时以后分帧器不肯交出数据（比如长度前缀的帧收了一半）就继续等下一帧。
+pub fn read_frame<F>(
+    framer: &mut F,
+    timer: &mut MessageTimer,
+    mut try_read: impl FnMut() -> Option<u8>,
+) -> Ended
+where
+    F: Framer + ?Sized,
+{
+    loop {
+        let now = Instant::now();
+        if let Some(ended) = step(framer, timer, try_read(), now) {
+            return ended;
+        }
+    }
+}
+
+/// 和 [`read_frame`] 一样，只是没有数据时不空转，让出 CPU 等 `period` 再来看，
+/// 同一个 executor 上的其它任务这时可以跑
+pub async fn poll_frame<F>(
+    framer: &mut F,
+    timer: &mut MessageTimer,
+    period: Duration,
+    mut try_read: impl FnMut() -> Option<u8>,
+) -> Ended
+where
+    F: Framer + ?Sized,
+{
+    loop {
+        let now = Instant::now();
+        let byte = try_read();
+        if let Some(ended) = step(framer, timer, byte, now) {
+            return ended;
+        }
+        if byte.is_none() {
+            Timer::after(period).await;
+        }
+    }
+}
+
+/// `now` 时刻读了一次，读到的是 `byte`。结束了一帧就返回是怎么结束的
+fn step<F>(
+    framer: &mut F,
+    timer: &mut MessageTimer,
+    byte: Option<u8>,
+    now: Instant,
+) -> Option<Ended>
+where
+    F: Framer + ?Sized,
+{
+    match byte {
+        Some(byte) => {
+            timer.byte(now);
+            if !framer.push(byte) {
+                return None;
+            }
+            timer.reset();
+            // 缓冲区满时多出来的那个字节留在分帧器里，算作下一条消息的开头
+            if framer.carried() {
+                timer.byte(now);
+            }
+            Some(Ended::Frame)
+        }
+        None => {
+            let ended = timer.expired(now)?;
+            timer.reset();
+            framer.timeout().then_some(ended)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::usart::framer::Nul;
+
+    const TIMEOUTS: Timeouts = Timeouts::new(Duration::from_millis(20), Duration::from_secs(1));
+
+    fn ms(n: u64) -> Instant {
+        Instant::from_millis(n)
+    }
+
+    /// 在 `at` 毫秒时依次收到 `bytes`，最后一个字节的结果
+    fn feed(framer: &mut Nul<2>, timer: &mut MessageTimer, bytes: &[u8], at: u64) -> Option<Ended> {
+        let mut ended = None;
+        for &byte in bytes {
+            ended = step(framer, timer, Some(byte), ms(at));
+        }
+        ended
+    }
+
+    #[test]
+    fn idle_after_frame_is_not_a_timeout() {
+        let mut framer = Nul::<2>::new();
+        let mut timer = MessageTimer::new(TIMEOUTS);
+        assert_eq!(
+            feed(&mut framer, &mut timer, b"hi\0", 0),
+            Some(Ended::Frame)
+        );
+        // 帧结束之后线路一直空闲，没有新消息，不该超时
+        for at in [10, 50, 2000] {
+            assert_eq!(step(&mut framer, &mut timer, None, ms(at)), None);
+        }
+        assert_eq!(framer.frame(), b"hi");
+    }
+
+    #[test]
+    fn carried_byte_starts_next_message() {
+        let mut framer = Nul::<2>::new();
+        let mut timer = MessageTimer::new(TIMEOUTS);
+        // 缓冲区满了交出 "ab"，"c" 留下来作为下一条消息的开头
+        assert_eq!(feed(&mut framer, &mut timer, b"abc", 0), Some(Ended::Frame));
+        assert_eq!(framer.frame(), b"ab");
+        assert_eq!(step(&mut framer, &mut timer, None, ms(10)), None);
+        assert_eq!(
+            step(&mut framer, &mut timer, None, ms(20)),
+            Some(Ended::InterByte)
+        );
+        assert_eq!(framer.frame(), b"c");
+    }
+
+    #[test]
+    fn message_timeout() {
+        let mut framer = Nul::<2>::new();
+        let mut timer = MessageTimer::new(Timeouts::new(
+            Duration::from_secs(1),
+            Duration::from_millis(50),
+        ));
+        assert_eq!(feed(&mut framer, &mut timer, b"a", 0), None);
+        assert_eq!(step(&mut framer, &mut timer, None, ms(49)), None);
+        assert_eq!(
+            step(&mut framer, &mut timer, None, ms(50)),
+            Some(Ended::Message)
+        );
+        assert_eq!(framer.frame(), b"a");
+    }
+
+    #[test]
+    fn poll_frame_waits_for_data() {
+        let mut framer = Nul::<8>::new();
+        let mut timer = MessageTimer::new(Timeouts::new(
+            Duration::from_millis(5),
+            Duration::from_secs(1),
+        ));
+        // 每隔一次才有数据，最后停在半条消息上
+        let mut data = [Some(b'o'), None, Some(b'k'), None, Some(0), Some(b'x')].into_iter();
+        let mut try_read = || data.next().flatten();
+        let period = Duration::from_millis(1);
+        let ended = block_on(poll_frame(&mut framer, &mut timer, period, &mut try_read));
+        assert_eq!((ended, framer.frame()), (Ended::Frame, &b"ok"[..]));
+        let ended = block_on(poll_frame(&mut framer, &mut timer, period, &mut try_read));
+        assert_eq!((ended, framer.frame()), (Ended::InterByte, &b"x"[..]));
+    }
+}
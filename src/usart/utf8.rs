@@ -0,0 +1,263 @@
+//! 流式 UTF-8 解码。
+//!
+//! 原来收到一段就 `core::str::from_utf8` 一次，一个中文字符被拆在两次 `read` 里，
+//! 或者刚好被 64 字节的缓冲区截断时，整段都会被当成非 UTF-8 丢掉。[`Utf8Decoder`] 逐字节
+//! 解码，凑了一半的字符留到下一次继续凑，只有真正不合法的字节才按 [`Invalid`] 处理。
+//!
+//! 和 `String::from_utf8_lossy` 一样，一段不合法的字节按“最长的合法前缀”算一处错误：
+//! `E4 BD 41` 是一处错误加一个 `A`，`FF FE` 是两处错误。过长编码、代理区和超过
+//! U+10FFFF 的码点都算不合法。
+
+/// 遇到不合法的字节怎么办
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum Invalid {
+    /// 每处错误换成一个 U+FFFD（�）
+    #[default]
+    Replace,
+    /// 直接丢掉，只计数
+    Reject,
+    /// 每个字节写成 `\xNN`，方便看清对方到底发了什么
+    Hex,
+}
+
+/// 逐字节的 UTF-8 解码器，见模块说明
+#[derive(Clone, Copy, Debug)]
+pub struct Utf8Decoder {
+    policy: Invalid,
+    /// 当前字符已经收到的字节
+    pending: [u8; 3],
+    len: u8,
+    /// 还差几个后续字节
+    need: u8,
+    /// 下一个后续字节的取值范围，用来排除过长编码、代理区和过大的码点
+    lower: u8,
+    upper: u8,
+    code: u32,
+    /// 遇到过几处错误
+    errors: u32,
+}
+
+impl Utf8Decoder {
+    pub const fn new(policy: Invalid) -> Self {
+        Self {
+            policy,
+            pending: [0; 3],
+            len: 0,
+            need: 0,
+            lower: 0x80,
+            upper: 0xBF,
+            code: 0,
+            errors: 0,
+        }
+    }
+
+    pub fn policy(&self) -> Invalid {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: Invalid) {
+        self.policy = policy;
+    }
+
+    /// 遇到过几处不合法的字节
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    /// 是否有凑了一半的字符
+    pub fn is_pending(&self) -> bool {
+        self.need > 0
+    }
+
+    /// 放入一个字节，解出来的字符（以及按策略替换错误得到的字符）依次交给 `emit`
+    pub fn push(&mut self, byte: u8, mut emit: impl FnMut(char)) {
+        if self.need > 0 {
+            if (self.lower..=self.upper).contains(&byte) {
+                self.code = (self.code << 6) | u32::from(byte & 0x3F);
+                self.need -= 1;
+                if self.need == 0 {
+                    let code = self.code;
+                    self.clear();
+                    // 取值范围已经检查过了，不会失败
+                    if let Some(c) = char::from_u32(code) {
+                        emit(c);
+                    }
+                } else {
+                    self.pending[usize::from(self.len)] = byte;
+                    self.len += 1;
+                    self.lower = 0x80;
+                    self.upper = 0xBF;
+                }
+                return;
+            }
+            // 没凑齐就断了，凑了一半的算一处错误，这个字节重新开始
+            self.invalid_pending(&mut emit);
+        }
+
+        let (need, lower, upper, code) = match byte {
+            0x00..=0x7F => return emit(char::from(byte)),
+            0xC2..=0xDF => (1, 0x80, 0xBF, byte & 0x1F),
+            0xE0 => (2, 0xA0, 0xBF, byte & 0x0F),
+            0xED => (2, 0x80, 0x9F, byte & 0x0F),
+            0xE1..=0xEF => (2, 0x80, 0xBF, byte & 0x0F),
+            0xF0 => (3, 0x90, 0xBF, byte & 0x07),
+            0xF4 => (3, 0x80, 0x8F, byte & 0x07),
+            0xF1..=0xF3 => (3, 0x80, 0xBF, byte & 0x07),
+            _ => return self.invalid(&[byte], &mut emit),
+        };
+        self.pending[0] = byte;
+        self.len = 1;
+        self.need = need;
+        self.lower = lower;
+        self.upper = upper;
+        self.code = u32::from(code);
+    }
+
+    /// 依次放入一段字节
+    pub fn push_slice(&mut self, bytes: &[u8], mut emit: impl FnMut(char)) {
+        for &byte in bytes {
+            self.push(byte, &mut emit);
+        }
+    }
+
+    /// 输入结束了（比如连接断开），凑了一半的字符算一处错误
+    pub fn finish(&mut self, mut emit: impl FnMut(char)) {
+        if self.need > 0 {
+            self.invalid_pending(&mut emit);
+        }
+    }
+
+    /// 丢掉凑了一半的字符，不算错误
+    pub fn reset(&mut self) {
+        self.clear();
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.need = 0;
+        self.lower = 0x80;
+        self.upper = 0xBF;
+        self.code = 0;
+    }
+
+    fn invalid_pending(&mut self, emit: &mut impl FnMut(char)) {
+        let pending = self.pending;
+        let len = usize::from(self.len);
+        self.clear();
+        self.invalid(&pending[..len], emit);
+    }
+
+    fn invalid(&mut self, bytes: &[u8], emit: &mut impl FnMut(char)) {
+        self.errors = self.errors.wrapping_add(1);
+        match self.policy {
+            Invalid::Replace => emit(char::REPLACEMENT_CHARACTER),
+            Invalid::Reject => {}
+            Invalid::Hex => {
+                for &byte in bytes {
+                    emit('\\');
+                    emit('x');
+                    emit(hex_digit(byte >> 4));
+                    emit(hex_digit(byte & 0x0F));
+                }
+            }
+        }
+    }
+}
+
+impl Default for Utf8Decoder {
+    fn default() -> Self {
+        Self::new(Invalid::default())
+    }
+}
+
+fn hex_digit(n: u8) -> char {
+    char::from(b"0123456789ABCDEF"[usize::from(n)])
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    fn decode(policy: Invalid, chunks: &[&[u8]]) -> (String, u32) {
+        let mut decoder = Utf8Decoder::new(policy);
+        let mut text = String::new();
+        for chunk in chunks {
+            decoder.push_slice(chunk, |c| text.push(c));
+        }
+        decoder.finish(|c| text.push(c));
+        (text, decoder.errors())
+    }
+
+    #[test]
+    fn split_at_every_offset() {
+        let text = "你好，世界 abc 😀 é!";
+        let bytes = text.as_bytes();
+        for i in 0..=bytes.len() {
+            for j in i..=bytes.len() {
+                let chunks = [&bytes[..i], &bytes[i..j], &bytes[j..]];
+                assert_eq!(
+                    decode(Invalid::Reject, &chunks),
+                    (text.into(), 0),
+                    "{i} {j}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pending() {
+        let mut decoder = Utf8Decoder::default();
+        decoder.push_slice(&"你".as_bytes()[..2], |_| panic!());
+        assert!(decoder.is_pending());
+        decoder.reset();
+        assert!(!decoder.is_pending());
+        assert_eq!(decoder.errors(), 0);
+    }
+
+    #[test]
+    fn policies() {
+        assert_eq!(
+            decode(Invalid::Replace, &[b"\xE4\xBDA"]),
+            ("\u{FFFD}A".into(), 1)
+        );
+        assert_eq!(
+            decode(Invalid::Replace, &[b"\xFF\xFE"]),
+            ("\u{FFFD}\u{FFFD}".into(), 2)
+        );
+        assert_eq!(
+            decode(Invalid::Hex, &[b"\xE4", b"\xBDA\xFF"]),
+            ("\\xE4\\xBDA\\xFF".into(), 2)
+        );
+        assert_eq!(
+            decode(Invalid::Hex, &[b"x\xE4\xBD"]),
+            ("x\\xE4\\xBD".into(), 1)
+        );
+        assert_eq!(decode(Invalid::Reject, &[b"a\xC0\x80b"]), ("ab".into(), 2));
+        // GBK 的“你好”
+        assert_eq!(
+            decode(Invalid::Hex, &[b"\xC4\xE3\xBA\xC3"]),
+            ("\\xC4\\xE3\\xBA\\xC3".into(), 3)
+        );
+    }
+
+    #[test]
+    fn same_as_std_lossy() {
+        let cases: [&[u8]; 7] = [
+            b"\xE4\xBDA\xF0\x9F\x98",
+            b"\xC2\xC2\xA9",
+            b"\xE0\x80\x80",
+            b"\xED\xA0\x80",
+            b"\xF4\x90\x80\x80",
+            b"\xF0\x9F\x98\x80\x80z",
+            b"\xC4\xE3\xBA\xC3",
+        ];
+        for bad in cases {
+            let (text, _) = decode(Invalid::Replace, &[bad]);
+            assert_eq!(text, String::from_utf8_lossy(bad));
+        }
+    }
+}